@@ -74,7 +74,7 @@ fn bench_execute(c: &mut Criterion) {
                 .expect("Failed to connect to FastCGI server on 127.0.0.1:9000");
             let mut client = Client::new_keep_alive(stream);
             
-            black_box(test_client(&mut client).await);
+            test_client(black_box(&mut client)).await;
         });
     });
 }
@@ -0,0 +1,68 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bytes::BytesMut;
+use criterion::{criterion_group, criterion_main, Criterion};
+use fcgi_client::{
+    meta::{encode_params, ParamPair},
+    Params,
+};
+use std::{borrow::Cow, hint::black_box};
+
+/// Number of params encoded per iteration.
+const PARAMS: usize = 50;
+
+fn params() -> Params<'static> {
+    (0..PARAMS).fold(Params::default(), |params, i| {
+        params.custom(format!("HTTP_X_HEADER_{i}"), format!("value of header {i}"))
+    })
+}
+
+/// Collects the params into pairs before encoding them, the way requests
+/// used to be encoded.
+fn encode_collected(params: &Params<'_>) -> BytesMut {
+    let pairs = params
+        .iter()
+        .map(|(name, value)| ParamPair::new(Cow::Borrowed(name), Cow::Borrowed(value)))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let mut buf = BytesMut::new();
+    for pair in &pairs {
+        pair.encode(&mut buf);
+    }
+    buf
+}
+
+fn encode_streaming(params: &Params<'_>) -> BytesMut {
+    let mut buf = BytesMut::new();
+    encode_params(params.iter(), &mut buf).unwrap();
+    buf
+}
+
+fn bench_params(c: &mut Criterion) {
+    let params = params();
+    assert_eq!(encode_collected(&params), encode_streaming(&params));
+
+    let mut group = c.benchmark_group("fastcgi_params_50");
+    group.bench_function("collected", |b| {
+        b.iter(|| encode_collected(black_box(&params)))
+    });
+    group.bench_function("streaming", |b| {
+        b.iter(|| encode_streaming(black_box(&params)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_params);
+criterion_main!(benches);
@@ -0,0 +1,99 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use fcgi_client::{conn::KeepAlive, Client, Params, Request};
+use std::hint::black_box;
+use tokio::{
+    io::{self, AsyncReadExt, AsyncWriteExt, DuplexStream},
+    runtime::Runtime,
+};
+
+mod alloc;
+
+/// Number of requests measured for the allocation count.
+const REQUESTS: usize = 100;
+
+/// Body sent with each request.
+const BODY: &[u8] = &[b'x'; 1024];
+
+#[global_allocator]
+static GLOBAL: alloc::Counting = alloc::Counting;
+
+/// Replies an empty successful response to every request on the stream.
+async fn serve(mut stream: DuplexStream) {
+    let mut header = [0; 8];
+    let mut content = vec![0; 0xffff + 0xff];
+    while stream.read_exact(&mut header).await.is_ok() {
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize + header[6] as usize;
+        stream.read_exact(&mut content[..length]).await.unwrap();
+
+        // An empty stdin record ends the request.
+        if header[1] == 5 && length == 0 {
+            let mut end_request = vec![1, 3, header[2], header[3], 0, 8, 0, 0];
+            end_request.extend_from_slice(&[0; 8]);
+            stream.write_all(&end_request).await.unwrap();
+        }
+    }
+}
+
+async fn execute(client: &mut Client<DuplexStream, KeepAlive>) {
+    let params = Params::default()
+        .request_method("POST")
+        .script_name("/index.php")
+        .content_length(BODY.len());
+    client.execute(Request::new(params, BODY)).await.unwrap();
+}
+
+async fn execute_get(client: &mut Client<DuplexStream, KeepAlive>) {
+    let params = Params::default()
+        .request_method("GET")
+        .script_name("/index.php");
+    client
+        .execute(Request::new(params, io::empty()))
+        .await
+        .unwrap();
+}
+
+fn bench_request(c: &mut Criterion) {
+    let rt = Runtime::new().expect("Failed to create Tokio runtime");
+    let (client_side, server_side) = io::duplex(1 << 16);
+    rt.spawn(serve(server_side));
+    let mut client = Client::new_keep_alive(client_side);
+
+    let (allocations, allocated) = alloc::count(|| {
+        rt.block_on(async {
+            for _ in 0..REQUESTS {
+                execute(&mut client).await;
+            }
+        })
+    });
+    println!(
+        "allocations per request: {:.2}, bytes allocated per request: {}",
+        allocations as f64 / REQUESTS as f64,
+        allocated / REQUESTS,
+    );
+
+    c.bench_function("fastcgi_keep_alive_request", |b| {
+        b.iter(|| rt.block_on(execute(black_box(&mut client))));
+    });
+
+    // The begin request, params and empty stdin records go out in one write.
+    c.bench_function("fastcgi_keep_alive_get", |b| {
+        b.iter(|| rt.block_on(execute_get(black_box(&mut client))));
+    });
+}
+
+criterion_group!(benches, bench_request);
+criterion_main!(benches);
@@ -0,0 +1,104 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use fcgi_client::{Client, Params, Request};
+use std::hint::black_box;
+use tokio::{
+    io::{self, AsyncReadExt, AsyncWriteExt},
+    runtime::Runtime,
+};
+
+mod alloc;
+
+/// Number of stdout records in the benchmarked response.
+const RECORDS: usize = 256;
+
+/// Content length of each stdout record.
+const RECORD_LENGTH: usize = 1024;
+
+#[global_allocator]
+static GLOBAL: alloc::Counting = alloc::Counting;
+
+/// Encodes a record for request id 1.
+fn record(r#type: u8, content: &[u8]) -> Vec<u8> {
+    let mut record = vec![1, r#type, 0, 1];
+    record.extend_from_slice(&(content.len() as u16).to_be_bytes());
+    record.extend_from_slice(&[0, 0]);
+    record.extend_from_slice(content);
+    record
+}
+
+/// The response of the fake server: stdout records and an `EndRequest`.
+fn response() -> Vec<u8> {
+    let mut response = Vec::new();
+    for _ in 0..RECORDS {
+        response.extend(record(6, &[b'x'; RECORD_LENGTH]));
+    }
+    response.extend(record(3, &[0; 8]));
+    response
+}
+
+/// Executes one request against an in-memory server replying `response`,
+/// reserving `expected_size` bytes of stdout if set.
+async fn execute(response: &'static [u8], expected_size: Option<usize>) {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        // The request is small enough to fit in the duplex buffer, so read
+        // it only after replying.
+        server_side.write_all(response).await.unwrap();
+        let mut request = Vec::new();
+        server_side.read_to_end(&mut request).await.unwrap();
+    });
+
+    let mut request = Request::new(Params::default(), io::empty());
+    if let Some(expected_size) = expected_size {
+        request = request.with_expected_response_size(expected_size);
+    }
+    let output = Client::new(client_side)
+        .execute_once(request)
+        .await
+        .unwrap();
+    assert_eq!(output.stdout.unwrap().len(), RECORDS * RECORD_LENGTH);
+    server.await.unwrap();
+}
+
+fn bench_response(c: &mut Criterion) {
+    let rt = Runtime::new().expect("Failed to create Tokio runtime");
+    let response: &'static [u8] = response().leak();
+
+    let expected_size = Some(RECORDS * RECORD_LENGTH);
+
+    for (name, expected_size) in [("no size hint", None), ("size hint", expected_size)] {
+        let (allocations, allocated) =
+            alloc::count(|| rt.block_on(execute(response, expected_size)));
+        println!(
+            "{name}: allocations per record: {:.2}, bytes allocated per record: {}",
+            allocations as f64 / RECORDS as f64,
+            allocated / RECORDS,
+        );
+    }
+
+    c.bench_function("fastcgi_response_records", |b| {
+        b.to_async(&rt).iter(|| execute(black_box(response), None));
+    });
+    c.bench_function("fastcgi_response_records_size_hint", |b| {
+        b.to_async(&rt)
+            .iter(|| execute(black_box(response), expected_size));
+    });
+}
+
+criterion_group!(benches, bench_response);
+criterion_main!(benches);
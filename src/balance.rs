@@ -0,0 +1,594 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Load balancing over several backends.
+//!
+//! This module provides the `BalancedClient` struct, which spreads requests
+//! over a pool per backend, such as the FPM pods behind a gateway, and the
+//! `Strategy` trait choosing the backend of each request. Backends have a
+//! weight and can be added, removed or reweighted at runtime through
+//! `Backends`, for example during a rolling deployment, and unhealthy
+//! backends are skipped after `BalancedClient::check_health` found them
+//! failing.
+
+use crate::{
+    request::Request, trace::debug, ClientError, ClientResult, Params, Pool, Response,
+    SocketOptions,
+};
+use futures_util::future::join_all;
+use std::{
+    collections::hash_map::RandomState,
+    fmt::Display,
+    hash::BuildHasher,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{self, AsyncRead, AsyncWrite},
+    net::{TcpStream, ToSocketAddrs},
+    time,
+};
+
+/// The load of a backend, as seen by a [Strategy].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackendLoad {
+    /// The number of requests in flight on the backend
+    pub outstanding: usize,
+    /// The weight of the backend, never 0
+    pub weight: u32,
+}
+
+impl BackendLoad {
+    /// Returns whether this backend is less loaded than `other` relative to
+    /// their weights.
+    fn is_lighter_than(&self, other: &BackendLoad) -> bool {
+        (self.outstanding as u128) * (other.weight as u128)
+            < (other.outstanding as u128) * (self.weight as u128)
+    }
+}
+
+/// Chooses the backend of each request of a [BalancedClient].
+pub trait Strategy: Send + Sync + 'static {
+    /// Returns the index of the backend to send the next request to.
+    ///
+    /// # Arguments
+    ///
+    /// * `backends` - The load of each backend that can take requests, never
+    ///   empty
+    fn pick(&self, backends: &[BackendLoad]) -> usize;
+}
+
+/// Sends requests to each backend in turn, as many as its weight, the
+/// default strategy.
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    next: AtomicU64,
+}
+
+impl Strategy for RoundRobin {
+    fn pick(&self, backends: &[BackendLoad]) -> usize {
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        weighted_index(backends, next % total_weight(backends), None)
+    }
+}
+
+/// Sends requests to the backend with the fewest requests in flight relative
+/// to its weight, the first one on ties.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LeastOutstanding;
+
+impl Strategy for LeastOutstanding {
+    fn pick(&self, backends: &[BackendLoad]) -> usize {
+        let mut lightest = 0;
+        for (i, backend) in backends.iter().enumerate().skip(1) {
+            if backend.is_lighter_than(&backends[lightest]) {
+                lightest = i;
+            }
+        }
+        lightest
+    }
+}
+
+/// Picks two different backends at random, in proportion to their weights,
+/// and sends requests to the one less loaded relative to its weight, which
+/// spreads load nearly as well as [LeastOutstanding] without sending bursts
+/// to the same backend.
+#[derive(Debug)]
+pub struct RandomTwoChoices {
+    state: AtomicU64,
+}
+
+impl RandomTwoChoices {
+    /// Returns the next pseudo random number, from a xorshift generator.
+    fn next_random(&self) -> u64 {
+        let mut x = self.state.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state.store(x, Ordering::Relaxed);
+        x
+    }
+}
+
+impl Default for RandomTwoChoices {
+    fn default() -> Self {
+        Self {
+            state: AtomicU64::new(RandomState::new().hash_one(0u64) | 1),
+        }
+    }
+}
+
+impl Strategy for RandomTwoChoices {
+    fn pick(&self, backends: &[BackendLoad]) -> usize {
+        let total = total_weight(backends);
+        let first = weighted_index(backends, self.next_random() % total, None);
+        if backends.len() == 1 {
+            return first;
+        }
+        let rest = total - u64::from(backends[first].weight);
+        let second = weighted_index(backends, self.next_random() % rest, Some(first));
+        if backends[second].is_lighter_than(&backends[first]) {
+            second
+        } else {
+            first
+        }
+    }
+}
+
+/// Returns the sum of the weights of the backends.
+fn total_weight(backends: &[BackendLoad]) -> u64 {
+    backends
+        .iter()
+        .map(|backend| u64::from(backend.weight))
+        .sum()
+}
+
+/// Returns the backend covering `point` when the backends, but `skip`, are
+/// laid end to end with the length of their weight.
+fn weighted_index(backends: &[BackendLoad], mut point: u64, skip: Option<usize>) -> usize {
+    for (i, backend) in backends.iter().enumerate() {
+        if Some(i) == skip {
+            continue;
+        }
+        if point < u64::from(backend.weight) {
+            return i;
+        }
+        point -= u64::from(backend.weight);
+    }
+    unreachable!("point is below the total weight")
+}
+
+/// Builds the params of the request probing a backend.
+type ProbeParams = Arc<dyn Fn() -> Params<'static> + Send + Sync>;
+
+/// How [BalancedClient::check_health] probes the backends.
+///
+/// By default a backend is [pinged](crate::Client::ping) with a `GetValues`
+/// management record, marked unhealthy after one failed probe and healthy
+/// again after one successful probe, and a probe times out after 5 seconds.
+#[derive(Clone)]
+pub struct HealthCheck {
+    request: Option<ProbeParams>,
+    timeout: Duration,
+    unhealthy_after: u32,
+    healthy_after: u32,
+}
+
+impl HealthCheck {
+    /// Creates the default health check.
+    pub fn new() -> Self {
+        Self {
+            request: None,
+            timeout: Duration::from_secs(5),
+            unhealthy_after: 1,
+            healthy_after: 1,
+        }
+    }
+
+    /// Probe with a request instead of a ping, such as the `ping.path` of
+    /// PHP-FPM, which also checks that workers answer. The probe succeeds if
+    /// the request completes without error.
+    ///
+    /// # Arguments
+    ///
+    /// * `params` - Builds the params of each probe request
+    pub fn request<F>(mut self, params: F) -> Self
+    where
+        F: Fn() -> Params<'static> + Send + Sync + 'static,
+    {
+        self.request = Some(Arc::new(params));
+        self
+    }
+
+    /// Set how long a probe may take before it counts as failed.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set how many probes in a row must fail to mark a backend unhealthy.
+    pub fn unhealthy_after(mut self, failures: u32) -> Self {
+        self.unhealthy_after = failures.max(1);
+        self
+    }
+
+    /// Set how many probes in a row must succeed to mark an unhealthy
+    /// backend healthy again.
+    pub fn healthy_after(mut self, successes: u32) -> Self {
+        self.healthy_after = successes.max(1);
+        self
+    }
+
+    /// Probes a backend once, returning whether it answered in time.
+    async fn probe<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
+        &self, pool: &Pool<S>,
+    ) -> bool {
+        let Ok(Ok(mut client)) = time::timeout(self.timeout, pool.get()).await else {
+            return false;
+        };
+        let probe = async {
+            match &self.request {
+                Some(params) => client
+                    .execute(Request::new(params(), io::empty()))
+                    .await
+                    .map(drop),
+                None => client.ping().await,
+            }
+        };
+        let passed = matches!(time::timeout(self.timeout, probe).await, Ok(Ok(())));
+        if !passed {
+            client.discard();
+        }
+        passed
+    }
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Client spreading requests over several backends, cheap to clone and
+/// share between tasks.
+///
+/// Each backend has its own [Pool] of keep alive connections, and the
+/// [Strategy] picks the backend of each request, [RoundRobin] unless set with
+/// [BalancedClient::strategy]. The set of backends can change at runtime
+/// through [BalancedClient::backends], and backends failing
+/// [BalancedClient::check_health] get no requests until they recover.
+///
+/// # Examples
+///
+/// ```
+/// use fcgi_client::{
+///     balance::{BalancedClient, LeastOutstanding},
+///     Params, Request, SocketOptions,
+/// };
+/// use std::time::Duration;
+/// use tokio::io;
+///
+/// async fn balanced() {
+///     let client = BalancedClient::connect_tcp(
+///         ["10.0.0.1:9000", "10.0.0.2:9000"],
+///         Duration::from_secs(1),
+///         SocketOptions::default(),
+///     )
+///     .strategy(LeastOutstanding);
+///
+///     let output = client
+///         .execute(Request::new(Params::default(), &mut io::empty()))
+///         .await
+///         .unwrap();
+/// }
+/// ```
+pub struct BalancedClient<S> {
+    backends: Arc<Backends<S>>,
+    strategy: Arc<dyn Strategy>,
+    health_check: HealthCheck,
+}
+
+/// The backends of a [BalancedClient], named so they can be changed at
+/// runtime.
+///
+/// Changes apply to the next requests: requests in flight on a removed or
+/// reweighted backend complete normally, and the connections of a removed
+/// backend are closed once they are done.
+pub struct Backends<S> {
+    list: RwLock<Vec<Arc<Backend<S>>>>,
+}
+
+/// A backend of a balanced client.
+struct Backend<S> {
+    name: String,
+    pool: Pool<S>,
+    outstanding: AtomicUsize,
+    weight: AtomicU32,
+    healthy: AtomicBool,
+    /// The number of probes in a row whose result disagrees with `healthy`.
+    streak: AtomicU32,
+}
+
+impl<S> Backend<S> {
+    /// Records the result of a probe, flipping the health of the backend
+    /// once enough probes in a row disagree with it.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn record_probe(&self, passed: bool, health_check: &HealthCheck) {
+        let healthy = self.healthy.load(Ordering::Relaxed);
+        if passed == healthy {
+            self.streak.store(0, Ordering::Relaxed);
+            return;
+        }
+        let threshold = if healthy {
+            health_check.unhealthy_after
+        } else {
+            health_check.healthy_after
+        };
+        if self.streak.fetch_add(1, Ordering::Relaxed) + 1 >= threshold {
+            debug!(
+                backend = self.name,
+                healthy = passed,
+                "Backend health changed."
+            );
+            self.healthy.store(passed, Ordering::Relaxed);
+            self.streak.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Counts a request in flight on a backend until dropped, so requests
+/// cancelled by dropping their future are counted out too.
+struct Outstanding<'a>(&'a AtomicUsize);
+
+impl Drop for Outstanding<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<S> Backends<S> {
+    /// Adds a backend, replacing the one with the same name. It is healthy
+    /// until [BalancedClient::check_health] finds otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the backend, such as its address
+    /// * `pool` - The pool of connections to the backend
+    /// * `weight` - The share of requests relative to the other backends, 0 to
+    ///   send it none
+    pub fn add(&self, name: impl Into<String>, pool: Pool<S>, weight: u32) {
+        let backend = Arc::new(Backend {
+            name: name.into(),
+            pool,
+            outstanding: AtomicUsize::new(0),
+            weight: AtomicU32::new(weight),
+            healthy: AtomicBool::new(true),
+            streak: AtomicU32::new(0),
+        });
+        let mut list = self.list.write().unwrap();
+        match list.iter_mut().find(|old| old.name == backend.name) {
+            Some(old) => *old = backend,
+            None => list.push(backend),
+        }
+    }
+
+    /// Removes a backend, returning whether it existed.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the backend
+    pub fn remove(&self, name: &str) -> bool {
+        let mut list = self.list.write().unwrap();
+        let len = list.len();
+        list.retain(|backend| backend.name != name);
+        list.len() != len
+    }
+
+    /// Changes the weight of a backend, returning whether it exists. A
+    /// weight of 0 drains the backend: it gets no new requests.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the backend
+    /// * `weight` - The share of requests relative to the other backends
+    pub fn set_weight(&self, name: &str, weight: u32) -> bool {
+        self.find(name, |backend| {
+            backend.weight.store(weight, Ordering::Relaxed)
+        })
+        .is_some()
+    }
+
+    /// Returns the weight of a backend.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the backend
+    pub fn weight(&self, name: &str) -> Option<u32> {
+        self.find(name, |backend| backend.weight.load(Ordering::Relaxed))
+    }
+
+    /// Returns whether a backend passed its last health checks.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the backend
+    pub fn is_healthy(&self, name: &str) -> Option<bool> {
+        self.find(name, |backend| backend.healthy.load(Ordering::Relaxed))
+    }
+
+    /// Returns the number of requests in flight on a backend.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the backend
+    pub fn outstanding(&self, name: &str) -> Option<usize> {
+        self.find(name, |backend| backend.outstanding.load(Ordering::Relaxed))
+    }
+
+    /// Returns the names of the backends, in the order they were added.
+    pub fn names(&self) -> Vec<String> {
+        let list = self.list.read().unwrap();
+        list.iter().map(|backend| backend.name.clone()).collect()
+    }
+
+    /// Applies `f` to the backend named `name`.
+    fn find<T>(&self, name: &str, f: impl FnOnce(&Backend<S>) -> T) -> Option<T> {
+        let list = self.list.read().unwrap();
+        list.iter()
+            .find(|backend| backend.name == name)
+            .map(|backend| f(backend))
+    }
+}
+
+impl BalancedClient<TcpStream> {
+    /// Creates a client balancing over TCP backends of weight 1, named after
+    /// their address, with a pool per address opening connections with the
+    /// socket options, see [Pool::connect_tcp].
+    ///
+    /// # Arguments
+    ///
+    /// * `addrs` - The addresses of the backends, such as `"php-fpm:9000"`
+    /// * `timeout` - How long to wait for each address to connect
+    /// * `socket_options` - The options of the sockets
+    pub fn connect_tcp<A>(
+        addrs: impl IntoIterator<Item = A>, timeout: Duration, socket_options: SocketOptions,
+    ) -> Self
+    where
+        A: ToSocketAddrs + Display + Clone + Send + Sync + 'static,
+    {
+        Self::new(addrs.into_iter().map(|addr| {
+            let name = addr.to_string();
+            (
+                name,
+                Pool::connect_tcp(addr, timeout, socket_options.clone()),
+            )
+        }))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> BalancedClient<S> {
+    /// Creates a client balancing over the named pools, each of weight 1.
+    ///
+    /// # Arguments
+    ///
+    /// * `pools` - A name and a pool per backend, more can be added later with
+    ///   [Backends::add]
+    pub fn new<N: Into<String>>(pools: impl IntoIterator<Item = (N, Pool<S>)>) -> Self {
+        let backends = Backends {
+            list: RwLock::new(Vec::new()),
+        };
+        for (name, pool) in pools {
+            backends.add(name, pool, 1);
+        }
+        Self {
+            backends: Arc::new(backends),
+            strategy: Arc::new(RoundRobin::default()),
+            health_check: HealthCheck::new(),
+        }
+    }
+
+    /// Set the strategy choosing the backend of each request.
+    ///
+    /// # Arguments
+    ///
+    /// * `strategy` - The strategy, such as [LeastOutstanding]
+    pub fn strategy(mut self, strategy: impl Strategy) -> Self {
+        self.strategy = Arc::new(strategy);
+        self
+    }
+
+    /// Set how [BalancedClient::check_health] probes the backends.
+    ///
+    /// # Arguments
+    ///
+    /// * `health_check` - The health check
+    pub fn health_check(mut self, health_check: HealthCheck) -> Self {
+        self.health_check = health_check;
+        self
+    }
+
+    /// Returns the backends, shared by the clones of this client.
+    pub fn backends(&self) -> &Backends<S> {
+        &self.backends
+    }
+
+    /// Sends a request to the backend picked by the strategy, see
+    /// [Pool::execute].
+    ///
+    /// Fails with [ClientError::NoBackend] if every backend has a weight of
+    /// 0 or is unhealthy, or there is none.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The request to send
+    pub async fn execute<I: AsyncRead + Unpin, D: AsyncRead + Unpin>(
+        &self, request: Request<'_, I, D>,
+    ) -> ClientResult<Response> {
+        let backend = self.pick()?;
+        let _outstanding = Outstanding(&backend.outstanding);
+        backend.pool.execute(request).await
+    }
+
+    /// Probes every backend once, concurrently, marking those failing as
+    /// many probes in a row as set by the [HealthCheck] unhealthy, and those
+    /// passing as many healthy again.
+    ///
+    /// Call it periodically, for example from a task driven by
+    /// `tokio::time::interval`. Each probe takes a connection from the pool
+    /// of the backend like a request.
+    pub async fn check_health(&self) {
+        let backends = self.backends.list.read().unwrap().clone();
+        join_all(backends.iter().map(|backend| async {
+            let passed = self.health_check.probe(&backend.pool).await;
+            backend.record_probe(passed, &self.health_check);
+        }))
+        .await;
+    }
+
+    /// Picks the backend of the next request with the strategy and counts
+    /// the request in flight on it.
+    fn pick(&self) -> ClientResult<Arc<Backend<S>>> {
+        let list = self.backends.list.read().unwrap();
+        let (candidates, loads): (Vec<_>, Vec<_>) = list
+            .iter()
+            .filter_map(|backend| {
+                let load = BackendLoad {
+                    outstanding: backend.outstanding.load(Ordering::Relaxed),
+                    weight: backend.weight.load(Ordering::Relaxed),
+                };
+                let routable = load.weight > 0 && backend.healthy.load(Ordering::Relaxed);
+                routable.then_some((backend, load))
+            })
+            .unzip();
+        if candidates.is_empty() {
+            return Err(ClientError::NoBackend);
+        }
+        let backend = candidates[self.strategy.pick(&loads)].clone();
+        backend.outstanding.fetch_add(1, Ordering::Relaxed);
+        Ok(backend)
+    }
+}
+
+impl<S> Clone for BalancedClient<S> {
+    fn clone(&self) -> Self {
+        Self {
+            backends: self.backends.clone(),
+            strategy: self.strategy.clone(),
+            health_check: self.health_check.clone(),
+        }
+    }
+}
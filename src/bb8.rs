@@ -0,0 +1,118 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [bb8](https://docs.rs/bb8) support, enabled by the `bb8` feature.
+//!
+//! `Manager` opens keep alive clients for a `bb8::Pool`, for services already
+//! pooling their other backends with bb8. bb8 validates a client with a ping
+//! when it is checked out, unless disabled with
+//! `bb8::Builder::test_on_check_out`, and drops clients that are
+//! [broken](crate::Client::is_broken) when they are returned.
+
+use crate::{conn::KeepAlive, pool::Connect, trace::debug, Client, ClientError, SocketOptions};
+use std::{future::Future, io, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpStream, ToSocketAddrs},
+};
+
+/// bb8 pool of keep alive clients.
+///
+/// # Examples
+///
+/// ```
+/// use fcgi_client::{
+///     bb8::{Manager, Pool},
+///     Params, Request,
+/// };
+/// use tokio::{io, net::TcpStream};
+///
+/// async fn pooled() {
+///     let manager = Manager::new(|| TcpStream::connect(("127.0.0.1", 9000)));
+///     let pool = Pool::builder().max_size(32).build(manager).await.unwrap();
+///
+///     let mut client = pool.get().await.unwrap();
+///     let output = client
+///         .execute(Request::new(Params::default(), &mut io::empty()))
+///         .await
+///         .unwrap();
+/// }
+/// ```
+pub type Pool<S> = bb8::Pool<Manager<S>>;
+
+/// Manager of the clients of a bb8 [Pool].
+pub struct Manager<S> {
+    connect: Connect<S>,
+}
+
+impl Manager<TcpStream> {
+    /// Creates a manager opening TCP connections with the socket options, see
+    /// [SocketOptions::connect].
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address of the server, such as `("php-fpm", 9000)`
+    /// * `timeout` - How long to wait for each address to connect
+    /// * `socket_options` - The options of the sockets
+    pub fn connect_tcp<A>(addr: A, timeout: Duration, socket_options: SocketOptions) -> Self
+    where
+        A: ToSocketAddrs + Clone + Send + Sync + 'static,
+    {
+        Self::new(move || {
+            let (addr, socket_options) = (addr.clone(), socket_options.clone());
+            async move {
+                socket_options
+                    .connect(addr, timeout)
+                    .await
+                    .map_err(io::Error::other)
+            }
+        })
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> Manager<S> {
+    /// Creates a manager opening connections with `connect`, such as
+    /// `|| TcpStream::connect(("127.0.0.1", 9000))`.
+    ///
+    /// # Arguments
+    ///
+    /// * `connect` - The function opening a new connection
+    pub fn new<F, Fut>(connect: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = io::Result<S>> + Send + 'static,
+    {
+        Self {
+            connect: Box::new(move || Box::pin(connect())),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> bb8::ManageConnection for Manager<S> {
+    type Connection = Client<S, KeepAlive>;
+    type Error = ClientError;
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        debug!("Open a new pooled connection.");
+        Ok(Client::new_keep_alive((self.connect)().await?))
+    }
+
+    async fn is_valid(&self, client: &mut Self::Connection) -> Result<(), Self::Error> {
+        client.ping().await
+    }
+
+    fn has_broken(&self, client: &mut Self::Connection) -> bool {
+        client.is_broken() || !client.is_reusable()
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Synchronous FastCGI client for codebases without an async runtime.
+//!
+//! This module is enabled by the `blocking` feature. The [BlockingClient]
+//! drives the async [Client] on a small internal current-thread runtime, so
+//! callers never have to set one up themselves.
+
+use crate::{
+    conn::{KeepAlive, ShortConn},
+    Client, ClientResult, Params, Request, Response,
+};
+use std::{
+    io::{self, Read},
+    pin::Pin,
+    task::{Context, Poll},
+};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::{
+    io::{AsyncRead, ReadBuf},
+    net::TcpStream,
+    runtime::{Builder, Runtime},
+};
+
+/// Blocking client for handling communication between fastcgi server.
+///
+/// The API mirrors [Client]: `new`/`execute_once` for short connection
+/// mode and `new_keep_alive`/`execute` for keep alive mode.
+///
+/// # Examples
+///
+/// ```no_run
+/// use fcgi_client::{blocking::BlockingClient, Params};
+/// use std::{io, net::TcpStream};
+///
+/// let stream = TcpStream::connect(("127.0.0.1", 9000)).unwrap();
+/// let mut client = BlockingClient::new_keep_alive(stream).unwrap();
+/// let output = client.execute(Params::default(), &mut io::empty()).unwrap();
+/// ```
+pub struct BlockingClient<S, M> {
+    runtime: Runtime,
+    client: Client<S, M>,
+}
+
+impl BlockingClient<TcpStream, ShortConn> {
+    /// Construct a `BlockingClient` from a `std::net::TcpStream`, under short
+    /// connection mode.
+    pub fn new(stream: std::net::TcpStream) -> ClientResult<Self> {
+        let runtime = new_runtime()?;
+        let stream = from_std_tcp(&runtime, stream)?;
+        Ok(Self {
+            runtime,
+            client: Client::new(stream),
+        })
+    }
+}
+
+impl BlockingClient<TcpStream, KeepAlive> {
+    /// Construct a `BlockingClient` from a `std::net::TcpStream`, under keep
+    /// alive connection mode.
+    pub fn new_keep_alive(stream: std::net::TcpStream) -> ClientResult<Self> {
+        let runtime = new_runtime()?;
+        let stream = from_std_tcp(&runtime, stream)?;
+        Ok(Self {
+            runtime,
+            client: Client::new_keep_alive(stream),
+        })
+    }
+}
+
+#[cfg(unix)]
+impl BlockingClient<UnixStream, ShortConn> {
+    /// Construct a `BlockingClient` from a `std::os::unix::net::UnixStream`,
+    /// under short connection mode.
+    pub fn new_unix(stream: std::os::unix::net::UnixStream) -> ClientResult<Self> {
+        let runtime = new_runtime()?;
+        let stream = from_std_unix(&runtime, stream)?;
+        Ok(Self {
+            runtime,
+            client: Client::new(stream),
+        })
+    }
+}
+
+#[cfg(unix)]
+impl BlockingClient<UnixStream, KeepAlive> {
+    /// Construct a `BlockingClient` from a `std::os::unix::net::UnixStream`,
+    /// under keep alive connection mode.
+    pub fn new_unix_keep_alive(stream: std::os::unix::net::UnixStream) -> ClientResult<Self> {
+        let runtime = new_runtime()?;
+        let stream = from_std_unix(&runtime, stream)?;
+        Ok(Self {
+            runtime,
+            client: Client::new_keep_alive(stream),
+        })
+    }
+}
+
+impl<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin> BlockingClient<S, ShortConn> {
+    /// Send request and receive response from fastcgi server, under short
+    /// connection mode.
+    ///
+    /// The body is read one record at a time as the request is sent.
+    pub fn execute_once<R: Read>(self, params: Params<'_>, body: &mut R) -> ClientResult<Response> {
+        let Self { runtime, client } = self;
+        runtime.block_on(client.execute_once(Request::new(params, BlockingBody(body))))
+    }
+}
+
+impl<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin> BlockingClient<S, KeepAlive> {
+    /// Send request and receive response from fastcgi server, under keep alive
+    /// connection mode.
+    ///
+    /// The body is read one record at a time as the request is sent.
+    pub fn execute<R: Read>(&mut self, params: Params<'_>, body: &mut R) -> ClientResult<Response> {
+        self.runtime.block_on(
+            self.client
+                .execute(Request::new(params, BlockingBody(body))),
+        )
+    }
+}
+
+/// Builds the current-thread runtime that drives the async client.
+fn new_runtime() -> ClientResult<Runtime> {
+    Ok(Builder::new_current_thread().enable_all().build()?)
+}
+
+/// Registers a std TCP stream with the runtime.
+fn from_std_tcp(runtime: &Runtime, stream: std::net::TcpStream) -> ClientResult<TcpStream> {
+    let _guard = runtime.enter();
+    stream.set_nonblocking(true)?;
+    Ok(TcpStream::from_std(stream)?)
+}
+
+/// Registers a std Unix stream with the runtime.
+#[cfg(unix)]
+fn from_std_unix(
+    runtime: &Runtime, stream: std::os::unix::net::UnixStream,
+) -> ClientResult<UnixStream> {
+    let _guard = runtime.enter();
+    stream.set_nonblocking(true)?;
+    Ok(UnixStream::from_std(stream)?)
+}
+
+/// Request body read from a blocking reader, at most one record per read.
+///
+/// The reads block the thread of the internal runtime, which only drives
+/// this client, so the body is streamed without being buffered first.
+struct BlockingBody<'a, R>(&'a mut R);
+
+impl<R: Read> AsyncRead for BlockingBody<'_, R> {
+    fn poll_read(
+        self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let body = &mut self.get_mut().0;
+        loop {
+            match body.read(buf.initialize_unfilled()) {
+                Ok(read) => {
+                    buf.advance(read);
+                    return Poll::Ready(Ok(()));
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Capabilities advertised by the FastCGI application.
+//!
+//! This module provides the `Capabilities` struct returned by
+//! `Client::capabilities`, and the `ServerValues` struct returned by
+//! `Client::server_values`.
+
+use crate::meta::{FCGI_MAX_CONNS, FCGI_MAX_REQS, FCGI_MPXS_CONNS};
+use std::collections::HashMap;
+
+/// Limits the application advertises through the `GetValues` management
+/// record.
+///
+/// Values the application doesn't report keep their defaults: no limit on
+/// connections, one request at a time and no multiplexing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The maximum number of concurrent connections, from `FCGI_MAX_CONNS`
+    pub max_conns: Option<usize>,
+    /// The maximum number of concurrent requests, from `FCGI_MAX_REQS`
+    pub max_reqs: usize,
+    /// Whether requests can be multiplexed over one connection, from
+    /// `FCGI_MPXS_CONNS`
+    pub mpxs_conns: bool,
+}
+
+impl Capabilities {
+    /// The names of the variables queried for the capabilities.
+    pub(crate) const NAMES: [&'static str; 3] = [FCGI_MAX_CONNS, FCGI_MAX_REQS, FCGI_MPXS_CONNS];
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            max_conns: None,
+            max_reqs: 1,
+            mpxs_conns: false,
+        }
+    }
+}
+
+/// Variables the application reports through the `GetValues` management
+/// record, see [crate::Client::server_values].
+///
+/// The well-known variables are `None` if the application didn't report
+/// them. Every other variable, and a well-known one whose value isn't a
+/// number, is kept by name in `other`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerValues {
+    /// The maximum number of concurrent connections, from `FCGI_MAX_CONNS`
+    pub max_conns: Option<usize>,
+    /// The maximum number of concurrent requests, from `FCGI_MAX_REQS`
+    pub max_reqs: Option<usize>,
+    /// Whether requests can be multiplexed over one connection, from
+    /// `FCGI_MPXS_CONNS`
+    pub mpxs_conns: Option<bool>,
+    /// The other variables by name
+    pub other: HashMap<String, String>,
+}
+
+impl ServerValues {
+    /// Reads the values of a `GetValuesResult`, taking the well-known
+    /// variables out of them.
+    ///
+    /// # Arguments
+    ///
+    /// * `values` - The variables by name
+    pub(crate) fn from_values(mut values: HashMap<String, String>) -> Self {
+        let mut number = |name| {
+            let number = values.get(name)?.trim().parse::<usize>().ok()?;
+            values.remove(name);
+            Some(number)
+        };
+        let max_conns = number(FCGI_MAX_CONNS);
+        let max_reqs = number(FCGI_MAX_REQS);
+        let mpxs_conns = number(FCGI_MPXS_CONNS).map(|mpxs| mpxs != 0);
+        Self {
+            max_conns,
+            max_reqs,
+            mpxs_conns,
+            other: values,
+        }
+    }
+
+    /// Returns the capabilities the values describe, keeping the defaults of
+    /// [Capabilities] for the variables that weren't reported.
+    pub fn capabilities(&self) -> Capabilities {
+        let default = Capabilities::default();
+        Capabilities {
+            max_conns: self.max_conns.or(default.max_conns),
+            max_reqs: self.max_reqs.unwrap_or(default.max_reqs),
+            mpxs_conns: self.mpxs_conns.unwrap_or(default.mpxs_conns),
+        }
+    }
+}
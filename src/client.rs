@@ -19,58 +19,474 @@
 //! The client can execute requests and receive responses or response streams.
 
 use crate::{
+    capabilities::{Capabilities, ServerValues},
     ClientError, ClientResult, Response,
     conn::{KeepAlive, Mode, ShortConn},
-    meta::{BeginRequestRec, EndRequestRec, Header, ParamPairs, RequestType, Role},
+    meta::{
+        check_params, check_request_id, read_ready, BeginRequestRec, Direction, EndRequestRec,
+        GetValuesRec, GetValuesResultRec, Header, Padding, RequestType, Role, UnknownTypeRec,
+        HEADER_LEN, MANAGEMENT_REQUEST_ID, MAX_LENGTH,
+    },
     params::Params,
-    request::Request,
-    response::ResponseStream,
+    request::{Request, StderrHandler},
+    response::{AuthResponse, ResponseStream, ResponseSummary, StreamShared},
+    retry::RetryPolicy,
+    socket::SocketOptions,
+    trace::{self, debug, warning, Instrument, Span},
 };
-use bytes::BytesMut;
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::task::noop_waker_ref;
 use std::{
+    cmp::min,
+    collections::HashMap,
+    future::Future,
     marker::PhantomData,
+    mem,
     ops::{Deref, DerefMut},
+    pin::Pin,
+    str,
+    sync::{atomic::Ordering, Arc},
+    task::{Context, Poll},
+    time::Duration,
 };
-use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
-use tracing::debug;
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::{ffi::OsString, os::unix::ffi::OsStringExt, path::PathBuf};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::{
+    net::{TcpStream, ToSocketAddrs},
+    time::Instant,
+};
+#[cfg(feature = "futures-io")]
+use tokio_util::compat::{Compat, FuturesAsyncReadCompatExt};
+use tokio_util::{io::poll_read_buf, sync::CancellationToken};
+#[cfg(feature = "vsock")]
+use tokio_vsock::{VsockAddr, VsockStream};
 
-/// I refer to nginx fastcgi implementation, found the request id is always 1.
-///
-/// <https://github.com/nginx/nginx/blob/f7ea8c76b55f730daa3b63f5511feb564b44d901/src/http/modules/ngx_http_fastcgi_module.c>
-const REQUEST_ID: u16 = 1;
+/// Maximum number of pending records discarded by [Client::shutdown].
+const MAX_DRAINED_RECORDS: usize = 16;
+
+/// Capacity reserved for each read of the response while the request is
+/// being sent.
+const READ_AHEAD_CAPACITY: usize = 8 * 1024;
 
 /// Async client for handling communication between fastcgi server.
 pub struct Client<S, M> {
     stream: S,
+    /// The last request id used, 0 before the first request.
+    request_id: u16,
+    /// Whether the server was asked to keep the connection open after the
+    /// last request.
+    reusable: bool,
+    /// Whether a request failed in a way that left the connection out of
+    /// sync, such as an IO error or a malformed record.
+    broken: bool,
+    /// The number of records of other requests skipped while reading
+    /// responses.
+    skipped_records: u64,
+    /// State shared with the response streams.
+    stream_shared: Arc<StreamShared>,
+    config: Config,
+    /// Scratch buffer for the records of params, stdin and data, reused
+    /// across requests.
+    write_buf: Vec<u8>,
+    /// Buffer the records sent before the body are encoded into, to send
+    /// them in one write, reused across requests.
+    preamble_buf: Vec<u8>,
+    /// Scratch buffer for the content of response records, reused across
+    /// requests.
+    read_buf: BytesMut,
+    /// Bytes of the response read while the request was being sent, see
+    /// [ReadAhead].
+    ahead: BytesMut,
+    /// The capabilities of the application, queried on first use.
+    capabilities: Option<Capabilities>,
+    /// When a record was last written or a reply last read.
+    last_used: Instant,
     _mode: PhantomData<M>,
 }
 
-impl<S: AsyncRead + AsyncWrite + Unpin> Client<S, ShortConn> {
-    /// Construct a `Client` Object with stream, such as `tokio::net::TcpStream`
-    /// or `tokio::net::UnixStream`, under short connection mode.
+/// Callback receiving the header of every record, see
+/// [ClientBuilder::on_record].
+pub(crate) type RecordTap = Arc<dyn Fn(Direction, &Header) + Send + Sync>;
+
+/// Settings applied to every request sent by a client.
+#[derive(Clone)]
+pub(crate) struct Config {
+    pub(crate) max_response_bytes: Option<usize>,
+    pub(crate) max_stderr_bytes: Option<usize>,
+    pub(crate) max_records: Option<usize>,
+    pub(crate) read_timeout: Option<Duration>,
+    pub(crate) default_role: Role,
+    pub(crate) strict_padding: bool,
+    pub(crate) strict: bool,
+    pub(crate) skip_unknown_ids: bool,
+    pub(crate) padding: Padding,
+    pub(crate) flush_every_record: bool,
+    pub(crate) record_size: usize,
+    pub(crate) on_record: Option<RecordTap>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_response_bytes: None,
+            max_stderr_bytes: None,
+            max_records: None,
+            read_timeout: None,
+            default_role: Role::Responder,
+            strict_padding: false,
+            strict: false,
+            skip_unknown_ids: false,
+            padding: Padding::Align8,
+            flush_every_record: false,
+            record_size: MAX_LENGTH,
+            on_record: None,
+        }
+    }
+}
+
+impl Config {
+    /// Sets the padding length of a record to be written.
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - The header of the record
+    #[inline]
+    pub(crate) fn pad(&self, header: &mut Header) {
+        header.padding_length = self.padding.padding_length(header.content_length);
+    }
+
+    /// Passes the header of a record to the record tap, if one is set.
+    ///
+    /// # Arguments
+    ///
+    /// * `direction` - Whether the record is read or written
+    /// * `header` - The header of the record
+    #[inline]
+    pub(crate) fn tap(&self, direction: Direction, header: &Header) {
+        if let Some(on_record) = &self.on_record {
+            on_record(direction, header);
+        }
+    }
+}
+
+/// The request a response is read for.
+struct InFlight<'a> {
+    /// The request ID to match
+    id: u16,
+    /// The ID of the previous request on the connection, 0 if none, whose
+    /// records arriving late are discarded
+    previous_id: u16,
+    /// The role of the request, reported if it is rejected
+    role: Role,
+    /// The stdout capacity reserved up front, capped by the response size
+    /// limit
+    expected_size: Option<usize>,
+    /// Cancels the request while waiting for the next record
+    cancel: Option<&'a CancellationToken>,
+    /// Receives stderr instead of the response, see
+    /// [Request::stderr_handler]
+    stderr_handler: Option<StderrHandler>,
+    /// The stdout limit of the request, see [Request::with_max_stdout_bytes]
+    max_stdout_bytes: Option<usize>,
+    /// The stderr limit of the request, see [Request::with_max_stderr_bytes]
+    max_stderr_bytes: Option<usize>,
+    /// Receives stdout instead of the response, see [Client::execute_into]
+    sink: Option<StdoutSink<'a>>,
+}
+
+/// Writer stdout of a response is written to as it arrives.
+struct StdoutSink<'a> {
+    /// The writer of the caller
+    writer: &'a mut (dyn AsyncWrite + Unpin + Send),
+    /// The number of bytes written
+    written: &'a mut u64,
+}
+
+/// Builder of [Client], the single entry point for its settings.
+///
+/// The connection mode is part of the client type, so the builder starts in
+/// short connection mode and [ClientBuilder::keep_alive] switches it to keep
+/// alive mode.
+///
+/// # Examples
+///
+/// ```
+/// use fcgi_client::{Client, ClientBuilder};
+/// use std::time::Duration;
+/// use tokio::net::TcpStream;
+///
+/// async fn build() {
+///     let stream = TcpStream::connect(("127.0.0.1", 9000)).await.unwrap();
+///     let mut client = ClientBuilder::new(stream)
+///         .keep_alive()
+///         .read_timeout(Some(Duration::from_secs(30)))
+///         .max_response_bytes(Some(16 << 20))
+///         .build();
+/// }
+/// ```
+pub struct ClientBuilder<S, M = ShortConn> {
+    stream: S,
+    config: Config,
+    _mode: PhantomData<M>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> ClientBuilder<S, ShortConn> {
+    /// Creates a builder for a client over the stream, such as
+    /// `tokio::net::TcpStream` or `tokio::net::UnixStream`, under short
+    /// connection mode.
     pub fn new(stream: S) -> Self {
         Self {
             stream,
+            config: Config::default(),
+            _mode: PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl<S> ClientBuilder<Compat<S>, ShortConn>
+where
+    S: futures_util::io::AsyncRead + futures_util::io::AsyncWrite + Unpin,
+{
+    /// Creates a builder for a client over a stream implementing the
+    /// `futures::io` traits, such as an `async-std` or `smol` socket, under
+    /// short connection mode.
+    ///
+    /// The stream is adapted with `tokio_util::compat`, and requests run on
+    /// any executor. Only [ClientBuilder::read_timeout] needs a tokio runtime
+    /// for its timer. Request bodies are still `tokio::io::AsyncRead`, a
+    /// `futures::io` reader can be adapted with `tokio_util::compat` too.
+    pub fn from_futures_io(stream: S) -> Self {
+        Self::new(stream.compat())
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin, M: Mode> ClientBuilder<S, M> {
+    /// Switches to keep alive connection mode, so the client can send several
+    /// requests over the stream.
+    pub fn keep_alive(self) -> ClientBuilder<S, KeepAlive> {
+        ClientBuilder {
+            stream: self.stream,
+            config: self.config,
             _mode: PhantomData,
         }
     }
 
+    /// Set how long to wait for each record of the response, `None` means
+    /// wait forever (the default).
+    ///
+    /// Exceeding it fails the request with [ClientError::ReadTimeout]. The
+    /// rest of the response is left unread, so the connection should not be
+    /// reused afterwards.
+    pub fn read_timeout(mut self, read_timeout: Option<Duration>) -> Self {
+        self.config.read_timeout = read_timeout;
+        self
+    }
+
+    /// Set the maximum number of stdout bytes buffered by `execute` and
+    /// `execute_once`, see [Client::max_response_bytes].
+    pub fn max_response_bytes(mut self, max_response_bytes: Option<usize>) -> Self {
+        self.config.max_response_bytes = max_response_bytes;
+        self
+    }
+
+    /// Set the maximum number of stderr bytes buffered by `execute` and
+    /// `execute_once`, see [Client::max_stderr_bytes].
+    pub fn max_stderr_bytes(mut self, max_stderr_bytes: Option<usize>) -> Self {
+        self.config.max_stderr_bytes = max_stderr_bytes;
+        self
+    }
+
+    /// Set the maximum number of records read by `execute` and
+    /// `execute_once` for a response, see [Client::max_records].
+    pub fn max_records(mut self, max_records: Option<usize>) -> Self {
+        self.config.max_records = max_records;
+        self
+    }
+
+    /// Set the role of requests built with [Request::new], `Responder` by
+    /// default.
+    pub fn default_role(mut self, default_role: Role) -> Self {
+        self.config.default_role = default_role;
+        self
+    }
+
+    /// Set whether to reject records whose padding bytes aren't all zero
+    /// with [ClientError::InvalidPadding], `false` by default.
+    ///
+    /// Padding is always discarded; checking it helps to find a desync when
+    /// debugging a FastCGI server.
+    pub fn strict_padding(mut self, strict_padding: bool) -> Self {
+        self.config.strict_padding = strict_padding;
+        self
+    }
+
+    /// Set whether to validate the records from the server strictly, `false`
+    /// by default.
+    ///
+    /// Besides [ClientBuilder::strict_padding], which it also sets, strict
+    /// validation fails with [ClientError::UnexpectedRecord] on a record of
+    /// another request, including the late records of the previous request
+    /// otherwise discarded, and on a record already waiting after the
+    /// `EndRequest`, and with [ClientError::TruncatedParams] on name-value
+    /// pairs ending in the middle of a pair. A record with a protocol
+    /// version other than 1 is always rejected.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.config.strict = strict;
+        self.config.strict_padding = strict;
+        self
+    }
+
+    /// Set whether responses skip records of request IDs other than the one
+    /// in flight, `false` by default.
+    ///
+    /// By default only late records of the previous request are skipped,
+    /// and a record of any other request fails with
+    /// [ClientError::ResponseNotFound]. Skipping them instead tolerates
+    /// servers that leak records of older requests on a keep-alive
+    /// connection. Skipped records are logged and counted, see
+    /// [Client::skipped_records]. Ignored in [ClientBuilder::strict] mode.
+    pub fn skip_unknown_ids(mut self, skip_unknown_ids: bool) -> Self {
+        self.config.skip_unknown_ids = skip_unknown_ids;
+        self
+    }
+
+    /// Set how records written to the server are padded,
+    /// [Padding::Align8] by default.
+    ///
+    /// Padding is optional in FastCGI; dropping it saves a few bytes per
+    /// record and helps with servers that mishandle it.
+    pub fn padding(mut self, padding: Padding) -> Self {
+        self.config.padding = padding;
+        self
+    }
+
+    /// Set whether to flush the stream after every record written, `false`
+    /// by default.
+    ///
+    /// By default the stream is flushed once the begin request and params
+    /// records are written, so the server can start on the request while
+    /// the body is sent, and again after the last stdin or data record.
+    /// Flushing every record lowers the latency of slow streaming uploads
+    /// through a buffered stream such as `tokio::io::BufWriter`.
+    pub fn flush_every_record(mut self, flush_every_record: bool) -> Self {
+        self.config.flush_every_record = flush_every_record;
+        self
+    }
+
+    /// Set the maximum content length of the params, stdin and data records
+    /// written, clamped to between 1 and the protocol maximum of 65535
+    /// bytes (the default).
+    ///
+    /// A record of the body is written as soon as the body has data, with
+    /// whatever else is available right away, rather than once a record is
+    /// full, so the record size only caps how much goes in one record.
+    pub fn record_size(mut self, record_size: usize) -> Self {
+        self.config.record_size = record_size.clamp(1, MAX_LENGTH);
+        self
+    }
+
+    /// Set a callback receiving the header of every record read or written,
+    /// for example to check the records exchanged with a FastCGI server in
+    /// integration tests.
+    ///
+    /// Only the header is passed, the content isn't copied. The callback is
+    /// called inline for each record, so it should return quickly.
+    pub fn on_record<F>(mut self, on_record: F) -> Self
+    where
+        F: Fn(Direction, &Header) + Send + Sync + 'static,
+    {
+        self.config.on_record = Some(Arc::new(on_record));
+        self
+    }
+
+    /// Builds the client.
+    pub fn build(self) -> Client<S, M> {
+        Client {
+            stream: self.stream,
+            request_id: 0,
+            reusable: true,
+            broken: false,
+            skipped_records: 0,
+            stream_shared: Arc::default(),
+            config: self.config,
+            write_buf: Vec::new(),
+            preamble_buf: Vec::new(),
+            ahead: BytesMut::new(),
+            read_buf: BytesMut::new(),
+            capabilities: None,
+            last_used: Instant::now(),
+            _mode: PhantomData,
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Client<S, ShortConn> {
+    /// Construct a `Client` Object with stream, such as `tokio::net::TcpStream`
+    /// or `tokio::net::UnixStream`, under short connection mode.
+    pub fn new(stream: S) -> Self {
+        ClientBuilder::new(stream).build()
+    }
+
+    /// Creates a [ClientBuilder] over the stream, to configure the client
+    /// before use.
+    pub fn builder(stream: S) -> ClientBuilder<S> {
+        ClientBuilder::new(stream)
+    }
+
     /// Send request and receive response from fastcgi server, under short
     /// connection mode.
-    pub async fn execute_once<I: AsyncRead + Unpin>(
+    ///
+    /// The response is read while stdin is sent, so an application writing
+    /// its output before reading all of its input doesn't block both sides
+    /// once the connection buffers are full. Stdin stops when the server
+    /// ends the request before reading all of it.
+    pub async fn execute_once<I: AsyncRead + Unpin, D: AsyncRead + Unpin>(
         mut self,
-        request: Request<'_, I>,
+        request: Request<'_, I, D>,
     ) -> ClientResult<Response> {
         self.inner_execute(request).await
     }
 
+    /// Send request to fastcgi server and write stdout of the response to
+    /// `writer` as it arrives, under short connection mode.
+    ///
+    /// Works like [Client::execute_once], without buffering stdout, see
+    /// [Client::execute_into].
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The request to execute
+    /// * `writer` - The writer stdout is written to
+    pub async fn execute_once_into<I, D, W>(
+        mut self,
+        request: Request<'_, I, D>,
+        writer: &mut W,
+    ) -> ClientResult<ResponseSummary>
+    where
+        I: AsyncRead + Unpin,
+        D: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin + Send,
+    {
+        self.inner_execute_into(request, writer).await
+    }
+
     /// Send request and receive response stream from fastcgi server, under
     /// short connection mode.
     ///
+    /// The part of the response received while stdin is sent is buffered and
+    /// yielded first by the stream.
+    ///
     /// # Examples
     ///
     /// ```
-    /// use fastcgi_client::{response::Content, Client, Params, Request, StreamExt};
+    /// use fcgi_client::{response::Content, Client, Params, Request};
+    /// use futures_util::StreamExt;
     /// use tokio::{io, net::TcpStream};
     ///
     /// async fn stream() {
@@ -91,12 +507,17 @@ impl<S: AsyncRead + AsyncWrite + Unpin> Client<S, ShortConn> {
     ///     }
     /// }
     /// ```
-    pub async fn execute_once_stream<I: AsyncRead + Unpin>(
+    pub async fn execute_once_stream<I: AsyncRead + Unpin, D: AsyncRead + Unpin>(
         mut self,
-        request: Request<'_, I>,
+        request: Request<'_, I, D>,
     ) -> ClientResult<ResponseStream<S>> {
-        Self::handle_request(&mut self.stream, REQUEST_ID, request.params, request.stdin).await?;
-        Ok(ResponseStream::new(self.stream, REQUEST_ID))
+        let id = self.next_request_id();
+        let span = self.request_span(id, &request);
+        let role = self
+            .handle_request(id, request, AheadLimits::default())
+            .instrument(span.clone())
+            .await?;
+        Ok(ResponseStream::new(self.stream, id, role, &self.config, span).read_ahead(self.ahead))
     }
 }
 
@@ -104,28 +525,115 @@ impl<S: AsyncRead + AsyncWrite + Unpin> Client<S, KeepAlive> {
     /// Construct a `Client` Object with stream, such as `tokio::net::TcpStream`
     /// or `tokio::net::UnixStream`, under keep alive connection mode.
     pub fn new_keep_alive(stream: S) -> Self {
-        Self {
-            stream,
-            _mode: PhantomData,
-        }
+        ClientBuilder::new(stream).keep_alive().build()
     }
 
     /// Send request and receive response from fastcgi server, under keep alive
     /// connection mode.
-    pub async fn execute<I: AsyncRead + Unpin>(
+    ///
+    /// The response ends at the `EndRequest` of the request. Records a
+    /// misbehaving server sends for the request after it are discarded, those
+    /// already received right away and later ones while reading the response
+    /// of the next request, so they don't desync the connection.
+    ///
+    /// The response is read while stdin is sent. If the server ends the
+    /// request before reading all of it, the rest isn't sent and the
+    /// connection is marked broken, see [Client::is_broken], since the server
+    /// may still expect it.
+    pub async fn execute<I: AsyncRead + Unpin, D: AsyncRead + Unpin>(
         &mut self,
-        request: Request<'_, I>,
+        request: Request<'_, I, D>,
     ) -> ClientResult<Response> {
         self.inner_execute(request).await
     }
 
+    /// Send request to fastcgi server and write stdout of the response to
+    /// `writer` as it arrives, such as a socket or a file, under keep alive
+    /// connection mode.
+    ///
+    /// Works like [Client::execute], but stdout isn't buffered: only the
+    /// number of bytes written is returned, along with the application
+    /// status and stderr. The writer is flushed once the response ends.
+    /// [ClientBuilder::max_response_bytes] doesn't apply, unlike
+    /// [Request::with_max_stdout_bytes].
+    ///
+    /// If writing fails, the rest of the response is left on the connection,
+    /// which is marked broken, see [Client::is_broken].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fcgi_client::{Client, Params, Request};
+    /// use tokio::{fs::File, io, net::TcpStream};
+    ///
+    /// async fn relay() {
+    ///     let stream = TcpStream::connect(("127.0.0.1", 9000)).await.unwrap();
+    ///     let mut client = Client::new_keep_alive(stream);
+    ///     let mut file = File::create("output").await.unwrap();
+    ///     let summary = client
+    ///         .execute_into(Request::new(Params::default(), io::empty()), &mut file)
+    ///         .await
+    ///         .unwrap();
+    ///     println!("{} bytes written", summary.stdout_len);
+    /// }
+    /// ```
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The request to execute
+    /// * `writer` - The writer stdout is written to
+    pub async fn execute_into<I, D, W>(
+        &mut self,
+        request: Request<'_, I, D>,
+        writer: &mut W,
+    ) -> ClientResult<ResponseSummary>
+    where
+        I: AsyncRead + Unpin,
+        D: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin + Send,
+    {
+        self.inner_execute_into(request, writer).await
+    }
+
+    /// Send request and receive response from fastcgi server like
+    /// [Client::execute], aborting the request when `token` is cancelled.
+    ///
+    /// The request is always written whole. Once it is sent, a cancellation
+    /// between records of the response sends `AbortRequest`, discards the
+    /// rest of the response up to its `EndRequest` and fails with
+    /// [ClientError::Cancelled], leaving the connection usable for the next
+    /// request. If the `EndRequest` doesn't arrive within the read timeout,
+    /// the request still fails with [ClientError::Cancelled] but the client
+    /// is [broken](Client::is_broken). A token already cancelled fails with
+    /// id 0 before anything is sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The request to execute
+    /// * `token` - The token cancelling the request
+    pub async fn execute_with_cancel<I: AsyncRead + Unpin, D: AsyncRead + Unpin>(
+        &mut self,
+        request: Request<'_, I, D>,
+        token: &CancellationToken,
+    ) -> ClientResult<Response> {
+        if token.is_cancelled() {
+            // No request is sent, so no id is taken for it.
+            return Err(ClientError::Cancelled { id: 0 });
+        }
+        self.execute_cancellable(request, &mut false, Some(token), None).await
+    }
+
     /// Send request and receive response stream from fastcgi server, under
     /// keep alive connection mode.
     ///
+    /// The part of the response received while stdin is sent is buffered and
+    /// yielded first by the stream.
+    ///
     /// # Examples
     ///
     /// ```
-    /// use fastcgi_client::{response::Content, Client, Params, Request, StreamExt};
+    /// use fcgi_client::{response::Content, Client, Params, Request};
+    /// use futures_util::StreamExt;
     /// use tokio::{io, net::TcpStream};
     ///
     /// async fn stream() {
@@ -149,49 +657,729 @@ impl<S: AsyncRead + AsyncWrite + Unpin> Client<S, KeepAlive> {
     ///     }
     /// }
     /// ```
-    pub async fn execute_stream<I: AsyncRead + Unpin>(
+    pub async fn execute_stream<I: AsyncRead + Unpin, D: AsyncRead + Unpin>(
         &mut self,
-        request: Request<'_, I>,
+        request: Request<'_, I, D>,
     ) -> ClientResult<ResponseStream<&mut S>> {
-        Self::handle_request(&mut self.stream, REQUEST_ID, request.params, request.stdin).await?;
-        Ok(ResponseStream::new(&mut self.stream, REQUEST_ID))
+        let previous_id = self.request_id;
+        let id = self.next_request_id();
+        let span = self.request_span(id, &request);
+        let role = self
+            .handle_request(id, request, AheadLimits::default())
+            .instrument(span.clone())
+            .await?;
+        let ahead = mem::take(&mut self.ahead);
+        let shared = self.stream_shared.clone();
+        Ok(ResponseStream::new(&mut self.stream, id, role, &self.config, span)
+            .read_ahead(ahead)
+            .shared(previous_id, shared))
+    }
+
+    /// Send request and receive response from fastcgi server, retrying it as
+    /// allowed by the policy, under keep alive connection mode.
+    ///
+    /// `request` builds the request for each attempt, since its stdin is
+    /// consumed by sending it. Each attempt gets a new request id.
+    ///
+    /// Only the `Overloaded` status is retried on the same connection; to
+    /// also retry a connection closed by the server, use
+    /// [crate::Pool::execute_with_retry], which opens a new one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fcgi_client::{Client, Params, Request, RetryPolicy};
+    /// use tokio::{io, net::TcpStream};
+    ///
+    /// async fn retry() {
+    ///     let stream = TcpStream::connect(("127.0.0.1", 9000)).await.unwrap();
+    ///     let mut client = Client::new_keep_alive(stream);
+    ///     let output = client
+    ///         .execute_with_retry(RetryPolicy::new(3), || {
+    ///             Request::new(Params::default().request_method("GET"), io::empty())
+    ///         })
+    ///         .await
+    ///         .unwrap();
+    /// }
+    /// ```
+    pub async fn execute_with_retry<'a, I, D, F>(
+        &mut self,
+        policy: RetryPolicy,
+        mut request: F,
+    ) -> ClientResult<Response>
+    where
+        I: AsyncRead + Unpin,
+        D: AsyncRead + Unpin,
+        F: FnMut() -> Request<'a, I, D>,
+    {
+        let mut attempt = 1;
+        loop {
+            let mut response_started = false;
+            match self.execute_tracked(request(), &mut response_started).await {
+                Err(err) if policy.should_retry(attempt, &err, response_started, false) => {
+                    debug!(attempt, ?err, "Retry request.");
+                    tokio::time::sleep(policy.delay(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Cancel an in-flight request by sending `FCGI_ABORT_REQUEST`, then read
+    /// and discard records until the `EndRequest` of that request arrives.
+    ///
+    /// If the server had already queued the `EndRequest` before the abort
+    /// record was written, that record is consumed and the abort is ignored
+    /// by the server.
+    ///
+    /// Nothing is sent if `request_id` isn't the last request, or if its
+    /// response was read to the end, as the server has no such request in
+    /// flight and would never answer the abort.
+    ///
+    /// To cancel a request whose response is being streamed, prefer
+    /// [ResponseStream::abort], which also discards the data already
+    /// buffered by the stream.
+    pub async fn abort(&mut self, request_id: u16) -> ClientResult<()> {
+        check_request_id(request_id)?;
+        if request_id != self.request_id || !self.is_broken() {
+            debug!(id = request_id, "No such request in flight, skip abort.");
+            return Ok(());
+        }
+        Self::handle_abort(&mut self.stream, request_id, &self.config).await
+    }
+
+    /// Closes the connection cleanly, first discarding records the server
+    /// already sent for completed requests, such as the tail of an aborted
+    /// one.
+    ///
+    /// Only records that have already arrived are read, up to 16 of them, so
+    /// this doesn't wait for the server when nothing is pending. The write
+    /// half is then flushed and shut down, and the client is no longer
+    /// [reusable](Client::is_reusable).
+    pub async fn shutdown(&mut self) -> ClientResult<()> {
+        self.reusable = false;
+        Self::discard_pending(&mut self.stream, &mut self.read_buf, &self.config, false).await?;
+        self.stream.flush().await?;
+        self.stream.shutdown().await?;
+        Ok(())
+    }
+}
+
+impl Client<TcpStream, ShortConn> {
+    /// Connect to a fastcgi server over TCP under short connection mode.
+    ///
+    /// The socket keeps the system defaults, see [SocketOptions::connect] to
+    /// change them.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address of the server, such as `("php-fpm", 9000)`
+    /// * `timeout` - How long to wait for each address to connect
+    pub async fn connect(addr: impl ToSocketAddrs, timeout: Duration) -> ClientResult<Self> {
+        Ok(Self::new(SocketOptions::default().connect(addr, timeout).await?))
+    }
+}
+
+impl Client<TcpStream, KeepAlive> {
+    /// Connect to a fastcgi server over TCP under keep alive connection mode.
+    ///
+    /// The socket keeps the system defaults, see [SocketOptions::connect] to
+    /// change them.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address of the server, such as `("php-fpm", 9000)`
+    /// * `timeout` - How long to wait for each address to connect
+    pub async fn connect_keep_alive(
+        addr: impl ToSocketAddrs,
+        timeout: Duration,
+    ) -> ClientResult<Self> {
+        Ok(Self::new_keep_alive(
+            SocketOptions::default().connect(addr, timeout).await?,
+        ))
+    }
+}
+
+#[cfg(unix)]
+impl Client<UnixStream, ShortConn> {
+    /// Connect to a fastcgi server listening on a Unix domain socket, such as
+    /// `/run/php/php-fpm.sock`, under short connection mode.
+    pub async fn connect_unix<P: AsRef<Path>>(path: P) -> ClientResult<Self> {
+        Ok(Self::new(UnixStream::connect(path).await?))
+    }
+}
+
+#[cfg(unix)]
+impl Client<UnixStream, KeepAlive> {
+    /// Connect to a fastcgi server listening on a Unix domain socket, such as
+    /// `/run/php/php-fpm.sock`, under keep alive connection mode.
+    pub async fn connect_unix_keep_alive<P: AsRef<Path>>(path: P) -> ClientResult<Self> {
+        Ok(Self::new_keep_alive(UnixStream::connect(path).await?))
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl Client<UnixStream, ShortConn> {
+    /// Connect to a fastcgi server listening on a socket of the Linux
+    /// abstract namespace, under short connection mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the socket, without the leading NUL byte
+    pub async fn connect_unix_abstract(name: impl AsRef<[u8]>) -> ClientResult<Self> {
+        Ok(Self::new(UnixStream::connect(abstract_path(name.as_ref())).await?))
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl Client<UnixStream, KeepAlive> {
+    /// Connect to a fastcgi server listening on a socket of the Linux
+    /// abstract namespace, under keep alive connection mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the socket, without the leading NUL byte
+    pub async fn connect_unix_abstract_keep_alive(name: impl AsRef<[u8]>) -> ClientResult<Self> {
+        Ok(Self::new_keep_alive(UnixStream::connect(abstract_path(name.as_ref())).await?))
+    }
+}
+
+#[cfg(feature = "vsock")]
+impl Client<VsockStream, ShortConn> {
+    /// Connect to a fastcgi server listening on a vsock port, such as an
+    /// application inside a microVM, under short connection mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `cid` - The context ID of the VM, or `VMADDR_CID_HOST` from a guest
+    /// * `port` - The vsock port of the server
+    pub async fn connect_vsock(cid: u32, port: u32) -> ClientResult<Self> {
+        Ok(Self::new(VsockStream::connect(VsockAddr::new(cid, port)).await?))
+    }
+}
+
+#[cfg(feature = "vsock")]
+impl Client<VsockStream, KeepAlive> {
+    /// Connect to a fastcgi server listening on a vsock port, such as an
+    /// application inside a microVM, under keep alive connection mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `cid` - The context ID of the VM, or `VMADDR_CID_HOST` from a guest
+    /// * `port` - The vsock port of the server
+    pub async fn connect_vsock_keep_alive(cid: u32, port: u32) -> ClientResult<Self> {
+        Ok(Self::new_keep_alive(VsockStream::connect(VsockAddr::new(cid, port)).await?))
+    }
+}
+
+impl<S, M> Client<S, M> {
+    /// Consumes the client and returns the underlying stream, for example to
+    /// hand it back to a connection pool.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Returns whether the server was asked to keep the connection open after
+    /// the last request, `false` once a request overrode keep alive with
+    /// [Request::with_keep_alive].
+    pub fn is_reusable(&self) -> bool {
+        self.reusable
+    }
+
+    /// Returns whether a request failed in a way that left the connection
+    /// out of sync, such as an IO error, a timeout or a malformed record, so
+    /// the client shouldn't be used again.
+    ///
+    /// Requests the server ended with an error status and cancelled requests
+    /// leave the connection usable. A response stream dropped before its end
+    /// breaks it.
+    pub fn is_broken(&self) -> bool {
+        self.broken || self.stream_shared.broken.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of records of other requests skipped while reading
+    /// responses, such as late records of the previous request, see
+    /// [ClientBuilder::skip_unknown_ids].
+    pub fn skipped_records(&self) -> u64 {
+        self.skipped_records + self.stream_shared.skipped_records.load(Ordering::Relaxed)
     }
 }
 
 impl<S: AsyncRead + AsyncWrite + Unpin, M: Mode> Client<S, M> {
+    /// Checks whether the connection can be used for another request, without
+    /// a round trip to the server.
+    ///
+    /// The stream is polled once without waiting: an idle connection has
+    /// nothing to read and is healthy, while a closed connection, a read
+    /// error, or unexpected bytes from the server (which would desync the
+    /// next request) make it unhealthy. In the last case one byte is consumed,
+    /// so the client should be discarded.
+    ///
+    /// A client that isn't [reusable](Client::is_reusable) or is
+    /// [broken](Client::is_broken) is never healthy.
+    pub fn is_healthy(&mut self) -> bool {
+        if !self.reusable || self.is_broken() {
+            return false;
+        }
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut byte = [0u8; 1];
+        let mut buf = ReadBuf::new(&mut byte);
+        let poll = Pin::new(&mut self.stream).poll_read(&mut cx, &mut buf);
+        if poll.is_ready() {
+            debug!(?poll, read = buf.filled().len(), "Connection isn't healthy.");
+        }
+        poll.is_pending()
+    }
+
+    /// Returns when the connection was last used: when the client was built,
+    /// a request or management record was last sent, or its reply was last
+    /// read.
+    ///
+    /// A pool can use it to decide when an idle connection should be
+    /// [pinged](Client::ping) or closed.
+    pub fn last_used(&self) -> Instant {
+        self.last_used
+    }
+
+    /// Checks that the application still answers on the connection, with a
+    /// `GetValues` management record querying no variables.
+    ///
+    /// An application that doesn't support management records but replies
+    /// with `UnknownType` is alive too. Unlike [Client::is_healthy] this is a
+    /// round trip, so it also finds connections silently dropped by the
+    /// network. The client is borrowed mutably, so a ping never runs while a
+    /// request is in flight on the connection.
+    pub async fn ping(&mut self) -> ClientResult<()> {
+        match self.get_values(&[]).await {
+            Ok(_) | Err(ClientError::UnknownType { .. }) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Set the maximum number of stdout bytes buffered by `execute` and
+    /// `execute_once`, `None` means unlimited (the default).
+    ///
+    /// The limit is checked before each `Stdout` record is read, and
+    /// exceeding it fails the request with [ClientError::ResponseTooLarge]
+    /// and drops the bytes read so far. The rest of the response is left
+    /// unread, so the connection should not be reused afterwards.
+    pub fn max_response_bytes(mut self, max_response_bytes: Option<usize>) -> Self {
+        self.config.max_response_bytes = max_response_bytes;
+        self
+    }
+
+    /// Set the maximum number of stderr bytes buffered by `execute` and
+    /// `execute_once`, `None` means unlimited (the default).
+    ///
+    /// Checked like [Client::max_response_bytes], exceeding it fails the
+    /// request with [ClientError::ResponseTooLarge].
+    pub fn max_stderr_bytes(mut self, max_stderr_bytes: Option<usize>) -> Self {
+        self.config.max_stderr_bytes = max_stderr_bytes;
+        self
+    }
+
+    /// Set the maximum number of records `execute` and `execute_once` read
+    /// for a response, `None` means unlimited (the default).
+    ///
+    /// Every record counts, including empty ones and those discarded, which
+    /// bounds the work a server flooding tiny records can cause. Exceeding
+    /// it fails the request with [ClientError::TooManyRecords] and leaves
+    /// the rest of the response unread, so the connection should not be
+    /// reused afterwards.
+    pub fn max_records(mut self, max_records: Option<usize>) -> Self {
+        self.config.max_records = max_records;
+        self
+    }
+
+    /// Queries variables of the application with a `GetValues` management
+    /// record, such as [crate::meta::FCGI_MPXS_CONNS].
+    ///
+    /// Variables the application doesn't know are missing from the result.
+    /// An application that doesn't support management records replies with
+    /// [ClientError::UnknownType].
+    ///
+    /// Records of requests arriving before the reply, such as the tail of a
+    /// response dropped early, are discarded and counted in
+    /// [Client::skipped_records], or fail with
+    /// [ClientError::UnexpectedRecord] in [strict](ClientBuilder::strict)
+    /// mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `names` - The names of the variables to query
+    pub async fn get_values(&mut self, names: &[&str]) -> ClientResult<HashMap<String, String>> {
+        debug!(?names, "Get values.");
+        let mut get_values_rec = GetValuesRec::new(names)?;
+        self.config.pad(&mut get_values_rec.header);
+        self.config.tap(Direction::Write, &get_values_rec.header);
+        get_values_rec.write_to_stream(&mut self.stream).await?;
+        self.stream.flush().await?;
+        self.last_used = Instant::now();
+
+        let read_timeout = self.config.read_timeout;
+        let mut records = 0;
+        let (header, content) = loop {
+            let header =
+                with_read_timeout(read_timeout, Header::new_from_stream(&mut self.stream)).await?;
+            self.config.tap(Direction::Read, &header);
+            records += 1;
+            if let Some(limit) = self.config.max_records {
+                if records > limit {
+                    self.broken = true;
+                    return Err(ClientError::TooManyRecords { limit });
+                }
+            }
+            if header.request_id != MANAGEMENT_REQUEST_ID && self.config.strict {
+                self.broken = true;
+                return Err(ClientError::UnexpectedRecord {
+                    request_type: header.r#type,
+                    request_id: header.request_id,
+                });
+            }
+            let content = with_read_timeout(
+                read_timeout,
+                header.read_content_from_stream(&mut self.stream, self.config.strict_padding),
+            )
+            .await?;
+            if header.request_id == MANAGEMENT_REQUEST_ID {
+                break (header, content);
+            }
+            // Such as the tail of a request dropped before its end.
+            warning!(?header, "Discard record of a request.");
+            self.skipped_records += 1;
+        };
+        self.last_used = Instant::now();
+        match header.r#type {
+            RequestType::GetValuesResult => {
+                if self.config.strict {
+                    check_params(&content)?;
+                }
+                Ok(GetValuesResultRec::new_from_buf(header, content).values)
+            }
+            RequestType::UnknownType => Err(ClientError::UnknownType {
+                type_id: UnknownTypeRec::new_from_buf(header, content).unknown_type.r#type,
+            }),
+            request_type => Err(ClientError::UnknownRequestType { request_type }),
+        }
+    }
+
+    /// Asks an Authorizer application whether a request is allowed.
+    ///
+    /// The params describe the request to authorize and are sent with the
+    /// `Authorizer` role and an empty stdin. Access is allowed if the
+    /// application replies with status 200, and the `Variable-*` headers of
+    /// the reply are collected in [AuthResponse::variables].
+    ///
+    /// # Arguments
+    ///
+    /// * `params` - The FastCGI parameters of the request to authorize
+    pub async fn authorize(&mut self, params: Params<'_>) -> ClientResult<AuthResponse> {
+        let mut request = Request::new(params, io::empty());
+        request.role = Some(Role::Authorizer);
+        let response = self.inner_execute(request).await?;
+        let auth_response = AuthResponse::new(response.parse()?);
+        debug!(allowed = auth_response.allowed, "Receive authorization.");
+        Ok(auth_response)
+    }
+
+    /// Queries the well-known variables of the application along with
+    /// `names`, returning them parsed, see [ServerValues].
+    ///
+    /// Unlike [Client::capabilities], nothing is cached, and an application
+    /// that doesn't support management records fails with
+    /// [ClientError::UnknownType].
+    ///
+    /// # Arguments
+    ///
+    /// * `names` - The names of the other variables to query
+    pub async fn server_values(&mut self, names: &[&str]) -> ClientResult<ServerValues> {
+        let mut all = Capabilities::NAMES.to_vec();
+        all.extend(names.iter().filter(|name| !Capabilities::NAMES.contains(name)));
+        Ok(ServerValues::from_values(self.get_values(&all).await?))
+    }
+
+    /// Returns the capabilities of the application, querying them with
+    /// [Client::get_values] on the first call.
+    ///
+    /// The result is cached for the lifetime of the connection, a client on
+    /// a new connection, such as one opened by [crate::Pool], queries again.
+    /// If the application doesn't support management records the defaults
+    /// are used.
+    pub async fn capabilities(&mut self) -> ClientResult<&Capabilities> {
+        if self.capabilities.is_none() {
+            let capabilities = match self.get_values(&Capabilities::NAMES).await {
+                Ok(values) => ServerValues::from_values(values).capabilities(),
+                Err(ClientError::UnknownType { .. }) => Capabilities::default(),
+                Err(err) => return Err(err),
+            };
+            debug!(?capabilities, "Receive capabilities.");
+            self.capabilities = Some(capabilities);
+        }
+        Ok(self.capabilities.as_ref().unwrap())
+    }
+
     /// Internal method to execute a request and return a complete response.
     ///
     /// # Arguments
     ///
     /// * `request` - The request to execute
-    async fn inner_execute<I: AsyncRead + Unpin>(
+    async fn inner_execute<I: AsyncRead + Unpin, D: AsyncRead + Unpin>(
         &mut self,
-        request: Request<'_, I>,
+        request: Request<'_, I, D>,
     ) -> ClientResult<Response> {
-        Self::handle_request(&mut self.stream, REQUEST_ID, request.params, request.stdin).await?;
-        Self::handle_response(&mut self.stream, REQUEST_ID).await
+        self.execute_tracked(request, &mut false).await
     }
 
-    /// Handles the complete request process.
+    /// Internal method to execute a request, writing stdout to `writer`.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The request to execute
+    /// * `writer` - The writer stdout is written to
+    async fn inner_execute_into<I: AsyncRead + Unpin, D: AsyncRead + Unpin>(
+        &mut self,
+        request: Request<'_, I, D>,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> ClientResult<ResponseSummary> {
+        let mut written = 0;
+        let sink = StdoutSink {
+            writer,
+            written: &mut written,
+        };
+        let response = self
+            .execute_cancellable(request, &mut false, None, Some(sink))
+            .await?;
+        Ok(ResponseSummary {
+            request_id: response.request_id,
+            app_status: response.app_status,
+            stdout_len: written,
+            stderr: response.stderr,
+        })
+    }
+
+    /// Executes a request like `inner_execute`, recording whether stdout of
+    /// the response was received, to tell whether a failed request can be
+    /// retried.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The request to execute
+    /// * `response_started` - Set once stdout is received
+    pub(crate) async fn execute_tracked<I: AsyncRead + Unpin, D: AsyncRead + Unpin>(
+        &mut self,
+        request: Request<'_, I, D>,
+        response_started: &mut bool,
+    ) -> ClientResult<Response> {
+        self.execute_cancellable(request, response_started, None, None).await
+    }
+
+    /// Executes a request like `execute_tracked`, aborting it if `cancel` is
+    /// cancelled while waiting for the response.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The request to execute
+    /// * `response_started` - Set once stdout is received
+    /// * `cancel` - The token cancelling the request
+    /// * `sink` - The writer stdout is written to instead of the response
+    async fn execute_cancellable<'c, I: AsyncRead + Unpin, D: AsyncRead + Unpin>(
+        &mut self,
+        mut request: Request<'_, I, D>,
+        response_started: &mut bool,
+        cancel: Option<&'c CancellationToken>,
+        sink: Option<StdoutSink<'c>>,
+    ) -> ClientResult<Response> {
+        let previous_id = self.request_id;
+        let id = self.next_request_id();
+        let span = self.request_span(id, &request);
+        let expected_size = request.expected_response_size;
+        let stderr_handler = request.stderr_handler.take();
+        let (max_stdout_bytes, max_stderr_bytes) =
+            (request.max_stdout_bytes, request.max_stderr_bytes);
+        let limits = AheadLimits {
+            records: self.config.max_records,
+            stdout: lowest(max_stdout_bytes, self.config.max_response_bytes, sink.is_none()),
+            stderr: lowest(
+                max_stderr_bytes,
+                self.config.max_stderr_bytes,
+                stderr_handler.is_none(),
+            ),
+        };
+        async {
+            let role = self.handle_request(id, request, limits).await?;
+            let in_flight = InFlight {
+                id,
+                previous_id,
+                role,
+                expected_size,
+                cancel,
+                stderr_handler,
+                max_stdout_bytes,
+                max_stderr_bytes,
+                sink,
+            };
+            let response = Self::handle_response(
+                &mut ReadAhead::new(&mut self.stream, &mut self.ahead, id),
+                in_flight,
+                &self.config,
+                &mut self.read_buf,
+                response_started,
+                &mut self.skipped_records,
+            )
+            .await;
+            self.last_used = Instant::now();
+            if !self.ahead.is_empty() {
+                // Part of a record read ahead is left, the rest of which is
+                // still in the stream.
+                self.broken = true;
+                self.ahead.clear();
+            }
+            match response {
+                Err(ClientError::ReadTimeout { .. })
+                    if cancel.is_some_and(CancellationToken::is_cancelled) =>
+                {
+                    // The abort went unanswered, the rest of the response
+                    // may still arrive.
+                    self.broken = true;
+                    Err(ClientError::Cancelled { id })
+                }
+                response => response,
+            }
+        }
+        .instrument(span)
+        .await
+        .inspect_err(|err| self.broken |= !err.keeps_connection())
+    }
+
+    /// Creates the span covering a request.
     ///
     /// # Arguments
     ///
-    /// * `stream` - The stream to write to
     /// * `id` - The request ID
-    /// * `params` - The request parameters
-    /// * `body` - The request body stream
-    async fn handle_request<'a, I: AsyncRead + Unpin>(
-        stream: &mut S,
+    /// * `request` - The request to send
+    fn request_span<I: AsyncRead + Unpin, D: AsyncRead + Unpin>(
+        &self,
         id: u16,
-        params: Params<'a>,
-        mut body: I,
-    ) -> ClientResult<()> {
-        Self::handle_request_start(stream, id).await?;
+        request: &Request<'_, I, D>,
+    ) -> Span {
+        trace::request_span(id, request.role.unwrap_or(self.config.default_role))
+    }
 
-        Self::handle_request_params(stream, id, params).await?;
-        Self::handle_request_body(stream, id, &mut body).await?;
-        Self::handle_request_flush(stream).await?;
-        Ok(())
+    /// Allocates the request id for the next request.
+    ///
+    /// Ids start at 1 like nginx does and increase by one per request,
+    /// wrapping around after `u16::MAX`. Id 0 is reserved for management
+    /// records and is never used. Requests are sent one at a time, so a
+    /// wrapped id can't collide with one still in flight.
+    fn next_request_id(&mut self) -> u16 {
+        self.request_id = match self.request_id {
+            u16::MAX => 1,
+            id => id + 1,
+        };
+        self.request_id
+    }
+
+    /// Handles the complete request process, returning the role the request
+    /// was sent with.
+    ///
+    /// Unless every record is flushed, the begin request and params records
+    /// are sent in one write, along with the end of stdin when the body is
+    /// empty.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The request ID
+    /// * `request` - The request to send
+    /// * `limits` - The limits of the response read ahead while sending
+    async fn handle_request<'a, I: AsyncRead + Unpin, D: AsyncRead + Unpin>(
+        &mut self,
+        id: u16,
+        request: Request<'a, I, D>,
+        limits: AheadLimits,
+    ) -> ClientResult<Role> {
+        let Request {
+            params,
+            raw_params,
+            mut stdin,
+            mut data,
+            role,
+            keep_alive,
+            ..
+        } = request;
+        let role = role.unwrap_or(self.config.default_role);
+        let keep_alive = keep_alive.unwrap_or_else(<M>::is_keep_alive);
+        self.reusable &= keep_alive;
+        let content_length = match raw_params {
+            Some(_) => None,
+            None => params
+                .get(&b"CONTENT_LENGTH"[..])
+                .and_then(|value| str::from_utf8(value).ok()?.trim().parse::<usize>().ok()),
+        };
+        let config = &self.config;
+        let flush = config.flush_every_record;
+        let stream = &mut ReadAhead::new(&mut self.stream, &mut self.ahead, id).limits(limits);
+        let buf = &mut self.write_buf;
+        let preamble = &mut self.preamble_buf;
+
+        let result: ClientResult<()> = async {
+            let sent = if flush {
+                Self::handle_request_start(stream, id, role, keep_alive, config).await?;
+                Self::handle_request_flush(stream).await?;
+                Self::handle_request_params(stream, id, params, raw_params, buf, config).await?;
+                Self::handle_request_body(stream, id, &mut stdin, buf, 0, config).await?
+            } else {
+                preamble.clear();
+                Self::handle_request_start(preamble, id, role, keep_alive, config).await?;
+                Self::handle_request_params(preamble, id, params, raw_params, buf, config).await?;
+
+                // Only a body that ends right away is sent with the preamble,
+                // a slow body must not hold back the params.
+                buf.resize(config.record_size, 0);
+                let filled = read_ready(&mut stdin, buf)?;
+                if filled == Some(0) {
+                    Self::handle_request_body(preamble, id, &mut stdin, buf, 0, config).await?;
+                }
+                stream.write_all(preamble).await?;
+                Self::handle_request_flush(stream).await?;
+
+                match filled {
+                    Some(0) => 0,
+                    filled => {
+                        let filled = filled.unwrap_or_default();
+                        Self::handle_request_body(stream, id, &mut stdin, buf, filled, config)
+                            .await?
+                    }
+                }
+            };
+            if content_length.is_some_and(|content_length| content_length != sent) {
+                warning!(
+                    id,
+                    content_length,
+                    sent,
+                    "CONTENT_LENGTH doesn't match the stdin sent."
+                );
+            }
+            if let Role::Filter = role {
+                Self::handle_request_data(stream, id, &mut data, buf, config).await?;
+            }
+            if !flush {
+                Self::handle_request_flush(stream).await?;
+            }
+            Ok(())
+        }
+        .await;
+        if let Err(err) = result {
+            if !stream.ended {
+                return Err(err);
+            }
+            // The rest of the body isn't sent, and the server may still
+            // expect the rest of a record cut short.
+            debug!(id, "The request ended before it was sent whole.");
+            self.broken = true;
+        }
+        self.last_used = Instant::now();
+        Ok(role)
     }
 
     /// Handles the start of a request by sending the begin request record.
@@ -200,13 +1388,24 @@ impl<S: AsyncRead + AsyncWrite + Unpin, M: Mode> Client<S, M> {
     ///
     /// * `stream` - The stream to write to
     /// * `id` - The request ID
-    async fn handle_request_start(stream: &mut S, id: u16) -> ClientResult<()> {
-        debug!(id, "Start handle request");
+    /// * `role` - The role of the application
+    /// * `keep_alive` - Whether the server should keep the connection open
+    /// * `config` - The client settings, for the padding and record tap
+    async fn handle_request_start<W: AsyncWrite + Unpin>(
+        stream: &mut W,
+        id: u16,
+        role: Role,
+        keep_alive: bool,
+        config: &Config,
+    ) -> ClientResult<()> {
+        debug!(id, ?role, keep_alive, "Start handle request");
 
-        let begin_request_rec = BeginRequestRec::new(id, Role::Responder, <M>::is_keep_alive());
+        let mut begin_request_rec = BeginRequestRec::new(id, role, keep_alive)?;
 
         //debug!(id, ?begin_request_rec, "Send to stream.");
 
+        config.pad(&mut begin_request_rec.header);
+        config.tap(Direction::Write, &begin_request_rec.header);
         begin_request_rec.write_to_stream(stream).await?;
 
         Ok(())
@@ -219,74 +1418,94 @@ impl<S: AsyncRead + AsyncWrite + Unpin, M: Mode> Client<S, M> {
     /// * `stream` - The stream to write to
     /// * `id` - The request ID
     /// * `params` - The request parameters
-    async fn handle_request_params<'a>(
-        stream: &mut S,
+    /// * `raw_params` - The encoded parameters sent instead of `params`
+    /// * `buf` - The scratch buffer records are written from
+    /// * `config` - The client settings, for the record size and flushing
+    async fn handle_request_params<'a, W: AsyncWrite + Unpin>(
+        stream: &mut W,
         id: u16,
         params: Params<'a>,
+        raw_params: Option<Bytes>,
+        buf: &mut Vec<u8>,
+        config: &Config,
     ) -> ClientResult<()> {
-        let param_pairs = ParamPairs::new(params);
-        debug!(id, "Params will be sent {param_pairs:#?}.");
-
-        Header::write_to_stream_batches(
-            RequestType::Params,
-            id,
-            stream,
-            &mut param_pairs.to_content().as_ref(),
-            Some(|header| {
-                debug!(id, ?header, "Send to stream for Params.");
-                header
-            }),
-        )
-        .await?;
+        let Some(raw_params) = raw_params else {
+            debug!(id, "Params will be sent {params:#?}.");
+            return Header::write_params_to_stream(id, stream, params.iter(), buf, config).await;
+        };
 
+        debug!(id, length = raw_params.len(), "Raw params will be sent.");
         Header::write_to_stream_batches(
             RequestType::Params,
             id,
             stream,
-            &mut tokio::io::empty(),
-            Some(|header| {
-                debug!(id, ?header, "Send to stream for Params.");
-                header
-            }),
+            &mut raw_params.as_ref(),
+            buf,
+            0,
+            config,
         )
         .await?;
 
         Ok(())
     }
 
-    /// Handles sending the request body to the stream.
+    /// Handles sending the request body to the stream, returning its length.
     ///
     /// # Arguments
     ///
     /// * `stream` - The stream to write to
     /// * `id` - The request ID
     /// * `body` - The request body stream
-    async fn handle_request_body<I: AsyncRead + Unpin>(
-        stream: &mut S,
+    /// * `buf` - The scratch buffer records are written from
+    /// * `filled` - The number of body bytes already read into the start of
+    ///   `buf`
+    /// * `config` - The client settings, for the record size and flushing
+    async fn handle_request_body<W: AsyncWrite + Unpin, I: AsyncRead + Unpin>(
+        stream: &mut W,
         id: u16,
         body: &mut I,
-    ) -> ClientResult<()> {
-        Header::write_to_stream_batches(
+        buf: &mut Vec<u8>,
+        filled: usize,
+        config: &Config,
+    ) -> ClientResult<usize> {
+        let sent = Header::write_to_stream_batches(
             RequestType::Stdin,
             id,
             stream,
             body,
-            Some(|header| {
-                debug!(id, ?header, "Send to stream for Stdin.");
-                header
-            }),
+            buf,
+            filled,
+            config,
         )
         .await?;
 
+        Ok(sent)
+    }
+
+    /// Handles sending the filter data stream to the stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - The stream to write to
+    /// * `id` - The request ID
+    /// * `data` - The data stream
+    /// * `buf` - The scratch buffer records are written from
+    /// * `config` - The client settings, for the record size and flushing
+    async fn handle_request_data<W: AsyncWrite + Unpin, D: AsyncRead + Unpin>(
+        stream: &mut W,
+        id: u16,
+        data: &mut D,
+        buf: &mut Vec<u8>,
+        config: &Config,
+    ) -> ClientResult<()> {
         Header::write_to_stream_batches(
-            RequestType::Stdin,
+            RequestType::Data,
             id,
             stream,
-            &mut tokio::io::empty(),
-            Some(|header| {
-                debug!(id, ?header, "Send to stream for Stdin.");
-                header
-            }),
+            data,
+            buf,
+            0,
+            config,
         )
         .await?;
 
@@ -298,58 +1517,336 @@ impl<S: AsyncRead + AsyncWrite + Unpin, M: Mode> Client<S, M> {
     /// # Arguments
     ///
     /// * `stream` - The stream to flush
-    async fn handle_request_flush(stream: &mut S) -> ClientResult<()> {
+    async fn handle_request_flush<W: AsyncWrite + Unpin>(stream: &mut W) -> ClientResult<()> {
         stream.flush().await?;
 
         Ok(())
     }
 
+    /// Sends an abort record and drains the stream until the matching
+    /// `EndRequest`, each record within the read timeout and no more than
+    /// [ClientBuilder::max_records] of them.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - The stream to write to and read from
+    /// * `id` - The request ID to abort
+    /// * `config` - The client settings, for the padding and record tap
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    async fn handle_abort<R: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut R,
+        id: u16,
+        config: &Config,
+    ) -> ClientResult<()> {
+        debug!(id, "Abort request.");
+        check_request_id(id)?;
+
+        let mut header = Header::new(RequestType::AbortRequest, id, &[]);
+        config.pad(&mut header);
+        config.tap(Direction::Write, &header);
+        header.write_to_stream(stream, &[]).await?;
+        stream.flush().await?;
+
+        let mut records = 0;
+        loop {
+            let (header, content) = with_read_timeout(config.read_timeout, async {
+                let header = Header::new_from_stream(stream).await?;
+                config.tap(Direction::Read, &header);
+                let content = header.read_content_from_stream(stream, false).await?;
+                Ok((header, content))
+            })
+            .await?;
+            debug!(id, ?header, "Discard from stream.");
+            records += 1;
+            if let Some(limit) = config.max_records {
+                if records > limit {
+                    return Err(ClientError::TooManyRecords { limit });
+                }
+            }
+
+            if header.request_id == id && matches!(header.r#type, RequestType::EndRequest) {
+                debug!(
+                    id,
+                    end_request_rec = ?EndRequestRec::new_from_buf(header, content),
+                    "Receive from stream."
+                );
+                return Ok(());
+            }
+        }
+    }
+
+    /// Discards records that have already arrived, up to 16 of them, without
+    /// waiting for more.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - The stream to read from
+    /// * `buf` - The scratch buffer record contents are read into
+    /// * `config` - The client settings, for the read timeout and record tap
+    /// * `reject` - Whether a pending record is an error instead, in strict
+    ///   mode
+    async fn discard_pending<R: AsyncRead + Unpin>(
+        stream: &mut R,
+        buf: &mut BytesMut,
+        config: &Config,
+        reject: bool,
+    ) -> ClientResult<()> {
+        for _ in 0..MAX_DRAINED_RECORDS {
+            let mut header_buf = [0u8; HEADER_LEN];
+            let filled = match read_ready(stream, &mut header_buf)? {
+                Some(filled) if filled > 0 => filled,
+                _ => break,
+            };
+            with_read_timeout(config.read_timeout, async {
+                stream.read_exact(&mut header_buf[filled..]).await?;
+                let header = Header::try_from(&header_buf[..])?;
+                config.tap(Direction::Read, &header);
+                if reject {
+                    return Err(ClientError::UnexpectedRecord {
+                        request_type: header.r#type,
+                        request_id: header.request_id,
+                    });
+                }
+                debug!(?header, "Discard pending record.");
+                header.read_content_into(stream, buf, false).await
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
     /// Handles reading and processing the response from the stream.
     ///
+    /// If the connection closes before the `EndRequest`, the stdout received
+    /// so far is attached to the error.
+    ///
     /// # Arguments
     ///
     /// * `stream` - The stream to read from
-    /// * `id` - The request ID to match
-    async fn handle_response(stream: &mut S, id: u16) -> ClientResult<Response> {
-        let mut response = Response::default();
+    /// * `in_flight` - The request the response is read for
+    /// * `config` - The client settings, for the response limits and
+    ///   read timeout
+    /// * `buf` - The scratch buffer record contents are read into
+    /// * `response_started` - Set once stdout is received
+    /// * `skipped` - The count of records of other requests skipped
+    async fn handle_response<R: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut R,
+        in_flight: InFlight<'_>,
+        config: &Config,
+        buf: &mut BytesMut,
+        response_started: &mut bool,
+        skipped: &mut u64,
+    ) -> ClientResult<Response> {
+        let mut stdout = match in_flight.expected_size {
+            Some(size) => BytesMut::with_capacity(
+                [config.max_response_bytes, in_flight.max_stdout_bytes]
+                    .into_iter()
+                    .flatten()
+                    .fold(size, min),
+            ),
+            None => BytesMut::new(),
+        };
+        Self::read_response(stream, in_flight, config, buf, &mut stdout, response_started, skipped)
+            .await
+            .map_err(|err| {
+                if stdout.is_empty() {
+                    err
+                } else {
+                    err.with_stdout(stdout.freeze())
+                }
+            })
+    }
 
+    /// Reads the records of the response until the `EndRequest`.
+    ///
+    /// Records already received after the `EndRequest` are discarded, as are
+    /// records of the previous request arriving before the response, and of
+    /// any other request if [ClientBuilder::skip_unknown_ids] is set.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - The stream to read from
+    /// * `in_flight` - The request the response is read for
+    /// * `config` - The client settings, for the response limits and
+    ///   read timeout
+    /// * `buf` - The scratch buffer record contents are read into
+    /// * `stdout` - The buffer stdout is collected into
+    /// * `response_started` - Set once stdout is received
+    /// * `skipped` - The count of records of other requests skipped
+    async fn read_response<R: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut R,
+        in_flight: InFlight<'_>,
+        config: &Config,
+        buf: &mut BytesMut,
+        stdout: &mut BytesMut,
+        response_started: &mut bool,
+        skipped: &mut u64,
+    ) -> ClientResult<Response> {
+        let InFlight {
+            id,
+            previous_id,
+            role,
+            cancel,
+            mut stderr_handler,
+            max_stdout_bytes,
+            max_stderr_bytes,
+            mut sink,
+            ..
+        } = in_flight;
+        let mut response = Response::default();
         let mut stderr = BytesMut::new();
-        let mut stdout = BytesMut::new();
+        let mut stderr_len = 0;
+        let mut records = 0;
 
         loop {
-            let header = Header::new_from_stream(stream).await?;
+            let header = match cancel {
+                Some(token) => {
+                    let header = with_read_timeout(
+                        config.read_timeout,
+                        Header::new_from_stream_or_cancel(stream, token),
+                    )
+                    .await?;
+                    match header {
+                        Some(header) => header,
+                        None => {
+                            Self::handle_abort(stream, id, config).await?;
+                            return Err(ClientError::Cancelled { id });
+                        }
+                    }
+                }
+                None => {
+                    with_read_timeout(config.read_timeout, Header::new_from_stream(stream)).await?
+                }
+            };
+            config.tap(Direction::Read, &header);
+            records += 1;
+            if let Some(limit) = config.max_records {
+                if records > limit {
+                    return Err(ClientError::TooManyRecords { limit });
+                }
+            }
+            if let RequestType::UnknownType = header.r#type {
+                let unknown_type_rec = with_read_timeout(
+                    config.read_timeout,
+                    UnknownTypeRec::from_header(&header, stream, config.strict_padding),
+                )
+                .await?;
+                debug!(id, ?unknown_type_rec, "Receive from stream.");
+                return Err(ClientError::UnknownType {
+                    type_id: unknown_type_rec.unknown_type.r#type,
+                });
+            }
             if header.request_id != id {
+                if config.strict {
+                    return Err(ClientError::UnexpectedRecord {
+                        request_type: header.r#type,
+                        request_id: header.request_id,
+                    });
+                }
+                let previous = header.request_id == previous_id && previous_id != 0;
+                if previous || config.skip_unknown_ids {
+                    warning!(id, ?header, "Discard record of another request.");
+                    with_read_timeout(
+                        config.read_timeout,
+                        header.read_content_into(stream, buf, config.strict_padding),
+                    )
+                    .await?;
+                    *skipped += 1;
+                    continue;
+                }
                 return Err(ClientError::ResponseNotFound { id });
             }
-            debug!(id, ?header, "Receive from stream.");
 
             match header.r#type {
                 RequestType::Stdout => {
-                    stdout.extend_from_slice(&header.read_content_from_stream(stream).await?);
+                    *response_started = true;
+                    let stdout_len = match &sink {
+                        Some(sink) => *sink.written as usize,
+                        None => stdout.len(),
+                    };
+                    if let Some(limit) = max_stdout_bytes {
+                        if stdout_len + header.content_length as usize > limit {
+                            return Err(output_too_large(header.r#type, limit, stdout, stderr));
+                        }
+                    }
+                    if let Some(limit) = config.max_response_bytes {
+                        let buffered = sink.is_none();
+                        if buffered && stdout_len + header.content_length as usize > limit {
+                            return Err(ClientError::ResponseTooLarge { limit });
+                        }
+                    }
+                    let content = with_read_timeout(
+                        config.read_timeout,
+                        header.read_content_into(stream, buf, config.strict_padding),
+                    )
+                    .await?;
+                    match &mut sink {
+                        Some(sink) => {
+                            sink.writer.write_all(&content).await?;
+                            *sink.written += content.len() as u64;
+                        }
+                        None => stdout.extend_from_slice(&content),
+                    }
                 }
                 RequestType::Stderr => {
-                    stderr.extend_from_slice(&header.read_content_from_stream(stream).await?);
+                    stderr_len += header.content_length as usize;
+                    if let Some(limit) = max_stderr_bytes {
+                        if stderr_len > limit {
+                            return Err(output_too_large(header.r#type, limit, stdout, stderr));
+                        }
+                    }
+                    if let Some(limit) = config.max_stderr_bytes {
+                        let buffered = stderr_handler.is_none();
+                        if buffered && stderr.len() + header.content_length as usize > limit {
+                            return Err(ClientError::ResponseTooLarge { limit });
+                        }
+                    }
+                    let content = with_read_timeout(
+                        config.read_timeout,
+                        header.read_content_into(stream, buf, config.strict_padding),
+                    )
+                    .await?;
+                    match &mut stderr_handler {
+                        Some(handler) if !content.is_empty() => handler(content.freeze()),
+                        Some(_) => {}
+                        None => stderr.extend_from_slice(&content),
+                    }
                 }
                 RequestType::EndRequest => {
-                    let end_request_rec = EndRequestRec::from_header(&header, stream).await?;
+                    let end_request_rec = with_read_timeout(
+                        config.read_timeout,
+                        EndRequestRec::from_header(&header, stream, config.strict_padding),
+                    )
+                    .await?;
                     debug!(id, ?end_request_rec, "Receive from stream.");
+                    Self::discard_pending(stream, buf, config, config.strict).await?;
+                    if let Some(sink) = &mut sink {
+                        sink.writer.flush().await?;
+                    }
 
-                    end_request_rec
-                        .end_request
-                        .protocol_status
-                        .convert_to_client_result(end_request_rec.end_request.app_status)?;
-
-                    response.stdout = if stdout.is_empty() {
+                    let stdout = if stdout.is_empty() {
                         None
                     } else {
-                        Some(stdout.freeze())
+                        Some(stdout.split().freeze())
                     };
-                    response.stderr = if stderr.is_empty() {
+                    let stderr = if stderr.is_empty() {
                         None
                     } else {
                         Some(stderr.freeze())
                     };
 
+                    end_request_rec.end_request.protocol_status.convert_to_client_result(
+                        role,
+                        end_request_rec.end_request.app_status,
+                        stdout.clone(),
+                        stderr.clone(),
+                    )?;
+
+                    response.request_id = id;
+                    response.app_status = end_request_rec.end_request.app_status;
+                    response.stdout = stdout;
+                    response.stderr = stderr;
+
                     return Ok(response);
                 }
                 r#type => {
@@ -362,6 +1859,259 @@ impl<S: AsyncRead + AsyncWrite + Unpin, M: Mode> Client<S, M> {
     }
 }
 
+/// Creates the error of a response exceeding a limit of its request, taking
+/// the output received so far.
+///
+/// # Arguments
+///
+/// * `request_type` - The type of the record over the limit
+/// * `limit` - The limit of the request
+/// * `stdout` - The stdout received so far
+/// * `stderr` - The stderr received so far
+fn output_too_large(
+    request_type: RequestType,
+    limit: usize,
+    stdout: &mut BytesMut,
+    stderr: BytesMut,
+) -> ClientError {
+    let non_empty = |bytes: BytesMut| (!bytes.is_empty()).then(|| bytes.freeze());
+    ClientError::OutputTooLarge {
+        request_type,
+        limit,
+        stdout: non_empty(stdout.split()),
+        stderr: non_empty(stderr),
+    }
+}
+
+/// Stream of a request being sent, which reads the response arriving
+/// meanwhile into a buffer, so a server that answers before it has read the
+/// whole body doesn't block on a client still writing it.
+///
+/// Once the `EndRequest` of the request has been read ahead, writes fail and
+/// [ReadAhead::ended] is set, to stop sending the body. Reads return the
+/// bytes read ahead before reading the stream.
+struct ReadAhead<'a, S> {
+    stream: &'a mut S,
+    buf: &'a mut BytesMut,
+    /// The request whose `EndRequest` ends the writes.
+    id: u16,
+    /// How much of `buf` was checked for the `EndRequest`, up to the end of
+    /// the last whole record.
+    scanned: usize,
+    /// Whether the stream ended while reading ahead.
+    eof: bool,
+    /// Whether the writes end, as the `EndRequest` of the request or a
+    /// record over the limits was read ahead.
+    ended: bool,
+    /// The limits checked on the records read ahead.
+    limits: AheadLimits,
+    /// The count of records read ahead.
+    records: usize,
+    /// The length of stdout of the request read ahead.
+    stdout_len: usize,
+    /// The length of stderr of the request read ahead.
+    stderr_len: usize,
+}
+
+/// The response limits checked while reading ahead, so that a response
+/// outgrowing them ends the request early rather than piling up in the
+/// read-ahead buffer.
+#[derive(Clone, Copy, Default)]
+struct AheadLimits {
+    /// See [ClientBuilder::max_records]
+    records: Option<usize>,
+    /// The lowest limit of stdout that is buffered
+    stdout: Option<usize>,
+    /// The lowest limit of stderr that is buffered
+    stderr: Option<usize>,
+}
+
+impl<'a, S: AsyncRead + AsyncWrite + Unpin> ReadAhead<'a, S> {
+    /// Creates a stream reading ahead into `buf`.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - The stream of the connection
+    /// * `buf` - The buffer to read ahead into
+    /// * `id` - The request ID
+    fn new(stream: &'a mut S, buf: &'a mut BytesMut, id: u16) -> Self {
+        Self {
+            stream,
+            buf,
+            id,
+            scanned: 0,
+            eof: false,
+            ended: false,
+            limits: AheadLimits::default(),
+            records: 0,
+            stdout_len: 0,
+            stderr_len: 0,
+        }
+    }
+
+    /// Sets the limits checked on the records read ahead.
+    ///
+    /// # Arguments
+    ///
+    /// * `limits` - The limits of the response
+    fn limits(mut self, limits: AheadLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Reads what has arrived without waiting for more, then checks whether
+    /// it completes the `EndRequest` of the request.
+    ///
+    /// # Arguments
+    ///
+    /// * `cx` - The context of the write
+    fn poll_ahead(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        while !self.eof && !self.ended {
+            self.buf.reserve(READ_AHEAD_CAPACITY);
+            match poll_read_buf(Pin::new(&mut *self.stream), cx, self.buf) {
+                Poll::Ready(Ok(0)) => self.eof = true,
+                Poll::Ready(Ok(_)) => self.scan(),
+                Poll::Ready(Err(err)) => return Err(err),
+                Poll::Pending => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Walks the whole records read ahead, looking for the `EndRequest` of
+    /// the request or a record over the limits, which is reported once the
+    /// response is read.
+    fn scan(&mut self) {
+        while let Some(header) = self.buf.get(self.scanned..self.scanned + HEADER_LEN) {
+            // A malformed header is reported when the response is read.
+            let Ok(header) = Header::try_from(header) else {
+                return;
+            };
+            let end = self.scanned
+                + HEADER_LEN
+                + header.content_length as usize
+                + header.padding_length as usize;
+            if self.buf.len() < end {
+                return;
+            }
+            self.scanned = end;
+            self.records += 1;
+            let length = header.content_length as usize;
+            let over = |len: &mut usize, limit: Option<usize>| {
+                *len += length;
+                limit.is_some_and(|limit| *len > limit)
+            };
+            self.ended = self.limits.records.is_some_and(|limit| self.records > limit)
+                || header.request_id == self.id
+                    && match header.r#type {
+                        RequestType::EndRequest => true,
+                        RequestType::Stdout => over(&mut self.stdout_len, self.limits.stdout),
+                        RequestType::Stderr => over(&mut self.stderr_len, self.limits.stderr),
+                        _ => false,
+                    };
+            if self.ended {
+                return;
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for ReadAhead<'_, S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.poll_ahead(cx)?;
+        if this.ended {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        match Pin::new(&mut *this.stream).poll_write(cx, buf) {
+            Poll::Ready(Err(err)) => {
+                // The server may have answered and closed the connection.
+                this.poll_ahead(cx)?;
+                Poll::Ready(Err(err))
+            }
+            poll => poll,
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ReadAhead<'_, S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.buf.is_empty() {
+            return Pin::new(&mut *this.stream).poll_read(cx, buf);
+        }
+        let len = min(this.buf.len(), buf.remaining());
+        buf.put_slice(&this.buf[..len]);
+        this.buf.advance(len);
+        this.scanned = this.scanned.saturating_sub(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Picks the lower of the limit of a request and the one of the client, the
+/// latter applying only to output that is buffered.
+///
+/// # Arguments
+///
+/// * `request` - The limit of the request
+/// * `client` - The limit of the client
+/// * `buffered` - Whether the output is buffered
+fn lowest(request: Option<usize>, client: Option<usize>, buffered: bool) -> Option<usize> {
+    match (request, client.filter(|_| buffered)) {
+        (Some(request), Some(client)) => Some(min(request, client)),
+        (request, client) => request.or(client),
+    }
+}
+
+/// Builds the path of a socket of the abstract namespace, which is its name
+/// after a NUL byte.
+///
+/// # Arguments
+///
+/// * `name` - The name of the socket
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn abstract_path(name: &[u8]) -> PathBuf {
+    let mut path = Vec::with_capacity(name.len() + 1);
+    path.push(0);
+    path.extend_from_slice(name);
+    PathBuf::from(OsString::from_vec(path))
+}
+
+/// Awaits a read from the server, failing with [ClientError::ReadTimeout] if
+/// it takes longer than the timeout.
+///
+/// # Arguments
+///
+/// * `timeout` - The read timeout, `None` to wait forever
+/// * `read` - The read to await
+async fn with_read_timeout<T>(
+    timeout: Option<Duration>,
+    read: impl Future<Output = ClientResult<T>>,
+) -> ClientResult<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, read)
+            .await
+            .map_err(|_| ClientError::ReadTimeout { timeout })?,
+        None => read.await,
+    }
+}
+
 /// Добавляю реализацию deref в stream потому что Client ведет себя по большей части просто как stream.
 impl<S, M> Deref for Client<S, M> {
     type Target = S;
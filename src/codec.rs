@@ -0,0 +1,133 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [tokio_util::codec] support for FastCGI records, enabled by the `codec`
+//! feature.
+//!
+//! `FastCgiCodec` frames a stream into `Record`s with `Framed`, using the
+//! same framing as the client, for proxies, sniffers or test servers.
+
+use crate::{
+    meta::{decode_record, Header, RequestType, HEADER_LEN, MAX_LENGTH},
+    ClientError,
+};
+use bytes::{BufMut, Bytes, BytesMut};
+use std::io;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// A FastCGI record, without its padding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// The type of the record
+    pub request_type: RequestType,
+    /// The request ID, 0 for management records
+    pub request_id: u16,
+    /// The content of the record, at most 65535 bytes
+    pub content: Bytes,
+}
+
+impl Record {
+    /// Creates a record.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of the record
+    /// * `request_id` - The request ID, 0 for management records
+    /// * `content` - The content of the record
+    pub fn new(request_type: RequestType, request_id: u16, content: impl Into<Bytes>) -> Self {
+        Self {
+            request_type,
+            request_id,
+            content: content.into(),
+        }
+    }
+}
+
+/// Encodes and decodes FastCGI records.
+///
+/// Records are encoded with the padding aligning them to 8 bytes, as the
+/// client writes them. Decoding fails like the client on a header with an
+/// unsupported version or an unknown record type, which usually means the
+/// peer isn't speaking FastCGI.
+///
+/// # Examples
+///
+/// ```
+/// use fcgi_client::codec::FastCgiCodec;
+/// use futures_util::StreamExt;
+/// use tokio::net::TcpStream;
+/// use tokio_util::codec::FramedRead;
+///
+/// async fn sniff(stream: TcpStream) {
+///     let mut records = FramedRead::new(stream, FastCgiCodec::new());
+///     while let Some(record) = records.next().await {
+///         println!("{:?}", record.unwrap());
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FastCgiCodec {
+    strict_padding: bool,
+    header: Option<Header>,
+}
+
+impl FastCgiCodec {
+    /// Creates a codec accepting any padding bytes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether decoding rejects records with non-zero padding bytes
+    /// with [ClientError::InvalidPadding], like
+    /// [crate::ClientBuilder::strict_padding].
+    pub fn strict_padding(mut self, strict_padding: bool) -> Self {
+        self.strict_padding = strict_padding;
+        self
+    }
+}
+
+impl Decoder for FastCgiCodec {
+    type Error = ClientError;
+    type Item = Record;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Record>, ClientError> {
+        let record = decode_record(src, &mut self.header, self.strict_padding)?;
+        Ok(record.map(|(header, content)| Record {
+            request_type: header.request_type(),
+            request_id: header.request_id(),
+            content: content.freeze(),
+        }))
+    }
+}
+
+impl Encoder<Record> for FastCgiCodec {
+    type Error = ClientError;
+
+    fn encode(&mut self, record: Record, dst: &mut BytesMut) -> Result<(), ClientError> {
+        if record.content.len() > MAX_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "record content exceeds 65535 bytes",
+            )
+            .into());
+        }
+        let header = Header::new(record.request_type, record.request_id, &record.content);
+        let padding_length = header.padding_length() as usize;
+        dst.reserve(HEADER_LEN + record.content.len() + padding_length);
+        dst.put(Bytes::from(&header));
+        dst.put(record.content);
+        dst.put_bytes(0, padding_length);
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [deadpool](https://docs.rs/deadpool) support, enabled by the `deadpool`
+//! feature.
+//!
+//! `Manager` opens keep alive clients for a `deadpool::managed::Pool`, for
+//! services already pooling their other backends with deadpool. A client is
+//! checked with `Client::is_healthy` before it is reused, and pinged first if
+//! it has been unused for too long.
+
+use crate::{conn::KeepAlive, pool::Connect, trace::debug, Client, ClientError, SocketOptions};
+use deadpool::managed::{self, Metrics, RecycleError, RecycleResult};
+use std::{future::Future, io, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpStream, ToSocketAddrs},
+};
+
+/// Deadpool pool of keep alive clients.
+///
+/// # Examples
+///
+/// ```
+/// use fcgi_client::{
+///     deadpool::{Manager, Pool},
+///     Params, Request,
+/// };
+/// use tokio::{io, net::TcpStream};
+///
+/// async fn pooled() {
+///     let manager = Manager::new(|| TcpStream::connect(("127.0.0.1", 9000)));
+///     let pool = Pool::builder(manager).max_size(32).build().unwrap();
+///
+///     let mut client = pool.get().await.unwrap();
+///     let output = client
+///         .execute(Request::new(Params::default(), &mut io::empty()))
+///         .await
+///         .unwrap();
+/// }
+/// ```
+pub type Pool<S> = managed::Pool<Manager<S>>;
+
+/// Manager of the clients of a deadpool [Pool].
+pub struct Manager<S> {
+    connect: Connect<S>,
+    probe_after: Option<Duration>,
+}
+
+impl Manager<TcpStream> {
+    /// Creates a manager opening TCP connections with the socket options, see
+    /// [SocketOptions::connect].
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address of the server, such as `("php-fpm", 9000)`
+    /// * `timeout` - How long to wait for each address to connect
+    /// * `socket_options` - The options of the sockets
+    pub fn connect_tcp<A>(addr: A, timeout: Duration, socket_options: SocketOptions) -> Self
+    where
+        A: ToSocketAddrs + Clone + Send + Sync + 'static,
+    {
+        Self::new(move || {
+            let (addr, socket_options) = (addr.clone(), socket_options.clone());
+            async move {
+                socket_options
+                    .connect(addr, timeout)
+                    .await
+                    .map_err(io::Error::other)
+            }
+        })
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> Manager<S> {
+    /// Creates a manager opening connections with `connect`, such as
+    /// `|| TcpStream::connect(("127.0.0.1", 9000))`.
+    ///
+    /// # Arguments
+    ///
+    /// * `connect` - The function opening a new connection
+    pub fn new<F, Fut>(connect: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = io::Result<S>> + Send + 'static,
+    {
+        Self {
+            connect: Box::new(move || Box::pin(connect())),
+            probe_after: None,
+        }
+    }
+
+    /// Set how long a client may go unused before it is
+    /// [pinged](Client::ping) when recycled, `None` means never (the
+    /// default), see [crate::Pool::probe_after].
+    pub fn probe_after(mut self, probe_after: Option<Duration>) -> Self {
+        self.probe_after = probe_after;
+        self
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> managed::Manager for Manager<S> {
+    type Error = ClientError;
+    type Type = Client<S, KeepAlive>;
+
+    async fn create(&self) -> Result<Self::Type, Self::Error> {
+        debug!("Open a new pooled connection.");
+        Ok(Client::new_keep_alive((self.connect)().await?))
+    }
+
+    async fn recycle(&self, client: &mut Self::Type, _: &Metrics) -> RecycleResult<Self::Error> {
+        if !client.is_healthy() {
+            return Err(RecycleError::message("connection isn't healthy"));
+        }
+        if let Some(probe_after) = self.probe_after {
+            if client.last_used().elapsed() >= probe_after {
+                client.ping().await?;
+            }
+        }
+        Ok(())
+    }
+}
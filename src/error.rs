@@ -17,7 +17,9 @@
 //! This module defines the error types that can occur during FastCGI
 //! communication and provides convenient type aliases for results.
 
-use crate::meta::{ProtocolStatus, RequestType};
+use crate::meta::{ProtocolStatus, RequestType, Role};
+use bytes::Bytes;
+use std::{net::SocketAddr, time::Duration};
 
 /// Result type alias for FastCGI client operations.
 pub type ClientResult<T> = Result<T, ClientError>;
@@ -29,6 +31,40 @@ pub enum ClientError {
     #[error(transparent)]
     Io(#[from] tokio::io::Error),
 
+    /// Connecting to the server over TCP failed for every address of the
+    /// host, or the host couldn't be resolved.
+    #[error("Failed to connect{}: {source}", connect_target(.addr))]
+    Connect {
+        /// The last address tried, `None` if the host didn't resolve to any
+        addr: Option<SocketAddr>,
+        /// The error of the last attempt or of the lookup
+        source: tokio::io::Error,
+    },
+
+    /// The TLS handshake with the server failed or timed out, with the
+    /// `rustls` or `native-tls` feature.
+    #[error("TLS handshake failed: {source}")]
+    TlsHandshake {
+        /// The error of the handshake
+        source: tokio::io::Error,
+    },
+
+    /// The SOCKS5 proxy refused to open the tunnel to the server, with the
+    /// `socks5` feature.
+    #[error("SOCKS5 proxy failed: {reason}")]
+    Socks5 {
+        /// What went wrong
+        reason: &'static str,
+    },
+
+    /// The host to connect to over TLS isn't a valid DNS name or IP address
+    /// to verify the certificate against, with the `rustls` feature.
+    #[error("Invalid TLS server name `{name}`")]
+    InvalidServerName {
+        /// The rejected host
+        name: String,
+    },
+
     /// Usually not happen.
     #[error("Response not found of request id `{id}`")]
     RequestIdNotFound {
@@ -43,6 +79,14 @@ pub enum ClientError {
         id: u16,
     },
 
+    /// An application request used the request id 0, which is reserved for
+    /// management records.
+    #[error("Request id `{id}` is reserved for management records")]
+    InvalidRequestId {
+        /// The rejected request ID
+        id: u16,
+    },
+
     /// Maybe unimplemented request type received fom response.
     #[error("Response not found of request id `{request_type}`")]
     UnknownRequestType {
@@ -50,28 +94,191 @@ pub enum ClientError {
         request_type: RequestType,
     },
 
+    /// The connection was closed in the middle of a record, for example
+    /// because the FastCGI worker crashed.
+    #[error(
+        "Connection closed while reading {}: received {received} of {expected} bytes",
+        record_name(.request_type)
+    )]
+    ConnectionClosed {
+        /// The number of bytes needed to complete the header or record
+        expected: usize,
+        /// The number of bytes received before the connection was closed
+        received: usize,
+        /// The type of the record being read, `None` while reading a header
+        request_type: Option<RequestType>,
+        /// The stdout received before the connection was closed, `None` if
+        /// there was none or the response was streamed
+        stdout: Option<Bytes>,
+    },
+
+    /// The application replied with `FCGI_UNKNOWN_TYPE` because it doesn't
+    /// understand a management record that was sent.
+    #[error("The application doesn't understand record type `{type_id}`")]
+    UnknownType {
+        /// The type of the record that wasn't understood
+        type_id: u8,
+    },
+
+    /// The peer sent a record with a protocol version other than 1, usually
+    /// because it isn't a FastCGI server.
+    #[error("Unsupported FastCGI protocol version `{version}`")]
+    UnsupportedVersion {
+        /// The version byte received
+        version: u8,
+    },
+
+    /// The peer sent a record type byte that isn't defined by the protocol,
+    /// usually a sign of a corrupted or misaligned stream.
+    #[error("Invalid record type `{request_type}`")]
+    InvalidRequestType {
+        /// The raw record type byte received
+        request_type: u8,
+    },
+
+    /// The CGI response in stdout has a header line that can't be parsed.
+    #[error("Invalid CGI response header `{line}`")]
+    InvalidCgiHeader {
+        /// The offending header line
+        line: String,
+    },
+
+    /// A parameter name or value is too long for the four byte length of
+    /// the name-value pair encoding.
+    #[error("Parameter of {length} bytes exceeds the maximum length")]
+    ParamTooLong {
+        /// The length of the name or value in bytes
+        length: usize,
+    },
+
+    /// The response stdout or stderr grew beyond the configured maximum
+    /// size, see [crate::ClientBuilder::max_response_bytes] and
+    /// [crate::ClientBuilder::max_stderr_bytes].
+    #[error("Response exceeded the limit of {limit} bytes")]
+    ResponseTooLarge {
+        /// The configured limit in bytes
+        limit: usize,
+    },
+
+    /// The response stdout or stderr grew beyond the maximum size set for
+    /// the request, see [crate::Request::with_max_stdout_bytes] and
+    /// [crate::Request::with_max_stderr_bytes].
+    #[error("Response {request_type:?} exceeded the request limit of {limit} bytes")]
+    OutputTooLarge {
+        /// The type of the records over the limit, `Stdout` or `Stderr`
+        request_type: RequestType,
+        /// The limit set for the request in bytes
+        limit: usize,
+        /// The stdout received before the limit was exceeded, `None` if
+        /// there was none
+        stdout: Option<Bytes>,
+        /// The stderr received before the limit was exceeded, `None` if
+        /// there was none or it was passed to a stderr handler
+        stderr: Option<Bytes>,
+    },
+
+    /// The response had more records than the configured maximum, see
+    /// [crate::ClientBuilder::max_records].
+    #[error("Response exceeded the limit of {limit} records")]
+    TooManyRecords {
+        /// The configured limit in records
+        limit: usize,
+    },
+
+    /// A record had non-zero padding bytes while strict padding is enabled,
+    /// usually a sign of a misaligned stream.
+    #[error("Non-zero padding in {} record", .request_type)]
+    InvalidPadding {
+        /// The type of the record with invalid padding
+        request_type: RequestType,
+    },
+
+    /// A record arrived where strict validation doesn't allow one: a record
+    /// of another request, or one already waiting after the `EndRequest`,
+    /// see [crate::ClientBuilder::strict].
+    #[error("Unexpected {request_type} record for request {request_id}")]
+    UnexpectedRecord {
+        /// The type of the record
+        request_type: RequestType,
+        /// The request ID of the record
+        request_id: u16,
+    },
+
+    /// Name-value pairs from the server ended in the middle of a pair, see
+    /// [crate::ClientBuilder::strict].
+    #[error("Name-value pairs truncated with {remaining} bytes left")]
+    TruncatedParams {
+        /// The length of the incomplete pair
+        remaining: usize,
+    },
+
+    /// A record header was decoded from fewer than the 8 bytes it takes.
+    #[error("Record header truncated to {length} bytes")]
+    TruncatedHeader {
+        /// The number of bytes available
+        length: usize,
+    },
+
+    /// No data arrived from the server within the configured read timeout.
+    #[error("Timed out after {timeout:?} waiting for the response")]
+    ReadTimeout {
+        /// The configured read timeout
+        timeout: Duration,
+    },
+
+    /// The request was cancelled with its token, see
+    /// [crate::Client::execute_with_cancel].
+    #[error("Request {id} was cancelled")]
+    Cancelled {
+        /// The request ID of the cancelled request, 0 if it was cancelled
+        /// before being sent
+        id: u16,
+    },
+
+    /// A [crate::BalancedClient] has no backend to send the request to,
+    /// because none was added or all have a weight of 0 or are unhealthy.
+    #[error("No backend available")]
+    NoBackend,
+
     /// Response not complete, first is protocol status and second is app
     /// status, see fastcgi protocol.
-    #[error("This app can't multiplex [CantMpxConn]; AppStatus: {app_status}")]
+    #[error("This app can't multiplex [CantMpxConn]; AppStatus: {app_status}{}", stderr_suffix(.stderr))]
     EndRequestCantMpxConn {
         /// The application status code
         app_status: u32,
+        /// The stdout received before the request ended, `None` if there
+        /// was none or the response was streamed
+        stdout: Option<Bytes>,
+        /// The stderr output received before the request ended
+        stderr: Option<Bytes>,
     },
 
     /// Response not complete, first is protocol status and second is app
     /// status, see fastcgi protocol.
-    #[error("New request rejected; too busy [OVERLOADED]; AppStatus: {app_status}")]
+    #[error("New request rejected; too busy [OVERLOADED]; AppStatus: {app_status}{}", stderr_suffix(.stderr))]
     EndRequestOverloaded {
         /// The application status code
         app_status: u32,
+        /// The stdout received before the request ended, `None` if there
+        /// was none or the response was streamed
+        stdout: Option<Bytes>,
+        /// The stderr output received before the request ended
+        stderr: Option<Bytes>,
     },
 
     /// Response not complete, first is protocol status and second is app
     /// status, see fastcgi protocol.
-    #[error("Role value not known [UnknownRole]; AppStatus: {app_status}")]
+    #[error("Role value not known [UnknownRole]; Role: {role:?}; AppStatus: {app_status}{}", stderr_suffix(.stderr))]
     EndRequestUnknownRole {
+        /// The role of the rejected request
+        role: Role,
         /// The application status code
         app_status: u32,
+        /// The stdout received before the request ended, `None` if there
+        /// was none or the response was streamed
+        stdout: Option<Bytes>,
+        /// The stderr output received before the request ended
+        stderr: Option<Bytes>,
     },
 }
 
@@ -81,14 +288,97 @@ impl ClientError {
     /// # Arguments
     ///
     /// * `protocol_status` - The protocol status returned by the FastCGI server
+    /// * `role` - The role of the request
     /// * `app_status` - The application status code
+    /// * `stdout` - The stdout output received before the request ended
+    /// * `stderr` - The stderr output received before the request ended
     pub(crate) fn new_end_request_with_protocol_status(
-        protocol_status: ProtocolStatus, app_status: u32,
+        protocol_status: ProtocolStatus, role: Role, app_status: u32, stdout: Option<Bytes>,
+        stderr: Option<Bytes>,
     ) -> Self {
         match protocol_status {
-            ProtocolStatus::CantMpxConn => ClientError::EndRequestCantMpxConn { app_status },
-            ProtocolStatus::Overloaded => ClientError::EndRequestOverloaded { app_status },
-            _ => ClientError::EndRequestUnknownRole { app_status },
+            ProtocolStatus::CantMpxConn => ClientError::EndRequestCantMpxConn {
+                app_status,
+                stdout,
+                stderr,
+            },
+            ProtocolStatus::Overloaded => ClientError::EndRequestOverloaded {
+                app_status,
+                stdout,
+                stderr,
+            },
+            _ => ClientError::EndRequestUnknownRole {
+                role,
+                app_status,
+                stdout,
+                stderr,
+            },
         }
     }
+
+    /// Attaches the stdout received so far to a connection closed error,
+    /// other errors are returned unchanged.
+    ///
+    /// # Arguments
+    ///
+    /// * `partial` - The stdout received before the error
+    pub(crate) fn with_stdout(mut self, partial: Bytes) -> Self {
+        if let ClientError::ConnectionClosed { stdout, .. } = &mut self {
+            *stdout = Some(partial);
+        }
+        self
+    }
+
+    /// Returns whether the connection is still in sync after a request
+    /// failed with this error: the server ended the request with an error
+    /// status, or the request was cancelled and its response discarded.
+    pub(crate) fn keeps_connection(&self) -> bool {
+        matches!(
+            self,
+            ClientError::Cancelled { .. }
+                | ClientError::EndRequestCantMpxConn { .. }
+                | ClientError::EndRequestOverloaded { .. }
+                | ClientError::EndRequestUnknownRole { .. }
+        )
+    }
+
+    /// Returns the stdout received before the request failed, if the
+    /// error carries any.
+    ///
+    /// Only buffered requests collect it, for streamed responses the
+    /// output was already yielded by the stream.
+    pub fn partial_stdout(&self) -> Option<&Bytes> {
+        match self {
+            ClientError::ConnectionClosed { stdout, .. }
+            | ClientError::EndRequestCantMpxConn { stdout, .. }
+            | ClientError::EndRequestOverloaded { stdout, .. }
+            | ClientError::EndRequestUnknownRole { stdout, .. }
+            | ClientError::OutputTooLarge { stdout, .. } => stdout.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// Describes the address of connect errors.
+fn connect_target(addr: &Option<SocketAddr>) -> String {
+    match addr {
+        Some(addr) => format!(" to {addr}"),
+        None => String::new(),
+    }
+}
+
+/// Describes the part of the stream being read for connection closed errors.
+fn record_name(request_type: &Option<RequestType>) -> String {
+    match request_type {
+        Some(request_type) => format!("{request_type:?} record"),
+        None => "record header".to_owned(),
+    }
+}
+
+/// Formats the captured stderr for the end request error messages.
+fn stderr_suffix(stderr: &Option<Bytes>) -> String {
+    match stderr {
+        Some(stderr) => format!("; Stderr: {}", String::from_utf8_lossy(stderr)),
+        None => String::new(),
+    }
 }
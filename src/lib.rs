@@ -12,12 +12,39 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod balance;
+#[cfg(feature = "bb8")]
+pub mod bb8;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod capabilities;
 pub mod client;
+#[cfg(feature = "codec")]
+pub mod codec;
 pub mod conn;
+#[cfg(feature = "deadpool")]
+pub mod deadpool;
 mod error;
 pub mod meta;
 pub mod params;
+pub mod pool;
 pub mod request;
 pub mod response;
+pub mod retry;
+pub mod socket;
+mod trace;
+#[cfg(any(feature = "rustls", feature = "native-tls", feature = "socks5"))]
+pub mod transport;
 
-pub use crate::{client::Client, error::*, params::Params, request::Request, response::Response};
+pub use crate::{
+    balance::BalancedClient,
+    capabilities::{Capabilities, ServerValues},
+    client::{Client, ClientBuilder},
+    error::*,
+    params::Params,
+    pool::Pool,
+    request::Request,
+    response::Response,
+    retry::RetryPolicy,
+    socket::SocketOptions,
+};
@@ -18,29 +18,47 @@
 //! for parsing and generating FastCGI protocol messages.
 
 use crate::{
+    client::Config,
     error::{ClientError, ClientResult},
-    Params,
+    trace::debug,
 };
 use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures_util::{
+    future::{select, Either},
+    pin_mut,
+    task::noop_waker_ref,
+};
 use std::{
     borrow::Cow,
     cmp::min,
     collections::HashMap,
     fmt::{self, Debug, Display},
     mem::size_of,
-    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
 };
-use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio_util::sync::CancellationToken;
 
 /// FastCGI protocol version 1
 pub(crate) const VERSION_1: u8 = 1;
 /// Maximum length for FastCGI content
 pub(crate) const MAX_LENGTH: usize = 0xffff;
+/// Maximum length of a parameter name or value, the high bit of the four
+/// byte length marks it as long
+pub const MAX_PARAM_LENGTH: usize = 0x7fff_ffff;
+
+/// Request id of management records, never used by application requests
+pub(crate) const MANAGEMENT_REQUEST_ID: u16 = 0;
+
+/// Maximum capacity reserved at once while reading record content.
+const READ_CHUNK: usize = 8192;
 /// Length of FastCGI header in bytes
 pub(crate) const HEADER_LEN: usize = size_of::<Header>();
 
 /// FastCGI request types as defined in the protocol specification.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum RequestType {
     /// Begin request record type
@@ -68,24 +86,36 @@ pub enum RequestType {
 }
 
 impl RequestType {
-    /// Converts a u8 value to RequestType.
+    /// Converts a u8 value to RequestType, mapping unrecognized values to
+    /// `UnknownType`.
     ///
     /// # Arguments
     ///
     /// * `u` - The numeric value to convert
-    fn from_u8(u: u8) -> Self {
+    pub fn from_u8(u: u8) -> Self {
+        Self::try_from_u8(u).unwrap_or(RequestType::UnknownType)
+    }
+
+    /// Converts a u8 value to RequestType, returning the raw value if it
+    /// isn't a type defined by the protocol.
+    ///
+    /// # Arguments
+    ///
+    /// * `u` - The numeric value to convert
+    pub fn try_from_u8(u: u8) -> Result<Self, u8> {
         match u {
-            1 => RequestType::BeginRequest,
-            2 => RequestType::AbortRequest,
-            3 => RequestType::EndRequest,
-            4 => RequestType::Params,
-            5 => RequestType::Stdin,
-            6 => RequestType::Stdout,
-            7 => RequestType::Stderr,
-            8 => RequestType::Data,
-            9 => RequestType::GetValues,
-            10 => RequestType::GetValuesResult,
-            _ => RequestType::UnknownType,
+            1 => Ok(RequestType::BeginRequest),
+            2 => Ok(RequestType::AbortRequest),
+            3 => Ok(RequestType::EndRequest),
+            4 => Ok(RequestType::Params),
+            5 => Ok(RequestType::Stdin),
+            6 => Ok(RequestType::Stdout),
+            7 => Ok(RequestType::Stderr),
+            8 => Ok(RequestType::Data),
+            9 => Ok(RequestType::GetValues),
+            10 => Ok(RequestType::GetValuesResult),
+            11 => Ok(RequestType::UnknownType),
+            u => Err(u),
         }
     }
 }
@@ -96,8 +126,57 @@ impl Display for RequestType {
     }
 }
 
+/// Direction of a record on the connection, passed to the callback set
+/// with [crate::ClientBuilder::on_record].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// A record read from the server
+    Read,
+    /// A record written to the server
+    Write,
+}
+
+/// How records written to the server are padded, set with
+/// [crate::ClientBuilder::padding].
+#[derive(Clone, Default)]
+pub enum Padding {
+    /// No padding, for servers that mishandle it.
+    None,
+    /// Padding aligning records to 8 bytes, as the specification recommends.
+    #[default]
+    Align8,
+    /// Padding length computed from the content length of the record.
+    Custom(Arc<dyn Fn(u16) -> u8 + Send + Sync>),
+}
+
+impl Padding {
+    /// Returns the padding length of a record.
+    ///
+    /// # Arguments
+    ///
+    /// * `content_length` - The content length of the record
+    pub(crate) fn padding_length(&self, content_length: u16) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Align8 => ((8 - content_length % 8) % 8) as u8,
+            Self::Custom(padding) => padding(content_length),
+        }
+    }
+}
+
+impl Debug for Padding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => f.write_str("None"),
+            Self::Align8 => f.write_str("Align8"),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// Header of a FastCGI record.
 #[derive(Debug, Clone)]
-pub(crate) struct Header {
+pub struct Header {
     /// FastCGI protocol version
     pub(crate) version: u8,
     /// Type of the FastCGI record
@@ -113,7 +192,35 @@ pub(crate) struct Header {
 }
 
 impl Header {
-    /// Writes data to a stream in batches with proper FastCGI headers.
+    /// Returns the type of the record.
+    pub fn request_type(&self) -> RequestType {
+        self.r#type
+    }
+
+    /// Returns the request ID of the record, 0 for management records.
+    pub fn request_id(&self) -> u16 {
+        self.request_id
+    }
+
+    /// Returns the length of the record content.
+    pub fn content_length(&self) -> u16 {
+        self.content_length
+    }
+
+    /// Returns the length of the padding after the content.
+    pub fn padding_length(&self) -> u8 {
+        self.padding_length
+    }
+
+    /// Writes data to a stream in batches with proper FastCGI headers,
+    /// returning the number of content bytes written.
+    ///
+    /// Each record holds what one read returned and whatever else the reader
+    /// has available without waiting, up to the configured record size, so a
+    /// slow body is sent as it arrives instead of waiting for a record to
+    /// fill. A body available at once, like one in memory, is split into
+    /// records of exactly the record size except the last, however the
+    /// reader chunks its reads. One empty record terminates the stream.
     ///
     /// # Arguments
     ///
@@ -121,33 +228,120 @@ impl Header {
     /// * `request_id` - The request ID
     /// * `writer` - The writer to write to
     /// * `content` - The content to write
-    /// * `before_write` - Optional callback to modify header before writing
-    pub(crate) async fn write_to_stream_batches<F, R, W>(
-        r#type: RequestType, request_id: u16, writer: &mut W, content: &mut R,
-        before_write: Option<F>,
-    ) -> io::Result<()>
+    /// * `buf` - The scratch buffer records are read into, resized to the
+    ///   record size and reused afterwards
+    /// * `filled` - The number of content bytes already read into the start of
+    ///   `buf`
+    /// * `config` - The client settings, for the record size, padding and
+    ///   flushing
+    pub(crate) async fn write_to_stream_batches<R, W>(
+        r#type: RequestType, request_id: u16, writer: &mut W, content: &mut R, buf: &mut Vec<u8>,
+        mut filled: usize, config: &Config,
+    ) -> io::Result<usize>
     where
-        F: Fn(Header) -> Header,
         R: AsyncRead + Unpin,
         W: AsyncWrite + Unpin,
     {
-        let mut buf = vec![0u8; MAX_LENGTH];
-        let mut had_written = false;
+        buf.resize(config.record_size, 0);
 
+        let mut written = 0;
         loop {
-            let read = content.read(&mut buf).await?;
-            if had_written && read == 0 {
+            let read = read_available(content, buf, filled).await?;
+            filled = 0;
+            written += read;
+
+            Self::write_record(r#type, request_id, writer, &buf[..read], config).await?;
+
+            if read == 0 {
                 break;
             }
+        }
+        Ok(written)
+    }
 
-            let buf = &buf[..read];
-            let mut header = Self::new(r#type.clone(), request_id, buf);
-            if let Some(ref f) = before_write {
-                header = f(header);
+    /// Writes name-value pairs as `Params` records, encoding them into `buf`
+    /// one record at a time instead of into one buffer first.
+    ///
+    /// Records are filled up to the configured record size, except that a
+    /// record ends early rather than splitting the length prefix of a pair,
+    /// unless the record size is too small to hold one. An empty record
+    /// terminates the stream. All lengths are checked before anything is
+    /// written, so a pair that is too long leaves the stream untouched.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_id` - The request ID
+    /// * `writer` - The writer to write to
+    /// * `pairs` - The names and values to encode
+    /// * `buf` - The scratch buffer records are encoded into
+    /// * `config` - The client settings, for the record size, padding and
+    ///   flushing
+    pub(crate) async fn write_params_to_stream<I, K, V, W>(
+        request_id: u16, writer: &mut W, pairs: I, buf: &mut Vec<u8>, config: &Config,
+    ) -> ClientResult<()>
+    where
+        I: IntoIterator<Item = (K, V)> + Clone,
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+        W: AsyncWrite + Unpin,
+    {
+        for (name, value) in pairs.clone() {
+            ParamLength::new(name.as_ref().len())?;
+            ParamLength::new(value.as_ref().len())?;
+        }
+
+        let record_size = config.record_size;
+        let mut prefix = BytesMut::with_capacity(8);
+        buf.clear();
+        for (name, value) in pairs {
+            let (name, value) = (name.as_ref(), value.as_ref());
+            prefix.clear();
+            ParamLength::new(name.len())?.encode(&mut prefix);
+            ParamLength::new(value.len())?.encode(&mut prefix);
+            if buf.len() + prefix.len() > record_size && prefix.len() <= record_size {
+                Self::write_record(RequestType::Params, request_id, writer, buf, config).await?;
+                buf.clear();
+            }
+
+            for mut part in [&prefix[..], name, value] {
+                while !part.is_empty() {
+                    if buf.len() == record_size {
+                        Self::write_record(RequestType::Params, request_id, writer, buf, config)
+                            .await?;
+                        buf.clear();
+                    }
+                    let length = min(record_size - buf.len(), part.len());
+                    buf.extend_from_slice(&part[..length]);
+                    part = &part[length..];
+                }
             }
-            header.write_to_stream(writer, buf).await?;
+        }
+        if !buf.is_empty() {
+            Self::write_record(RequestType::Params, request_id, writer, buf, config).await?;
+        }
+        Self::write_record(RequestType::Params, request_id, writer, &[], config).await?;
+        Ok(())
+    }
 
-            had_written = true;
+    /// Writes one record with the configured padding, after passing its
+    /// header to the record tap.
+    ///
+    /// # Arguments
+    ///
+    /// * `r#type` - The type of FastCGI record
+    /// * `request_id` - The request ID
+    /// * `writer` - The writer to write to
+    /// * `content` - The content of the record, at most 65535 bytes
+    /// * `config` - The client settings, for the padding and flushing
+    async fn write_record<W: AsyncWrite + Unpin>(
+        r#type: RequestType, request_id: u16, writer: &mut W, content: &[u8], config: &Config,
+    ) -> io::Result<()> {
+        let mut header = Self::new(r#type, request_id, content);
+        config.pad(&mut header);
+        config.tap(Direction::Write, &header);
+        header.write_to_stream(writer, content).await?;
+        if config.flush_every_record {
+            writer.flush().await?;
         }
         Ok(())
     }
@@ -159,14 +353,14 @@ impl Header {
     /// * `r#type` - The type of FastCGI record
     /// * `request_id` - The request ID
     /// * `content` - The content data
-    fn new(r#type: RequestType, request_id: u16, content: &[u8]) -> Self {
+    pub(crate) fn new(r#type: RequestType, request_id: u16, content: &[u8]) -> Self {
         let content_length = min(content.len(), MAX_LENGTH) as u16;
         Self {
             version: VERSION_1,
             r#type,
             request_id,
             content_length,
-            padding_length: (-(content_length as i16) & 7) as u8,
+            padding_length: Padding::Align8.padding_length(content_length),
             reserved: 0,
         }
     }
@@ -177,16 +371,17 @@ impl Header {
     ///
     /// * `writer` - The writer to write to
     /// * `content` - The content to write
-    async fn write_to_stream<W: AsyncWrite + Unpin>(
+    pub(crate) async fn write_to_stream<W: AsyncWrite + Unpin>(
         self, writer: &mut W, content: &[u8],
     ) -> io::Result<()> {
+        self.trace("Write record.");
         let mut buf: Bytes = (&self).into();
 
         writer.write_all_buf(&mut buf).await?;
         writer.write_all(content).await?;
 
         if self.padding_length > 0 {
-            let padding = [0u8; 7]; // Max padding is 7 bytes
+            let padding = [0u8; u8::MAX as usize];
             writer
                 .write_all(&padding[..self.padding_length as usize])
                 .await?;
@@ -199,10 +394,67 @@ impl Header {
     /// # Arguments
     ///
     /// * `reader` - The reader to read from
-    pub(crate) async fn new_from_stream<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Self> {
-        let mut buf = BytesMut::zeroed(HEADER_LEN);
-        reader.read_exact(&mut buf).await?;
-        Ok(Self::from(buf))
+    pub(crate) async fn new_from_stream<R: AsyncRead + Unpin>(
+        reader: &mut R,
+    ) -> ClientResult<Self> {
+        let mut buf = [0; HEADER_LEN];
+        read_exact_or_closed(reader, &mut buf, 0, None).await?;
+        let header = Self::try_from(&buf[..])?;
+        header.trace("Read record.");
+        Ok(header)
+    }
+
+    /// Creates a new header by reading from a stream, unless `token` is
+    /// cancelled before any byte of it arrives, which returns `None`.
+    ///
+    /// Once the first byte is read the header is completed regardless of the
+    /// token, so a cancellation never leaves the stream inside a record.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The reader to read from
+    /// * `token` - The token cancelling the wait
+    pub(crate) async fn new_from_stream_or_cancel<R: AsyncRead + Unpin>(
+        reader: &mut R, token: &CancellationToken,
+    ) -> ClientResult<Option<Self>> {
+        let mut buf = [0; HEADER_LEN];
+        let read = {
+            let cancelled = token.cancelled();
+            let read = reader.read(&mut buf[..1]);
+            pin_mut!(cancelled, read);
+            match select(cancelled, read).await {
+                Either::Left(_) => return Ok(None),
+                Either::Right((read, _)) => read?,
+            }
+        };
+        if read == 0 {
+            return Err(ClientError::ConnectionClosed {
+                expected: HEADER_LEN,
+                received: 0,
+                request_type: None,
+                stdout: None,
+            });
+        }
+        read_exact_or_closed(reader, &mut buf, 1, None).await?;
+        let header = Self::try_from(&buf[..])?;
+        header.trace("Read record.");
+        Ok(Some(header))
+    }
+
+    /// Emits a debug event describing the record.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The event message
+    #[inline]
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn trace(&self, message: &str) {
+        debug!(
+            request_id = self.request_id,
+            record_type = ?self.r#type,
+            content_length = self.content_length,
+            "{message}"
+        );
     }
 
     /// Reads content from a stream based on the header's content length.
@@ -210,50 +462,247 @@ impl Header {
     /// # Arguments
     ///
     /// * `reader` - The reader to read from
+    /// * `strict_padding` - Whether to reject non-zero padding bytes
     pub(crate) async fn read_content_from_stream<R: AsyncRead + Unpin>(
-        &self, reader: &mut R,
-    ) -> io::Result<BytesMut> {
-        let mut buf = BytesMut::zeroed(self.content_length as usize);
-        reader.read_exact(&mut buf).await?;
-        let mut padding_buf = BytesMut::zeroed(self.padding_length as usize);
-        reader.read_exact(&mut padding_buf).await?;
-        Ok(buf)
+        &self, reader: &mut R, strict_padding: bool,
+    ) -> ClientResult<BytesMut> {
+        self.read_content_into(reader, &mut BytesMut::new(), strict_padding)
+            .await
+    }
+
+    /// Reads content from a stream into a reusable buffer, skipping the
+    /// padding.
+    ///
+    /// The buffer grows with the bytes actually received rather than the
+    /// declared length, so a header promising more than the peer sends
+    /// doesn't allocate up front. Once the returned content is dropped, the
+    /// next call reuses the buffer's allocation.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The reader to read from
+    /// * `buf` - The scratch buffer, cleared before reading
+    /// * `strict_padding` - Whether to reject non-zero padding bytes
+    pub(crate) async fn read_content_into<R: AsyncRead + Unpin>(
+        &self, reader: &mut R, buf: &mut BytesMut, strict_padding: bool,
+    ) -> ClientResult<BytesMut> {
+        let content_length = self.content_length as usize;
+        let block_length = content_length + self.padding_length as usize;
+
+        buf.clear();
+        while buf.len() < block_length {
+            let remaining = block_length - buf.len();
+            buf.reserve(min(remaining, READ_CHUNK));
+            let read = (&mut *reader).take(remaining as u64).read_buf(buf).await?;
+            if read == 0 {
+                return Err(ClientError::ConnectionClosed {
+                    expected: block_length,
+                    received: buf.len(),
+                    request_type: Some(self.r#type),
+                    stdout: None,
+                });
+            }
+        }
+
+        let content = buf.split_to(content_length);
+        if strict_padding {
+            self.check_padding(buf)?;
+        }
+        buf.clear();
+        Ok(content)
+    }
+
+    /// Checks that the padding bytes of the record are all zero.
+    ///
+    /// # Arguments
+    ///
+    /// * `padding` - The padding bytes read after the content
+    pub(crate) fn check_padding(&self, padding: &[u8]) -> ClientResult<()> {
+        if padding.iter().any(|&byte| byte != 0) {
+            return Err(ClientError::InvalidPadding {
+                request_type: self.r#type,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Reads into `buf` after its first `filled` bytes, waiting for data only if
+/// none was read yet, then takes whatever else is available without waiting,
+/// until `buf` is full. Returns the number of bytes in `buf`, 0 at EOF.
+///
+/// # Arguments
+///
+/// * `reader` - The reader to read from
+/// * `buf` - The buffer to fill
+/// * `filled` - The number of bytes already read into the start of `buf`
+async fn read_available<R: AsyncRead + Unpin>(
+    reader: &mut R, buf: &mut [u8], mut filled: usize,
+) -> io::Result<usize> {
+    if filled == 0 {
+        filled = reader.read(buf).await?;
     }
+    while filled > 0 && filled < buf.len() {
+        match read_ready(reader, &mut buf[filled..])? {
+            Some(read) if read > 0 => filled += read,
+            _ => break,
+        }
+    }
+    Ok(filled)
 }
 
-impl Into<Bytes> for &Header {
-    fn into(self) -> Bytes {
+/// Reads from a reader without waiting, returning `None` if no data or end
+/// of stream is available yet.
+///
+/// # Arguments
+///
+/// * `reader` - The reader to read from
+/// * `buf` - The buffer to read into
+pub(crate) fn read_ready<R: AsyncRead + Unpin>(
+    reader: &mut R, buf: &mut [u8],
+) -> io::Result<Option<usize>> {
+    let mut read_buf = ReadBuf::new(buf);
+    let mut cx = Context::from_waker(noop_waker_ref());
+    match Pin::new(reader).poll_read(&mut cx, &mut read_buf) {
+        Poll::Ready(Ok(())) => Ok(Some(read_buf.filled().len())),
+        Poll::Ready(Err(err)) => Err(err),
+        Poll::Pending => Ok(None),
+    }
+}
+
+/// Checks that a request id can be used by an application request, which
+/// excludes the id reserved for management records.
+///
+/// # Arguments
+///
+/// * `request_id` - The request ID
+pub(crate) fn check_request_id(request_id: u16) -> ClientResult<()> {
+    if request_id == MANAGEMENT_REQUEST_ID {
+        return Err(ClientError::InvalidRequestId { id: request_id });
+    }
+    Ok(())
+}
+
+/// Decodes a record from the start of `src`, returning its header and
+/// content, or `None` if it hasn't fully arrived yet.
+///
+/// # Arguments
+///
+/// * `src` - The bytes received
+/// * `pending` - The header of a record whose content hasn't fully arrived,
+///   kept across calls
+/// * `strict_padding` - Whether to reject non-zero padding bytes
+pub(crate) fn decode_record(
+    src: &mut BytesMut, pending: &mut Option<Header>, strict_padding: bool,
+) -> ClientResult<Option<(Header, BytesMut)>> {
+    let header = match pending.take() {
+        Some(header) => header,
+        None if src.len() < HEADER_LEN => {
+            src.reserve(HEADER_LEN - src.len());
+            return Ok(None);
+        }
+        None => {
+            let header = Header::try_from(&src[..HEADER_LEN])?;
+            src.advance(HEADER_LEN);
+            header
+        }
+    };
+
+    let content_length = header.content_length() as usize;
+    let padding_length = header.padding_length() as usize;
+    if src.len() < content_length + padding_length {
+        src.reserve(content_length + padding_length - src.len());
+        *pending = Some(header);
+        return Ok(None);
+    }
+
+    let content = src.split_to(content_length);
+    if strict_padding {
+        header.check_padding(&src[..padding_length])?;
+    }
+    src.advance(padding_length);
+    Ok(Some((header, content)))
+}
+
+/// Fills `buf` from the reader, reporting an end of stream before it is full
+/// as [ClientError::ConnectionClosed].
+///
+/// # Arguments
+///
+/// * `reader` - The reader to read from
+/// * `buf` - The buffer to fill
+/// * `received` - The number of bytes at the start of `buf` already filled
+/// * `request_type` - The type of the record being read, `None` for a header
+async fn read_exact_or_closed<R: AsyncRead + Unpin>(
+    reader: &mut R, buf: &mut [u8], mut received: usize, request_type: Option<RequestType>,
+) -> ClientResult<()> {
+    while received < buf.len() {
+        let read = reader.read(&mut buf[received..]).await?;
+        if read == 0 {
+            return Err(ClientError::ConnectionClosed {
+                expected: buf.len(),
+                received,
+                request_type,
+                stdout: None,
+            });
+        }
+        received += read;
+    }
+    Ok(())
+}
+
+impl From<&Header> for Bytes {
+    fn from(header: &Header) -> Self {
         let mut buf = BytesMut::with_capacity(HEADER_LEN);
-        buf.put_u8(self.version);
-        buf.put_u8(self.r#type as u8);
-        buf.put_u16(self.request_id);
-        buf.put_u16(self.content_length);
-        buf.put_u8(self.padding_length);
-        buf.put_u8(self.reserved);
+        buf.put_u8(header.version);
+        buf.put_u8(header.r#type as u8);
+        buf.put_u16(header.request_id);
+        buf.put_u16(header.content_length);
+        buf.put_u8(header.padding_length);
+        buf.put_u8(header.reserved);
         buf.freeze()
     }
 }
 
-impl From<BytesMut> for Header {
+impl TryFrom<&[u8]> for Header {
+    type Error = ClientError;
+
     /// Creates a new header from a buffer.
     ///
+    /// Fails with [ClientError::TruncatedHeader] if the buffer is shorter
+    /// than a header. Anything other than `VERSION_1` usually means the peer
+    /// isn't speaking FastCGI at all, so the version is checked before the
+    /// rest of the header is trusted, then the record type must be one
+    /// defined by the protocol.
+    ///
     /// # Arguments
     ///
     /// * `buf` - The buffer containing header data
-    fn from(mut buf: BytesMut) -> Self {
-        Self {
-            version: buf.get_u8(),
-            r#type: RequestType::from_u8(buf.get_u8()),
+    fn try_from(mut buf: &[u8]) -> Result<Self, Self::Error> {
+        if buf.len() < HEADER_LEN {
+            return Err(ClientError::TruncatedHeader { length: buf.len() });
+        }
+        let version = buf.get_u8();
+        if version != VERSION_1 {
+            return Err(ClientError::UnsupportedVersion { version });
+        }
+
+        let r#type = RequestType::try_from_u8(buf.get_u8())
+            .map_err(|request_type| ClientError::InvalidRequestType { request_type })?;
+
+        Ok(Self {
+            version,
+            r#type,
             request_id: buf.get_u16(),
             content_length: buf.get_u16(),
             padding_length: buf.get_u8(),
             reserved: buf.get_u8(),
-        }
+        })
     }
 }
 
 /// FastCGI application roles.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u16)]
 #[allow(dead_code)]
 pub enum Role {
@@ -265,6 +714,25 @@ pub enum Role {
     Filter = 3,
 }
 
+impl TryFrom<u16> for Role {
+    type Error = u16;
+
+    /// Converts a role value from the wire, returning the raw value if it
+    /// isn't a role defined by the protocol.
+    ///
+    /// # Arguments
+    ///
+    /// * `u` - The numeric value to convert
+    fn try_from(u: u16) -> Result<Self, Self::Error> {
+        match u {
+            1 => Ok(Role::Responder),
+            2 => Ok(Role::Authorizer),
+            3 => Ok(Role::Filter),
+            u => Err(u),
+        }
+    }
+}
+
 /// Begin request record body data.
 #[derive(Debug)]
 pub(crate) struct BeginRequest {
@@ -307,6 +775,7 @@ pub(crate) struct BeginRequestRec {
     /// The FastCGI header
     pub(crate) header: Header,
     /// The begin request data
+    #[allow(dead_code)]
     pub(crate) begin_request: BeginRequest,
     /// The serialized content
     pub(crate) content: BytesMut,
@@ -320,15 +789,20 @@ impl BeginRequestRec {
     /// * `request_id` - The request ID
     /// * `role` - The role of the application
     /// * `keep_alive` - Whether to keep the connection alive
-    pub(crate) fn new(request_id: u16, role: Role, keep_alive: bool) -> Self {
+    ///
+    /// # Errors
+    ///
+    /// Returns [ClientError::InvalidRequestId] for the management request id.
+    pub(crate) fn new(request_id: u16, role: Role, keep_alive: bool) -> ClientResult<Self> {
+        check_request_id(request_id)?;
         let begin_request = BeginRequest::new(role, keep_alive);
         let content = begin_request.to_content();
         let header = Header::new(RequestType::BeginRequest, request_id, &content);
-        Self {
+        Ok(Self {
             header,
             begin_request,
             content,
-        }
+        })
     }
 
     /// Writes the begin request record to a stream.
@@ -358,13 +832,51 @@ impl ParamLength {
     /// # Arguments
     ///
     /// * `length` - The length to encode
-    pub fn new(length: usize) -> Self {
+    ///
+    /// # Errors
+    ///
+    /// Returns [ClientError::ParamTooLong] if the length is above
+    /// [MAX_PARAM_LENGTH].
+    pub fn new(length: usize) -> ClientResult<Self> {
         if length < 128 {
-            ParamLength::Short(length as u8)
+            Ok(ParamLength::Short(length as u8))
+        } else if length <= MAX_PARAM_LENGTH {
+            Ok(ParamLength::Long(length as u32 | 1 << 31))
         } else {
-            let mut length = length;
-            length |= 1 << 31;
-            ParamLength::Long(length as u32)
+            Err(ClientError::ParamTooLong { length })
+        }
+    }
+
+    /// Decodes a parameter length, one byte if below 128, otherwise four
+    /// bytes with the high bit set.
+    ///
+    /// Returns `None` without consuming anything if `buf` is too short.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - The buffer to decode from
+    pub fn decode(buf: &mut impl Buf) -> Option<usize> {
+        if !buf.has_remaining() {
+            return None;
+        }
+        if buf.chunk()[0] & 0x80 == 0 {
+            return Some(buf.get_u8() as usize);
+        }
+        if buf.remaining() < 4 {
+            return None;
+        }
+        Some((buf.get_u32() & 0x7fff_ffff) as usize)
+    }
+
+    /// Encodes the parameter length into a buffer.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - The buffer to write to
+    pub fn encode(self, buf: &mut BytesMut) {
+        match self {
+            ParamLength::Short(l) => buf.put_u8(l),
+            ParamLength::Long(l) => buf.put_u32(l),
         }
     }
 
@@ -386,16 +898,15 @@ impl ParamLength {
 }
 
 /// A single parameter name-value pair.
-#[derive(Debug)]
 pub struct ParamPair<'a> {
     /// Length of the parameter name
     name_length: ParamLength,
     /// Length of the parameter value
     value_length: ParamLength,
     /// The parameter name
-    name_data: Cow<'a, str>,
+    name_data: Cow<'a, [u8]>,
     /// The parameter value
-    value_data: Cow<'a, str>,
+    value_data: Cow<'a, [u8]>,
 }
 
 impl<'a> ParamPair<'a> {
@@ -405,77 +916,126 @@ impl<'a> ParamPair<'a> {
     ///
     /// * `name` - The parameter name
     /// * `value` - The parameter value
-    fn new(name: Cow<'a, str>, value: Cow<'a, str>) -> Self {
-        let name_length = ParamLength::new(name.len());
-        let value_length = ParamLength::new(value.len());
-        Self {
+    ///
+    /// # Errors
+    ///
+    /// Returns [ClientError::ParamTooLong] if the name or value is longer
+    /// than [MAX_PARAM_LENGTH].
+    pub fn new(name: Cow<'a, [u8]>, value: Cow<'a, [u8]>) -> ClientResult<Self> {
+        let name_length = ParamLength::new(name.len())?;
+        let value_length = ParamLength::new(value.len())?;
+        Ok(Self {
             name_length,
             value_length,
             name_data: name,
             value_data: value,
-        }
+        })
     }
 
-    /// Writes the parameter pair to a buffer.
+    /// Decodes a parameter pair, borrowing the name and value from `buf`.
+    ///
+    /// Returns `None` without consuming anything if `buf` ends before the
+    /// pair does.
     ///
     /// # Arguments
     ///
-    /// * `buf` - The buffer to write to
-    fn write_to_buf(&self, buf: &mut BytesMut) {
-        let name_len = self.name_length.content();
-        buf.extend_from_slice(&name_len);
-        let value_len = self.value_length.content();
-        buf.extend_from_slice(&value_len);
-        buf.extend_from_slice(self.name_data.as_bytes());
-        buf.extend_from_slice(self.value_data.as_bytes());
+    /// * `buf` - The buffer to decode from, advanced past the pair
+    pub fn decode(buf: &mut &'a [u8]) -> Option<Self> {
+        let mut rest = *buf;
+        let name_length = ParamLength::decode(&mut rest)?;
+        let value_length = ParamLength::decode(&mut rest)?;
+        // The lengths can add up past `usize::MAX` on 32-bit targets.
+        if rest.len() < name_length.checked_add(value_length)? {
+            return None;
+        }
+        let (name, rest) = rest.split_at(name_length);
+        let (value, rest) = rest.split_at(value_length);
+        *buf = rest;
+        Self::new(Cow::Borrowed(name), Cow::Borrowed(value)).ok()
     }
-}
 
-/// Collection of parameter pairs.
-#[derive(Debug)]
-pub(crate) struct ParamPairs<'a>(Vec<ParamPair<'a>>);
+    /// Returns the parameter name.
+    pub fn name(&self) -> &[u8] {
+        &self.name_data
+    }
+
+    /// Returns the parameter value.
+    pub fn value(&self) -> &[u8] {
+        &self.value_data
+    }
 
-impl<'a> ParamPairs<'a> {
-    /// Creates parameter pairs from a Params object.
+    /// Encodes the parameter pair, lengths first, into a buffer.
     ///
     /// # Arguments
     ///
-    /// * `params` - The parameters to convert
-    pub(crate) fn new(params: Params<'a>) -> Self {
-        let mut param_pairs = Vec::new();
-        let params: HashMap<Cow<'a, str>, Cow<'a, str>> = params.into();
-        for (name, value) in params.into_iter() {
-            let param_pair = ParamPair::new(name, value);
-            param_pairs.push(param_pair);
-        }
-
-        Self(param_pairs)
+    /// * `buf` - The buffer to write to
+    pub fn encode(&self, buf: &mut BytesMut) {
+        self.name_length.encode(buf);
+        self.value_length.encode(buf);
+        buf.extend_from_slice(&self.name_data);
+        buf.extend_from_slice(&self.value_data);
     }
+}
 
-    /// Converts the parameter pairs to bytes.
-    pub(crate) fn to_content(&self) -> Bytes {
-        let mut buf = BytesMut::new();
-
-        for param_pair in self.iter() {
-            param_pair.write_to_buf(&mut buf);
-        }
-
-        buf.freeze()
+impl Debug for ParamPair<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParamPair")
+            .field("name_length", &self.name_length)
+            .field("value_length", &self.value_length)
+            .field("name_data", &String::from_utf8_lossy(&self.name_data))
+            .field("value_data", &String::from_utf8_lossy(&self.value_data))
+            .finish()
     }
 }
 
-impl<'a> Deref for ParamPairs<'a> {
-    type Target = Vec<ParamPair<'a>>;
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
+/// Encodes name-value pairs as the content of `Params` records, one pair at
+/// a time in the order of the iterator, without collecting them first.
+///
+/// The result can be sent with [crate::Request::with_raw_params], for
+/// params that come from another ordered collection than [crate::Params].
+///
+/// # Arguments
+///
+/// * `pairs` - The names and values to encode
+/// * `buf` - The buffer to write to
+///
+/// # Errors
+///
+/// Returns [ClientError::ParamTooLong] if a name or value is longer than
+/// [MAX_PARAM_LENGTH], the pairs before it are left in `buf`.
+pub fn encode_params<I, K, V>(pairs: I, buf: &mut BytesMut) -> ClientResult<()>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    for (name, value) in pairs {
+        let (name, value) = (name.as_ref(), value.as_ref());
+        let name_length = ParamLength::new(name.len())?;
+        let value_length = ParamLength::new(value.len())?;
+        buf.reserve(8 + name.len() + value.len());
+        name_length.encode(buf);
+        value_length.encode(buf);
+        buf.extend_from_slice(name);
+        buf.extend_from_slice(value);
     }
+    Ok(())
 }
 
-impl<'a> DerefMut for ParamPairs<'a> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+/// Checks that name-value pairs, such as the content of a get values result
+/// record, don't end in the middle of a pair.
+///
+/// # Arguments
+///
+/// * `buf` - The encoded pairs
+pub(crate) fn check_params(mut buf: &[u8]) -> ClientResult<()> {
+    while ParamPair::decode(&mut buf).is_some() {}
+    if !buf.is_empty() {
+        return Err(ClientError::TruncatedParams {
+            remaining: buf.len(),
+        });
     }
+    Ok(())
 }
 
 /// FastCGI protocol status codes.
@@ -511,12 +1071,17 @@ impl ProtocolStatus {
     ///
     /// # Arguments
     ///
+    /// * `role` - The role of the request, reported if it is unknown
     /// * `app_status` - The application status code
-    pub(crate) fn convert_to_client_result(self, app_status: u32) -> ClientResult<()> {
+    /// * `stdout` - The stdout output to attach to the error
+    /// * `stderr` - The stderr output to attach to the error
+    pub(crate) fn convert_to_client_result(
+        self, role: Role, app_status: u32, stdout: Option<Bytes>, stderr: Option<Bytes>,
+    ) -> ClientResult<()> {
         match self {
             ProtocolStatus::RequestComplete => Ok(()),
             _ => Err(ClientError::new_end_request_with_protocol_status(
-                self, app_status,
+                self, role, app_status, stdout, stderr,
             )),
         }
     }
@@ -536,6 +1101,7 @@ pub struct EndRequest {
 
 impl From<BytesMut> for EndRequest {
     fn from(mut buf: BytesMut) -> Self {
+        buf.resize(8, 0);
         let app_status = buf.get_u32();
         let protocol_status = ProtocolStatus::from_u8(buf.get_u8());
         let mut reserved = [0u8; 3];
@@ -566,11 +1132,14 @@ impl EndRequestRec {
     ///
     /// * `header` - The FastCGI header
     /// * `reader` - The reader to read content from
+    /// * `strict_padding` - Whether to reject non-zero padding bytes
     pub(crate) async fn from_header<R: AsyncRead + Unpin>(
-        header: &Header, reader: &mut R,
-    ) -> io::Result<Self> {
+        header: &Header, reader: &mut R, strict_padding: bool,
+    ) -> ClientResult<Self> {
         let header = header.clone();
-        let content = header.read_content_from_stream(reader).await?;
+        let content = header
+            .read_content_from_stream(reader, strict_padding)
+            .await?;
         Ok(Self::new_from_buf(header, content))
     }
 
@@ -587,3 +1156,151 @@ impl EndRequestRec {
         }
     }
 }
+
+/// Unknown type record body data, sent by the application in reply to a
+/// management record it doesn't understand.
+#[derive(Debug)]
+pub struct UnknownType {
+    /// The type byte of the record that wasn't understood
+    pub(crate) r#type: u8,
+    /// Reserved bytes
+    #[allow(dead_code)]
+    reserved: [u8; 7],
+}
+
+impl From<BytesMut> for UnknownType {
+    fn from(mut buf: BytesMut) -> Self {
+        buf.resize(8, 0);
+        let r#type = buf.get_u8();
+        let mut reserved = [0u8; 7];
+        buf.copy_to_slice(&mut reserved);
+
+        Self { r#type, reserved }
+    }
+}
+
+/// Complete unknown type record with header and content.
+#[derive(Debug)]
+pub(crate) struct UnknownTypeRec {
+    /// The FastCGI header
+    #[allow(dead_code)]
+    header: Header,
+    /// The unknown type data
+    pub(crate) unknown_type: UnknownType,
+}
+
+impl UnknownTypeRec {
+    /// Creates an unknown type record from a header and reader.
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - The FastCGI header
+    /// * `reader` - The reader to read content from
+    /// * `strict_padding` - Whether to reject non-zero padding bytes
+    pub(crate) async fn from_header<R: AsyncRead + Unpin>(
+        header: &Header, reader: &mut R, strict_padding: bool,
+    ) -> ClientResult<Self> {
+        let header = header.clone();
+        let content = header
+            .read_content_from_stream(reader, strict_padding)
+            .await?;
+        Ok(Self::new_from_buf(header, content))
+    }
+
+    /// Creates an unknown type record from a header and buffer.
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - The FastCGI header
+    /// * `buf` - The buffer containing the unknown type data
+    pub(crate) fn new_from_buf(header: Header, buf: BytesMut) -> Self {
+        Self {
+            header,
+            unknown_type: UnknownType::from(buf),
+        }
+    }
+}
+
+/// Name of the management variable with the maximum number of concurrent
+/// transport connections the application accepts.
+pub const FCGI_MAX_CONNS: &str = "FCGI_MAX_CONNS";
+/// Name of the management variable with the maximum number of concurrent
+/// requests the application accepts.
+pub const FCGI_MAX_REQS: &str = "FCGI_MAX_REQS";
+/// Name of the management variable telling whether the application
+/// multiplexes connections, `"1"` if it does.
+pub const FCGI_MPXS_CONNS: &str = "FCGI_MPXS_CONNS";
+
+/// Complete get values management record, asking the application for the
+/// values of some variables.
+#[derive(Debug)]
+pub(crate) struct GetValuesRec {
+    /// The FastCGI header
+    pub(crate) header: Header,
+    /// The names of the variables as name-value pairs with empty values
+    content: BytesMut,
+}
+
+impl GetValuesRec {
+    /// Creates a new get values record, always with request id 0.
+    ///
+    /// # Arguments
+    ///
+    /// * `names` - The names of the variables to query
+    pub(crate) fn new(names: &[&str]) -> ClientResult<Self> {
+        let mut content = BytesMut::new();
+        for name in names {
+            ParamPair::new(name.as_bytes().into(), (&[][..]).into())?.encode(&mut content);
+        }
+        if content.len() > MAX_LENGTH {
+            return Err(ClientError::ParamTooLong {
+                length: content.len(),
+            });
+        }
+        let header = Header::new(RequestType::GetValues, MANAGEMENT_REQUEST_ID, &content);
+        Ok(Self { header, content })
+    }
+
+    /// Writes the get values record to a stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - The writer to write to
+    pub(crate) async fn write_to_stream<W: AsyncWrite + Unpin>(
+        self, writer: &mut W,
+    ) -> io::Result<()> {
+        self.header.write_to_stream(writer, &self.content).await
+    }
+}
+
+/// Complete get values result management record, with the values of the
+/// variables the application knows.
+#[derive(Debug)]
+pub(crate) struct GetValuesResultRec {
+    /// The FastCGI header
+    #[allow(dead_code)]
+    header: Header,
+    /// The variables by name
+    pub(crate) values: HashMap<String, String>,
+}
+
+impl GetValuesResultRec {
+    /// Creates a get values result record from a header and buffer, ignoring
+    /// a truncated last pair.
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - The FastCGI header
+    /// * `buf` - The buffer containing the name-value pairs
+    pub(crate) fn new_from_buf(header: Header, buf: BytesMut) -> Self {
+        let mut values = HashMap::new();
+        let mut content = &buf[..];
+        while let Some(pair) = ParamPair::decode(&mut content) {
+            values.insert(
+                String::from_utf8_lossy(pair.name()).into_owned(),
+                String::from_utf8_lossy(pair.value()).into_owned(),
+            );
+        }
+        Self { header, values }
+    }
+}
@@ -18,17 +18,40 @@
 //! for FastCGI parameters that are sent to the FastCGI server.
 //! It includes convenient methods for setting common CGI parameters.
 
+use indexmap::IndexMap;
 use std::{
     borrow::Cow,
     collections::HashMap,
+    fmt::{self, Debug},
     ops::{Deref, DerefMut},
 };
 
 /// Fastcgi params, please reference to nginx-php-fpm fastcgi_params.
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Params<'a>(HashMap<Cow<'a, str>, Cow<'a, str>>);
+///
+/// Params are sent in the order they were first set; setting the same name
+/// again overwrites the value in place.
+///
+/// Names and values are byte strings on the wire; the `&str` setters cover
+/// the common case and [Params::set_bytes] accepts arbitrary bytes.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Params<'a>(IndexMap<Cow<'a, [u8]>, Cow<'a, [u8]>>);
 
 impl<'a> Params<'a> {
+    /// Sets a parameter from raw bytes, for names or values that aren't
+    /// valid UTF-8.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The parameter name
+    /// * `value` - The parameter value
+    #[inline]
+    pub fn set_bytes<K: Into<Cow<'a, [u8]>>, V: Into<Cow<'a, [u8]>>>(
+        mut self, name: K, value: V,
+    ) -> Self {
+        self.insert(name.into(), value.into());
+        self
+    }
+
     /// Sets a custom parameter with the given key and value.
     ///
     /// # Arguments
@@ -39,7 +62,7 @@ impl<'a> Params<'a> {
     pub fn custom<K: Into<Cow<'a, str>>, S: Into<Cow<'a, str>>>(
         mut self, key: K, value: S,
     ) -> Self {
-        self.insert(key.into(), value.into());
+        self.insert_str(key, value);
         self
     }
 
@@ -50,7 +73,7 @@ impl<'a> Params<'a> {
     /// * `gateway_interface` - The gateway interface version (e.g., "CGI/1.1")
     #[inline]
     pub fn gateway_interface<S: Into<Cow<'a, str>>>(mut self, gateway_interface: S) -> Self {
-        self.insert("GATEWAY_INTERFACE".into(), gateway_interface.into());
+        self.insert_str("GATEWAY_INTERFACE", gateway_interface);
         self
     }
 
@@ -61,7 +84,7 @@ impl<'a> Params<'a> {
     /// * `server_software` - The server software name and version
     #[inline]
     pub fn server_software<S: Into<Cow<'a, str>>>(mut self, server_software: S) -> Self {
-        self.insert("SERVER_SOFTWARE".into(), server_software.into());
+        self.insert_str("SERVER_SOFTWARE", server_software);
         self
     }
 
@@ -72,7 +95,7 @@ impl<'a> Params<'a> {
     /// * `server_protocol` - The server protocol version (e.g., "HTTP/1.1")
     #[inline]
     pub fn server_protocol<S: Into<Cow<'a, str>>>(mut self, server_protocol: S) -> Self {
-        self.insert("SERVER_PROTOCOL".into(), server_protocol.into());
+        self.insert_str("SERVER_PROTOCOL", server_protocol);
         self
     }
 
@@ -83,7 +106,7 @@ impl<'a> Params<'a> {
     /// * `request_method` - The HTTP request method (e.g., "GET", "POST")
     #[inline]
     pub fn request_method<S: Into<Cow<'a, str>>>(mut self, request_method: S) -> Self {
-        self.insert("REQUEST_METHOD".into(), request_method.into());
+        self.insert_str("REQUEST_METHOD", request_method);
         self
     }
 
@@ -94,7 +117,7 @@ impl<'a> Params<'a> {
     /// * `script_filename` - The full path to the script file
     #[inline]
     pub fn script_filename<S: Into<Cow<'a, str>>>(mut self, script_filename: S) -> Self {
-        self.insert("SCRIPT_FILENAME".into(), script_filename.into());
+        self.insert_str("SCRIPT_FILENAME", script_filename);
         self
     }
 
@@ -105,7 +128,7 @@ impl<'a> Params<'a> {
     /// * `script_name` - The URI part that identifies the script
     #[inline]
     pub fn script_name<S: Into<Cow<'a, str>>>(mut self, script_name: S) -> Self {
-        self.insert("SCRIPT_NAME".into(), script_name.into());
+        self.insert_str("SCRIPT_NAME", script_name);
         self
     }
 
@@ -116,7 +139,7 @@ impl<'a> Params<'a> {
     /// * `query_string` - The query string part of the URL
     #[inline]
     pub fn query_string<S: Into<Cow<'a, str>>>(mut self, query_string: S) -> Self {
-        self.insert("QUERY_STRING".into(), query_string.into());
+        self.insert_str("QUERY_STRING", query_string);
         self
     }
 
@@ -127,7 +150,7 @@ impl<'a> Params<'a> {
     /// * `request_uri` - The full request URI
     #[inline]
     pub fn request_uri<S: Into<Cow<'a, str>>>(mut self, request_uri: S) -> Self {
-        self.insert("REQUEST_URI".into(), request_uri.into());
+        self.insert_str("REQUEST_URI", request_uri);
         self
     }
 
@@ -138,7 +161,7 @@ impl<'a> Params<'a> {
     /// * `document_root` - The document root directory path
     #[inline]
     pub fn document_root<S: Into<Cow<'a, str>>>(mut self, document_root: S) -> Self {
-        self.insert("DOCUMENT_ROOT".into(), document_root.into());
+        self.insert_str("DOCUMENT_ROOT", document_root);
         self
     }
 
@@ -149,7 +172,7 @@ impl<'a> Params<'a> {
     /// * `document_uri` - The document URI
     #[inline]
     pub fn document_uri<S: Into<Cow<'a, str>>>(mut self, document_uri: S) -> Self {
-        self.insert("DOCUMENT_URI".into(), document_uri.into());
+        self.insert_str("DOCUMENT_URI", document_uri);
         self
     }
 
@@ -160,7 +183,7 @@ impl<'a> Params<'a> {
     /// * `remote_addr` - The remote client IP address
     #[inline]
     pub fn remote_addr<S: Into<Cow<'a, str>>>(mut self, remote_addr: S) -> Self {
-        self.insert("REMOTE_ADDR".into(), remote_addr.into());
+        self.insert_str("REMOTE_ADDR", remote_addr);
         self
     }
 
@@ -171,7 +194,7 @@ impl<'a> Params<'a> {
     /// * `remote_port` - The remote client port number
     #[inline]
     pub fn remote_port(mut self, remote_port: u16) -> Self {
-        self.insert("REMOTE_PORT".into(), remote_port.to_string().into());
+        self.insert_str("REMOTE_PORT", remote_port.to_string());
         self
     }
 
@@ -182,7 +205,7 @@ impl<'a> Params<'a> {
     /// * `server_addr` - The server IP address
     #[inline]
     pub fn server_addr<S: Into<Cow<'a, str>>>(mut self, server_addr: S) -> Self {
-        self.insert("SERVER_ADDR".into(), server_addr.into());
+        self.insert_str("SERVER_ADDR", server_addr);
         self
     }
 
@@ -193,7 +216,7 @@ impl<'a> Params<'a> {
     /// * `server_port` - The server port number
     #[inline]
     pub fn server_port(mut self, server_port: u16) -> Self {
-        self.insert("SERVER_PORT".into(), server_port.to_string().into());
+        self.insert_str("SERVER_PORT", server_port.to_string());
         self
     }
 
@@ -204,7 +227,7 @@ impl<'a> Params<'a> {
     /// * `server_name` - The server name or hostname
     #[inline]
     pub fn server_name<S: Into<Cow<'a, str>>>(mut self, server_name: S) -> Self {
-        self.insert("SERVER_NAME".into(), server_name.into());
+        self.insert_str("SERVER_NAME", server_name);
         self
     }
 
@@ -215,7 +238,7 @@ impl<'a> Params<'a> {
     /// * `content_type` - The content type of the request body
     #[inline]
     pub fn content_type<S: Into<Cow<'a, str>>>(mut self, content_type: S) -> Self {
-        self.insert("CONTENT_TYPE".into(), content_type.into());
+        self.insert_str("CONTENT_TYPE", content_type);
         self
     }
 
@@ -226,14 +249,155 @@ impl<'a> Params<'a> {
     /// * `content_length` - The length of the request body in bytes
     #[inline]
     pub fn content_length(mut self, content_length: usize) -> Self {
-        self.insert("CONTENT_LENGTH".into(), content_length.to_string().into());
+        self.insert_str("CONTENT_LENGTH", content_length.to_string());
         self
     }
+
+    /// Sets the FCGI_DATA_LAST_MOD parameter for the `Filter` role.
+    ///
+    /// # Arguments
+    ///
+    /// * `data_last_mod` - The last modification time of the filtered file, in
+    ///   seconds since the Unix epoch
+    #[inline]
+    pub fn data_last_mod(mut self, data_last_mod: u64) -> Self {
+        self.insert_str("FCGI_DATA_LAST_MOD", data_last_mod.to_string());
+        self
+    }
+
+    /// Sets the FCGI_DATA_LENGTH parameter for the `Filter` role.
+    ///
+    /// # Arguments
+    ///
+    /// * `data_length` - The length of the filtered file in bytes
+    #[inline]
+    pub fn data_length(mut self, data_length: usize) -> Self {
+        self.insert_str("FCGI_DATA_LENGTH", data_length.to_string());
+        self
+    }
+}
+
+#[cfg(feature = "http")]
+impl<'a> Params<'a> {
+    /// Creates params from an `http::Request`, mapping the method, URI,
+    /// protocol version and headers into the standard CGI variables.
+    ///
+    /// Headers become `HTTP_*` params with the name uppercased and `-`
+    /// replaced by `_`, except `Content-Type` and `Content-Length` which set
+    /// `CONTENT_TYPE` and `CONTENT_LENGTH`. Repeated headers are joined with
+    /// `, `, or `; ` for `Cookie`.
+    ///
+    /// Like nginx and Apache, the `Proxy` header is dropped, since it would
+    /// set `HTTP_PROXY` which many HTTP libraries take as the proxy to use
+    /// (httpoxy, CVE-2016-5385), and so are header names containing `_`,
+    /// which would collide with the same name spelled with `-`.
+    ///
+    /// The params that describe the server side still must be set by the
+    /// caller, at least `SCRIPT_FILENAME` and `DOCUMENT_ROOT`, and usually
+    /// `SCRIPT_NAME`, `REMOTE_ADDR`, `REMOTE_PORT`, `SERVER_ADDR`,
+    /// `SERVER_PORT` and `SERVER_NAME`.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The HTTP request to translate
+    pub fn from_http<T>(request: &'a http::Request<T>) -> Self {
+        let uri = request.uri();
+        let mut params = Params::default()
+            .server_protocol(match request.version() {
+                http::Version::HTTP_09 => "HTTP/0.9",
+                http::Version::HTTP_10 => "HTTP/1.0",
+                http::Version::HTTP_2 => "HTTP/2.0",
+                http::Version::HTTP_3 => "HTTP/3.0",
+                _ => "HTTP/1.1",
+            })
+            .request_method(request.method().as_str())
+            .request_uri(uri.path_and_query().map_or("/", |path| path.as_str()))
+            .document_uri(uri.path())
+            .query_string(uri.query().unwrap_or_default());
+
+        let headers = request.headers();
+        for name in headers.keys() {
+            if name.as_str() == "proxy" || name.as_str().contains('_') {
+                continue;
+            }
+            let key: Cow<'a, [u8]> = match *name {
+                http::header::CONTENT_TYPE => Cow::Borrowed(b"CONTENT_TYPE"),
+                http::header::CONTENT_LENGTH => Cow::Borrowed(b"CONTENT_LENGTH"),
+                _ => {
+                    let name = name.as_str().to_ascii_uppercase().replace('-', "_");
+                    Cow::Owned(format!("HTTP_{name}").into_bytes())
+                }
+            };
+            let separator: &[u8] = match *name {
+                http::header::COOKIE => b"; ",
+                _ => b", ",
+            };
+
+            let mut values = headers.get_all(name).iter();
+            let mut value = Cow::Borrowed(values.next().map_or(&[][..], |value| value.as_bytes()));
+            for next in values {
+                let value = value.to_mut();
+                value.extend_from_slice(separator);
+                value.extend_from_slice(next.as_bytes());
+            }
+            params.insert(key, value);
+        }
+
+        params
+    }
+}
+
+impl Params<'_> {
+    /// Converts borrowed names and values into owned ones, so the params can
+    /// be moved into a spawned task.
+    pub fn into_owned(self) -> Params<'static> {
+        Params(
+            self.0
+                .into_iter()
+                .map(|(name, value)| {
+                    (
+                        Cow::Owned(name.into_owned()),
+                        Cow::Owned(value.into_owned()),
+                    )
+                })
+                .collect(),
+        )
+    }
+}
+
+impl<'a> Params<'a> {
+    /// Inserts a UTF-8 name-value pair as bytes.
+    #[inline]
+    fn insert_str<K: Into<Cow<'a, str>>, S: Into<Cow<'a, str>>>(&mut self, key: K, value: S) {
+        self.insert(str_into_bytes(key.into()), str_into_bytes(value.into()));
+    }
+}
+
+/// Converts a string `Cow` into a byte `Cow` without copying.
+#[inline]
+fn str_into_bytes(s: Cow<'_, str>) -> Cow<'_, [u8]> {
+    match s {
+        Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+        Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+    }
+}
+
+impl Debug for Params<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.0.iter().map(|(name, value)| {
+                (
+                    String::from_utf8_lossy(name),
+                    String::from_utf8_lossy(value),
+                )
+            }))
+            .finish()
+    }
 }
 
 impl<'a> Default for Params<'a> {
     fn default() -> Self {
-        Params(HashMap::new())
+        Params(IndexMap::new())
             .gateway_interface("FastCGI/1.0")
             .server_software("fastcgi-client-rs")
             .server_protocol("HTTP/1.1")
@@ -241,7 +405,7 @@ impl<'a> Default for Params<'a> {
 }
 
 impl<'a> Deref for Params<'a> {
-    type Target = HashMap<Cow<'a, str>, Cow<'a, str>>;
+    type Target = IndexMap<Cow<'a, [u8]>, Cow<'a, [u8]>>;
 
     fn deref(&self) -> &Self::Target {
         &self.0
@@ -254,8 +418,14 @@ impl<'a> DerefMut for Params<'a> {
     }
 }
 
-impl<'a> From<Params<'a>> for HashMap<Cow<'a, str>, Cow<'a, str>> {
+impl<'a> From<Params<'a>> for IndexMap<Cow<'a, [u8]>, Cow<'a, [u8]>> {
     fn from(params: Params<'a>) -> Self {
         params.0
     }
 }
+
+impl<'a> From<Params<'a>> for HashMap<Cow<'a, [u8]>, Cow<'a, [u8]>> {
+    fn from(params: Params<'a>) -> Self {
+        params.0.into_iter().collect()
+    }
+}
@@ -0,0 +1,503 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pool of keep alive clients.
+//!
+//! This module provides the `Pool` struct, which reuses keep alive
+//! connections across requests and reconnects when one is found dead.
+
+use crate::{
+    conn::KeepAlive, request::Request, retry::RetryPolicy, trace::debug, Client, ClientResult,
+    Response, SocketOptions,
+};
+use futures_util::future::try_join_all;
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io, mem,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::Instant,
+};
+
+/// Default maximum number of connections of a pool.
+const DEFAULT_MAX_SIZE: usize = 16;
+
+/// Opens a new connection of the pool.
+pub(crate) type Connect<S> =
+    Box<dyn Fn() -> Pin<Box<dyn Future<Output = io::Result<S>> + Send>> + Send + Sync>;
+
+/// Pool of keep alive clients, cheap to clone and share between tasks.
+///
+/// Clients are handed out by [Pool::get] and go back to the pool when the
+/// returned [PooledClient] is dropped. Before a client is reused its
+/// connection is checked with [Client::is_healthy], and dead, idle or expired
+/// connections are replaced by new ones from the connect function.
+///
+/// # Examples
+///
+/// ```
+/// use fcgi_client::{Params, Pool, Request};
+/// use tokio::{io, net::TcpStream};
+///
+/// async fn pooled() {
+///     let pool = Pool::new(|| TcpStream::connect(("127.0.0.1", 9000))).max_size(32);
+///
+///     let mut client = pool.get().await.unwrap();
+///     let output = client
+///         .execute(Request::new(Params::default(), &mut io::empty()))
+///         .await
+///         .unwrap();
+/// }
+/// ```
+pub struct Pool<S> {
+    inner: Arc<Inner<S>>,
+}
+
+struct Inner<S> {
+    connect: Connect<S>,
+    idle: Mutex<VecDeque<Idle<S>>>,
+    permits: Arc<Semaphore>,
+    max_size: usize,
+    idle_timeout: Option<Duration>,
+    max_lifetime: Option<Duration>,
+    probe_after: Option<Duration>,
+}
+
+/// A client waiting in the pool.
+struct Idle<S> {
+    client: Client<S, KeepAlive>,
+    since: Instant,
+    created: Instant,
+}
+
+impl<S> Inner<S> {
+    /// Returns whether a connection opened at `created` has outlived
+    /// [Pool::max_lifetime].
+    fn is_expired(&self, created: Instant) -> bool {
+        self.max_lifetime
+            .is_some_and(|max_lifetime| created.elapsed() >= max_lifetime)
+    }
+}
+
+impl Pool<TcpStream> {
+    /// Creates a pool opening TCP connections with the socket options, see
+    /// [SocketOptions::connect].
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address of the server, such as `("php-fpm", 9000)`
+    /// * `timeout` - How long to wait for each address to connect
+    /// * `socket_options` - The options of the sockets
+    pub fn connect_tcp<A>(addr: A, timeout: Duration, socket_options: SocketOptions) -> Self
+    where
+        A: ToSocketAddrs + Clone + Send + Sync + 'static,
+    {
+        Self::new(move || {
+            let (addr, socket_options) = (addr.clone(), socket_options.clone());
+            async move {
+                socket_options
+                    .connect(addr, timeout)
+                    .await
+                    .map_err(io::Error::other)
+            }
+        })
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> Pool<S> {
+    /// Creates a pool opening connections with `connect`, such as
+    /// `|| TcpStream::connect(("127.0.0.1", 9000))`.
+    ///
+    /// Connections are opened lazily, up to 16 at a time unless set with
+    /// [Pool::max_size].
+    ///
+    /// # Arguments
+    ///
+    /// * `connect` - The function opening a new connection
+    pub fn new<F, Fut>(connect: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = io::Result<S>> + Send + 'static,
+    {
+        Self {
+            inner: Arc::new(Inner {
+                connect: Box::new(move || Box::pin(connect())),
+                idle: Mutex::new(VecDeque::new()),
+                permits: Arc::new(Semaphore::new(DEFAULT_MAX_SIZE)),
+                max_size: DEFAULT_MAX_SIZE,
+                idle_timeout: None,
+                max_lifetime: None,
+                probe_after: None,
+            }),
+        }
+    }
+
+    /// Set the maximum number of connections, in use or idle.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pool has already been cloned.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        let inner = self.inner_mut();
+        inner.max_size = max_size;
+        inner.permits = Arc::new(Semaphore::new(max_size));
+        self
+    }
+
+    /// Set how long a connection may stay idle in the pool before it is
+    /// closed instead of reused, `None` means forever (the default).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pool has already been cloned.
+    pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.inner_mut().idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Set how long a connection may be used after it was opened, `None`
+    /// means forever (the default).
+    ///
+    /// An expired connection is closed when it would go back to the pool or
+    /// be reused, so requests in flight are never cut off. This bounds how
+    /// long a connection to a restarted worker can linger.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pool has already been cloned.
+    pub fn max_lifetime(mut self, max_lifetime: Option<Duration>) -> Self {
+        self.inner_mut().max_lifetime = max_lifetime;
+        self
+    }
+
+    /// Set how long a connection may go unused before it is
+    /// [pinged](Client::ping) when taken from the pool, `None` means never
+    /// (the default).
+    ///
+    /// This finds connections dropped silently, for example by a NAT timeout
+    /// or a worker restart, which [Client::is_healthy] can't see. A connection
+    /// failing the ping is closed and another one is used. See also
+    /// [Pool::probe_idle].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pool has already been cloned.
+    pub fn probe_after(mut self, probe_after: Option<Duration>) -> Self {
+        self.inner_mut().probe_after = probe_after;
+        self
+    }
+
+    /// Returns the maximum number of connections.
+    pub fn size_limit(&self) -> usize {
+        self.inner.max_size
+    }
+
+    /// Returns the number of idle connections.
+    pub fn idle_count(&self) -> usize {
+        self.inner.idle.lock().unwrap().len()
+    }
+
+    /// Takes a client from the pool, waiting if all connections are in use.
+    ///
+    /// Idle clients are reused if healthy, otherwise a new connection is
+    /// opened.
+    pub async fn get(&self) -> ClientResult<PooledClient<S>> {
+        let permit = self.acquire().await;
+
+        let (client, created) = match self.take_idle().await {
+            Some(idle) => idle,
+            None => {
+                debug!("Open a new pooled connection.");
+                let client = Client::new_keep_alive((self.inner.connect)().await?);
+                (client, Instant::now())
+            }
+        };
+
+        Ok(PooledClient {
+            client: Some(client),
+            created,
+            broken: false,
+            pool: self.inner.clone(),
+            _permit: permit,
+        })
+    }
+
+    /// Opens connections until `connections` are idle in the pool, at most
+    /// the maximum size, so the first requests don't wait for them.
+    ///
+    /// Connections are opened concurrently, each taking a connection slot
+    /// like a request. With `query_capabilities` each one also queries
+    /// [Client::capabilities], which checks that the application answers and
+    /// caches its capabilities on the client.
+    ///
+    /// # Arguments
+    ///
+    /// * `connections` - The number of idle connections to reach
+    /// * `query_capabilities` - Whether to query the capabilities
+    pub async fn warm_up(&self, connections: usize, query_capabilities: bool) -> ClientResult<()> {
+        let missing = connections
+            .min(self.inner.max_size)
+            .saturating_sub(self.idle_count());
+        debug!(missing, "Warm up the pool.");
+        try_join_all((0..missing).map(|_| async {
+            let permit = self.acquire().await;
+            let mut client = Client::new_keep_alive((self.inner.connect)().await?);
+            if query_capabilities {
+                client.capabilities().await?;
+            }
+            drop(PooledClient {
+                client: Some(client),
+                created: Instant::now(),
+                broken: false,
+                pool: self.inner.clone(),
+                _permit: permit,
+            });
+            ClientResult::Ok(())
+        }))
+        .await?;
+        Ok(())
+    }
+
+    /// Sends a request with a client from the pool, see
+    /// [PooledClient::execute].
+    ///
+    /// The connection goes back to the pool if the request succeeds and is
+    /// closed if it fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The request to send
+    pub async fn execute<I: AsyncRead + Unpin, D: AsyncRead + Unpin>(
+        &self, request: Request<'_, I, D>,
+    ) -> ClientResult<Response> {
+        self.get().await?.execute(request).await
+    }
+
+    /// Sends a request with a client from the pool, retrying it as allowed by
+    /// the policy.
+    ///
+    /// `request` builds the request for each attempt, since its stdin is
+    /// consumed by sending it. A connection that failed is dropped, so a
+    /// request whose connection was closed before the response started is
+    /// retried on a new one.
+    pub async fn execute_with_retry<'a, I, D, F>(
+        &self, policy: RetryPolicy, mut request: F,
+    ) -> ClientResult<Response>
+    where
+        I: AsyncRead + Unpin,
+        D: AsyncRead + Unpin,
+        F: FnMut() -> Request<'a, I, D>,
+    {
+        let mut attempt = 1;
+        loop {
+            let mut client = self.get().await?;
+            let mut response_started = false;
+            let result = client
+                .deref_mut()
+                .execute_tracked(request(), &mut response_started)
+                .await;
+            if result.is_err() {
+                client.discard();
+            }
+            drop(client);
+
+            match result {
+                Err(err) if policy.should_retry(attempt, &err, response_started, true) => {
+                    debug!(attempt, ?err, "Retry request.");
+                    tokio::time::sleep(policy.delay(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Pings the idle connections unused for longer than
+    /// [Pool::probe_after], closing those that fail, so they are found
+    /// before a request needs them.
+    ///
+    /// Call it periodically, for example from a task driven by
+    /// `tokio::time::interval`. Each ping takes a connection slot like a
+    /// request, so connections in use are never probed and the pool size
+    /// limit holds. Does nothing if [Pool::probe_after] isn't set.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub async fn probe_idle(&self) {
+        let Some(probe_after) = self.inner.probe_after else {
+            return;
+        };
+        for _ in 0..self.idle_count() {
+            let _permit = self.acquire().await;
+            let Some(Idle {
+                mut client,
+                since,
+                created,
+            }) = self.inner.idle.lock().unwrap().pop_front()
+            else {
+                return;
+            };
+            if client.last_used().elapsed() >= probe_after {
+                if let Err(err) = client.ping().await {
+                    debug!(?err, "Drop a pooled connection failing the ping.");
+                    continue;
+                }
+            }
+            self.inner.idle.lock().unwrap().push_back(Idle {
+                client,
+                since,
+                created,
+            });
+        }
+    }
+
+    /// Pops idle clients until a healthy one that hasn't timed out is found,
+    /// pinging it first if it has been unused for too long, and returns it
+    /// with when its connection was opened.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    async fn take_idle(&self) -> Option<(Client<S, KeepAlive>, Instant)> {
+        loop {
+            let Idle {
+                mut client,
+                since,
+                created,
+            } = self.inner.idle.lock().unwrap().pop_front()?;
+            if let Some(idle_timeout) = self.inner.idle_timeout {
+                if since.elapsed() >= idle_timeout {
+                    debug!("Close an idle pooled connection.");
+                    continue;
+                }
+            }
+            if self.inner.is_expired(created) {
+                debug!("Close an expired pooled connection.");
+                continue;
+            }
+            if !client.is_healthy() {
+                debug!("Drop a dead pooled connection.");
+                continue;
+            }
+            if let Some(probe_after) = self.inner.probe_after {
+                if client.last_used().elapsed() >= probe_after {
+                    if let Err(err) = client.ping().await {
+                        debug!(?err, "Drop a pooled connection failing the ping.");
+                        continue;
+                    }
+                }
+            }
+            return Some((client, created));
+        }
+    }
+
+    /// Waits for a connection slot.
+    async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.inner
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("pool semaphore is never closed")
+    }
+
+    /// Returns the inner state of a pool that hasn't been cloned yet.
+    fn inner_mut(&mut self) -> &mut Inner<S> {
+        Arc::get_mut(&mut self.inner).expect("pool must be configured before it is cloned")
+    }
+}
+
+impl<S> Clone for Pool<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// Client taken from a [Pool], returned to it on drop.
+///
+/// Dereferences to [Client], and [PooledClient::execute] additionally keeps
+/// a connection that failed a request out of the pool. A client is only
+/// returned if it is still [reusable](Client::is_reusable).
+pub struct PooledClient<S> {
+    client: Option<Client<S, KeepAlive>>,
+    created: Instant,
+    broken: bool,
+    pool: Arc<Inner<S>>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> PooledClient<S> {
+    /// Send request and receive response from fastcgi server, see
+    /// [Client::execute].
+    ///
+    /// If the request fails in a way that leaves the connection out of sync,
+    /// or its future is dropped before it completes, the connection isn't
+    /// returned to the pool. Requests the server ended with an error status
+    /// keep it.
+    pub async fn execute<I: AsyncRead + Unpin, D: AsyncRead + Unpin>(
+        &mut self, request: Request<'_, I, D>,
+    ) -> ClientResult<Response> {
+        // Stays set if the future is dropped halfway through the request.
+        let broken = mem::replace(&mut self.broken, true);
+        let result = self.deref_mut().execute(request).await;
+        self.broken = broken || result.as_ref().is_err_and(|err| !err.keeps_connection());
+        result
+    }
+
+    /// Keeps the connection out of the pool when this client is dropped, for
+    /// example after a response that couldn't be used.
+    pub fn discard(&mut self) {
+        self.broken = true;
+    }
+}
+
+impl<S> Deref for PooledClient<S> {
+    type Target = Client<S, KeepAlive>;
+
+    fn deref(&self) -> &Self::Target {
+        self.client.as_ref().unwrap()
+    }
+}
+
+impl<S> DerefMut for PooledClient<S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.client.as_mut().unwrap()
+    }
+}
+
+impl<S> Drop for PooledClient<S> {
+    fn drop(&mut self) {
+        let Some(client) = self.client.take() else {
+            return;
+        };
+        if self.broken || client.is_broken() || !client.is_reusable() {
+            debug!("Drop a pooled connection that can't be reused.");
+            return;
+        }
+        if self.pool.is_expired(self.created) {
+            debug!("Close an expired pooled connection.");
+            return;
+        }
+        self.pool.idle.lock().unwrap().push_back(Idle {
+            client,
+            since: Instant::now(),
+            created: self.created,
+        });
+    }
+}
@@ -17,16 +17,35 @@
 //! This module provides the `Request` struct that encapsulates
 //! the parameters and stdin data for a FastCGI request.
 
-use crate::Params;
-use tokio::io::AsyncRead;
+use crate::{meta::Role, Params};
+use bytes::Bytes;
+use std::io::Cursor;
+use tokio::io::{self, AsyncRead};
+
+/// Callback receiving the stderr of a request as it arrives, see
+/// [Request::stderr_handler].
+pub(crate) type StderrHandler = Box<dyn FnMut(Bytes) + Send>;
 
 /// FastCGI request containing parameters and stdin data.
 ///
 /// This structure represents a complete FastCGI request with all necessary
-/// parameters and an optional stdin stream for request body data.
-pub struct Request<'a, I: AsyncRead + Unpin> {
+/// parameters and an optional stdin stream for request body data. Requests
+/// for the `Filter` role also carry a data stream, see
+/// [Request::new_filter].
+///
+/// Requests built with [Request::new] use the client's default role, which
+/// is `Responder` unless set with [crate::ClientBuilder::default_role].
+pub struct Request<'a, I: AsyncRead + Unpin, D: AsyncRead + Unpin = io::Empty> {
     pub(crate) params: Params<'a>,
+    pub(crate) raw_params: Option<Bytes>,
     pub(crate) stdin: I,
+    pub(crate) data: D,
+    pub(crate) role: Option<Role>,
+    pub(crate) keep_alive: Option<bool>,
+    pub(crate) expected_response_size: Option<usize>,
+    pub(crate) stderr_handler: Option<StderrHandler>,
+    pub(crate) max_stdout_bytes: Option<usize>,
+    pub(crate) max_stderr_bytes: Option<usize>,
 }
 
 impl<'a, I: AsyncRead + Unpin> Request<'a, I> {
@@ -37,7 +56,217 @@ impl<'a, I: AsyncRead + Unpin> Request<'a, I> {
     /// * `params` - The FastCGI parameters
     /// * `stdin` - The stdin stream for request body data
     pub fn new(params: Params<'a>, stdin: I) -> Self {
-        Self { params, stdin }
+        Self {
+            params,
+            raw_params: None,
+            stdin,
+            data: io::empty(),
+            role: None,
+            keep_alive: None,
+            expected_response_size: None,
+            stderr_handler: None,
+            max_stdout_bytes: None,
+            max_stderr_bytes: None,
+        }
+    }
+}
+
+impl<'a> Request<'a, Cursor<Bytes>> {
+    /// Creates a new FastCGI request with a body already in memory, such as
+    /// a JSON payload.
+    ///
+    /// `CONTENT_LENGTH` is set to the length of the body unless the params
+    /// already have it.
+    ///
+    /// # Arguments
+    ///
+    /// * `params` - The FastCGI parameters
+    /// * `body` - The request body
+    pub fn with_body_bytes(params: Params<'a>, body: impl Into<Bytes>) -> Self {
+        let body = body.into();
+        let params = if params.contains_key(&b"CONTENT_LENGTH"[..]) {
+            params
+        } else {
+            params.content_length(body.len())
+        };
+        Self::new(params, Cursor::new(body))
+    }
+}
+
+impl<'a, I: AsyncRead + Unpin, D: AsyncRead + Unpin> Request<'a, I, D> {
+    /// Creates a new FastCGI request for the `Filter` role, which sends the
+    /// file to filter as a data stream after stdin.
+    ///
+    /// Set `FCGI_DATA_LAST_MOD` and `FCGI_DATA_LENGTH` with
+    /// [Params::data_last_mod] and [Params::data_length].
+    ///
+    /// # Arguments
+    ///
+    /// * `params` - The FastCGI parameters
+    /// * `stdin` - The stdin stream for request body data
+    /// * `data` - The data stream for the file to filter
+    pub fn new_filter(params: Params<'a>, stdin: I, data: D) -> Self {
+        Self {
+            params,
+            raw_params: None,
+            stdin,
+            data,
+            role: Some(Role::Filter),
+            keep_alive: None,
+            expected_response_size: None,
+            stderr_handler: None,
+            max_stdout_bytes: None,
+            max_stderr_bytes: None,
+        }
+    }
+
+    /// Converts the params into owned ones, so the request can be moved into
+    /// a spawned task when its streams are `'static`.
+    pub fn into_owned(self) -> Request<'static, I, D> {
+        Request {
+            params: self.params.into_owned(),
+            raw_params: self.raw_params,
+            stdin: self.stdin,
+            data: self.data,
+            role: self.role,
+            keep_alive: self.keep_alive,
+            expected_response_size: self.expected_response_size,
+            stderr_handler: self.stderr_handler,
+            max_stdout_bytes: self.max_stdout_bytes,
+            max_stderr_bytes: self.max_stderr_bytes,
+        }
+    }
+
+    /// Returns the role the application is asked to play, `None` if the
+    /// client's default role is used.
+    pub fn role(&self) -> Option<Role> {
+        self.role
+    }
+
+    /// Overrides the connection mode of the client for this request, setting
+    /// whether the server should keep the connection open after it.
+    ///
+    /// Setting it to `false` on a keep alive client lets the server close
+    /// the connection after the response, and the client is no longer
+    /// reusable, see [crate::Client::is_reusable].
+    ///
+    /// # Arguments
+    ///
+    /// * `keep_alive` - Whether to keep the connection open
+    pub fn with_keep_alive(mut self, keep_alive: bool) -> Self {
+        self.keep_alive = Some(keep_alive);
+        self
+    }
+
+    /// Returns the keep alive override, `None` if the client's connection
+    /// mode is used.
+    pub fn keep_alive(&self) -> Option<bool> {
+        self.keep_alive
+    }
+
+    /// Reserves stdout capacity for a response of about this size, to avoid
+    /// growing the buffer while the response arrives.
+    ///
+    /// This is only a hint for [crate::Client::execute] and
+    /// [crate::Client::execute_once], responses of any size are still read
+    /// whole. The reservation never exceeds the response size limit, see
+    /// [crate::ClientBuilder::max_response_bytes].
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - The expected stdout length in bytes
+    pub fn with_expected_response_size(mut self, size: usize) -> Self {
+        self.expected_response_size = Some(size);
+        self
+    }
+
+    /// Returns the expected response size set with
+    /// [Request::with_expected_response_size].
+    pub fn expected_response_size(&self) -> Option<usize> {
+        self.expected_response_size
+    }
+
+    /// Set the maximum number of stdout bytes accepted for this request, on
+    /// top of the client's [crate::ClientBuilder::max_response_bytes].
+    ///
+    /// Exceeding it fails `execute` and `execute_once` with
+    /// [crate::ClientError::OutputTooLarge], carrying the output received so
+    /// far, and the connection is not reused.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_stdout_bytes` - The limit in bytes
+    pub fn with_max_stdout_bytes(mut self, max_stdout_bytes: usize) -> Self {
+        self.max_stdout_bytes = Some(max_stdout_bytes);
+        self
+    }
+
+    /// Returns the stdout limit set with [Request::with_max_stdout_bytes].
+    pub fn max_stdout_bytes(&self) -> Option<usize> {
+        self.max_stdout_bytes
+    }
+
+    /// Set the maximum number of stderr bytes accepted for this request, on
+    /// top of the client's [crate::ClientBuilder::max_stderr_bytes].
+    ///
+    /// Checked like [Request::with_max_stdout_bytes], stderr passed to a
+    /// [Request::stderr_handler] counts towards it.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_stderr_bytes` - The limit in bytes
+    pub fn with_max_stderr_bytes(mut self, max_stderr_bytes: usize) -> Self {
+        self.max_stderr_bytes = Some(max_stderr_bytes);
+        self
+    }
+
+    /// Returns the stderr limit set with [Request::with_max_stderr_bytes].
+    pub fn max_stderr_bytes(&self) -> Option<usize> {
+        self.max_stderr_bytes
+    }
+
+    /// Passes the stderr of the response to `handler` record by record as it
+    /// arrives, for example to forward it to a log, instead of collecting it
+    /// into [crate::Response::stderr].
+    ///
+    /// The handler is called by [crate::Client::execute] and
+    /// [crate::Client::execute_once], the stderr it receives doesn't count
+    /// towards [crate::ClientBuilder::max_stderr_bytes]. Response streams
+    /// already yield stderr as it arrives and ignore it.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - Called with the content of each stderr record
+    pub fn stderr_handler(mut self, handler: impl FnMut(Bytes) + Send + 'static) -> Self {
+        self.stderr_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Returns whether a handler was set with [Request::stderr_handler].
+    pub fn has_stderr_handler(&self) -> bool {
+        self.stderr_handler.is_some()
+    }
+
+    /// Sends already encoded params instead of the [Params] of the request,
+    /// for example a params block cached for repeated requests to the same
+    /// script.
+    ///
+    /// The bytes are sent verbatim as `Params` records, split at the maximum
+    /// record length and followed by the empty record ending the stream. They
+    /// must be valid FastCGI name-value pairs, they aren't checked. The
+    /// [Params] of the request are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `raw_params` - The encoded name-value pairs
+    pub fn with_raw_params(mut self, raw_params: Bytes) -> Self {
+        self.raw_params = Some(raw_params);
+        self
+    }
+
+    /// Returns the encoded params set with [Request::with_raw_params].
+    pub fn raw_params(&self) -> Option<&Bytes> {
+        self.raw_params.as_ref()
     }
 
     /// Returns a reference to the request parameters.
@@ -59,4 +288,14 @@ impl<'a, I: AsyncRead + Unpin> Request<'a, I> {
     pub fn stdin_mut(&mut self) -> &mut I {
         &mut self.stdin
     }
+
+    /// Returns a reference to the data stream.
+    pub fn data(&self) -> &D {
+        &self.data
+    }
+
+    /// Returns a mutable reference to the data stream.
+    pub fn data_mut(&mut self) -> &mut D {
+        &mut self.data
+    }
 }
@@ -18,23 +18,42 @@
 //! including both complete responses and streaming responses.
 
 use std::{
+    cmp::min,
+    collections::HashMap,
     fmt::{self, Debug},
+    future::Future,
+    io,
     pin::Pin,
     str,
-    task::Poll,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    task::{ready, Context, Poll},
+    time::Duration,
 };
 
-use bytes::{Bytes, BytesMut};
-use futures_util::stream::Stream;
-use tokio::io::AsyncRead;
-use tokio_util::io::ReaderStream;
-use tracing::debug;
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::stream::{Stream, StreamExt};
+use tokio::{
+    io::{AsyncBufRead, AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf},
+    time::{sleep, Sleep},
+};
+use tokio_util::io::poll_read_buf;
 
 use crate::{
-    meta::{EndRequestRec, Header, RequestType, HEADER_LEN},
+    client::{Config, RecordTap},
+    meta::{
+        decode_record, Direction, EndRequestRec, Header, RequestType, Role, UnknownTypeRec,
+        HEADER_LEN,
+    },
+    trace::{debug, warning, Span},
     ClientError, ClientResult,
 };
 
+/// Capacity reserved in the read buffer before each poll of the stream.
+const READ_CAPACITY: usize = 4096;
+
 /// Output of FastCGI request, contains STDOUT and STDERR data.
 ///
 /// This structure represents a complete FastCGI response with
@@ -44,8 +63,14 @@ use crate::{
 pub struct Response {
     /// The stdout output from the FastCGI server
     pub stdout: Option<Bytes>,
-    /// The stderr output from the FastCGI server
+    /// The stderr output from the FastCGI server, all `Stderr` records
+    /// concatenated in arrival order, also when the request succeeded; `None`
+    /// if no stderr bytes were received
     pub stderr: Option<Bytes>,
+    /// The application status (exit code of the script) from `EndRequest`
+    pub app_status: u32,
+    /// The request id the response belongs to
+    pub request_id: u16,
 }
 
 impl Debug for Response {
@@ -53,10 +78,276 @@ impl Debug for Response {
         f.debug_struct("Response")
             .field("stdout", &self.stdout.as_deref().map(str::from_utf8))
             .field("stderr", &self.stderr.as_deref().map(str::from_utf8))
+            .field("app_status", &self.app_status)
+            .field("request_id", &self.request_id)
             .finish()
     }
 }
 
+impl Response {
+    /// Parses the CGI response in stdout into a status, headers and body.
+    ///
+    /// The header block ends at the first empty line (`\r\n\r\n` or
+    /// `\n\n`); output without one is treated as headers only. Folded header
+    /// lines are joined to the previous value, and a `Status:` header is
+    /// removed from the headers and mapped to [ResponseParts::status], which
+    /// defaults to 200.
+    pub fn parse(&self) -> ClientResult<ResponseParts> {
+        let stdout = self.stdout.clone().unwrap_or_default();
+
+        let (head, body) = match find_header_end(&stdout) {
+            Some((end, separator_len)) => {
+                (stdout.slice(..end), stdout.slice(end + separator_len..))
+            }
+            None => (stdout, Bytes::new()),
+        };
+
+        let mut parts = ResponseParts {
+            status: 200,
+            reason: None,
+            headers: Vec::new(),
+            body,
+        };
+
+        for (name, value) in parse_headers(&head)? {
+            if name.eq_ignore_ascii_case("Status") {
+                let (status, reason) = parse_status(&value)?;
+                parts.status = status;
+                parts.reason = reason;
+            } else {
+                parts.headers.push((name, value));
+            }
+        }
+
+        Ok(parts)
+    }
+
+    /// Returns the status code from the `Status:` header of the CGI
+    /// response, 200 when absent, without splitting off the body.
+    ///
+    /// A `Status:` value without a reason phrase, such as `404`, is
+    /// accepted. A malformed status or header block is reported as
+    /// [ClientError::InvalidCgiHeader], like [Response::parse].
+    pub fn status_code(&self) -> ClientResult<u16> {
+        let stdout = self.stdout.as_deref().unwrap_or_default();
+        let head = match find_header_end(stdout) {
+            Some((end, _)) => &stdout[..end],
+            None => stdout,
+        };
+
+        for (name, value) in parse_headers(head)? {
+            if name.eq_ignore_ascii_case("Status") {
+                return parse_status(&value).map(|(status, _)| status);
+            }
+        }
+        Ok(200)
+    }
+}
+
+/// CGI response parsed from stdout by [Response::parse].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ResponseParts {
+    /// Status code from the `Status:` header, 200 when absent
+    pub status: u16,
+    /// Reason phrase from the `Status:` header, if any
+    pub reason: Option<String>,
+    /// Response headers in the order they were sent, without `Status:`
+    pub headers: Vec<(String, String)>,
+    /// Response body after the header block
+    pub body: Bytes,
+}
+
+#[cfg(feature = "http")]
+impl ResponseParts {
+    /// Returns the headers as an `http::HeaderMap`, keeping repeated headers
+    /// in order.
+    ///
+    /// Fails with [ClientError::InvalidCgiHeader] on a name or value that
+    /// isn't valid in HTTP.
+    pub fn header_map(&self) -> ClientResult<http::HeaderMap> {
+        let mut headers = http::HeaderMap::with_capacity(self.headers.len());
+        for (name, value) in &self.headers {
+            let invalid = || ClientError::InvalidCgiHeader {
+                line: format!("{name}: {value}"),
+            };
+            headers.append(
+                http::HeaderName::try_from(name.as_str()).map_err(|_| invalid())?,
+                http::HeaderValue::try_from(value.as_str()).map_err(|_| invalid())?,
+            );
+        }
+        Ok(headers)
+    }
+
+    /// Converts the parts into an `http::Response` with the status, headers
+    /// and body, for example to answer from a hyper or axum server.
+    ///
+    /// Fails with [ClientError::InvalidCgiHeader] if the status or a header
+    /// isn't valid in HTTP.
+    pub fn into_http(self) -> ClientResult<http::Response<Bytes>> {
+        let headers = self.header_map()?;
+        let status =
+            http::StatusCode::from_u16(self.status).map_err(|_| ClientError::InvalidCgiHeader {
+                line: format!("Status: {}", self.status),
+            })?;
+        let mut response = http::Response::new(self.body);
+        *response.status_mut() = status;
+        *response.headers_mut() = headers;
+        Ok(response)
+    }
+}
+
+/// Headers of one connection, which a gateway sets itself rather than pass
+/// on from the application.
+#[cfg(feature = "http")]
+const HOP_BY_HOP_HEADERS: [http::HeaderName; 4] = [
+    http::header::CONNECTION,
+    http::header::TRANSFER_ENCODING,
+    http::header::UPGRADE,
+    http::HeaderName::from_static("keep-alive"),
+];
+
+#[cfg(feature = "http")]
+impl TryFrom<Response> for http::Response<Bytes> {
+    type Error = ClientError;
+
+    /// Parses the CGI response in stdout with [Response::parse] into an
+    /// `http::Response` ready to return from a handler: `Status:` sets the
+    /// status code, and the hop-by-hop headers `Connection`, `Keep-Alive`,
+    /// `Transfer-Encoding` and `Upgrade` are removed.
+    fn try_from(response: Response) -> ClientResult<Self> {
+        let mut response = response.parse()?.into_http()?;
+        for name in HOP_BY_HOP_HEADERS {
+            response.headers_mut().remove(name);
+        }
+        Ok(response)
+    }
+}
+
+/// Outcome of a request whose stdout was written to a sink, returned by
+/// [Client::execute_into](crate::client::Client::execute_into).
+#[derive(Debug, Default, Clone)]
+#[non_exhaustive]
+pub struct ResponseSummary {
+    /// The request id the response belongs to
+    pub request_id: u16,
+    /// The application status (exit code of the script) from `EndRequest`
+    pub app_status: u32,
+    /// The number of stdout bytes written to the sink
+    pub stdout_len: u64,
+    /// The stderr output from the FastCGI server, `None` if no stderr bytes
+    /// were received or they were passed to a stderr handler
+    pub stderr: Option<Bytes>,
+}
+
+/// Decision of an Authorizer application, returned by
+/// [Client::authorize](crate::client::Client::authorize).
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct AuthResponse {
+    /// Whether access is allowed, only when the status is 200
+    pub allowed: bool,
+    /// The parsed CGI response, for the status and headers of a denial
+    pub parts: ResponseParts,
+    /// Variables from the `Variable-*` headers, named without the prefix,
+    /// to pass on to the following requests
+    pub variables: HashMap<String, String>,
+}
+
+impl AuthResponse {
+    /// Classifies the response of an Authorizer application.
+    ///
+    /// # Arguments
+    ///
+    /// * `parts` - The parsed CGI response
+    pub(crate) fn new(parts: ResponseParts) -> Self {
+        let variables = parts
+            .headers
+            .iter()
+            .filter_map(|(name, value)| {
+                let prefix = name.get(..AUTH_VARIABLE_PREFIX.len())?;
+                if !prefix.eq_ignore_ascii_case(AUTH_VARIABLE_PREFIX) {
+                    return None;
+                }
+                Some((name[prefix.len()..].to_owned(), value.clone()))
+            })
+            .collect();
+        Self {
+            allowed: parts.status == 200,
+            parts,
+            variables,
+        }
+    }
+}
+
+/// Prefix of the headers an Authorizer application uses to pass variables.
+const AUTH_VARIABLE_PREFIX: &str = "Variable-";
+
+/// Finds the empty line ending the header block, returning its offset and
+/// the length of the separator.
+fn find_header_end(buf: &[u8]) -> Option<(usize, usize)> {
+    let crlf = buf.windows(4).position(|w| w == b"\r\n\r\n");
+    let lf = buf.windows(2).position(|w| w == b"\n\n");
+    match (crlf, lf) {
+        (Some(crlf), Some(lf)) if lf < crlf => Some((lf, 2)),
+        (Some(crlf), _) => Some((crlf, 4)),
+        (None, Some(lf)) => Some((lf, 2)),
+        (None, None) => None,
+    }
+}
+
+/// Parses `Name: value` lines, joining folded lines onto the previous value.
+fn parse_headers(head: &[u8]) -> ClientResult<Vec<(String, String)>> {
+    let head = str::from_utf8(head).map_err(|_| ClientError::InvalidCgiHeader {
+        line: String::from_utf8_lossy(head).into_owned(),
+    })?;
+
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in head.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with([' ', '\t']) {
+            let Some((_, value)) = headers.last_mut() else {
+                return Err(ClientError::InvalidCgiHeader { line: line.into() });
+            };
+            value.push(' ');
+            value.push_str(line.trim());
+            continue;
+        }
+
+        match line.split_once(':') {
+            Some((name, value)) if !name.is_empty() && !name.contains([' ', '\t']) => {
+                headers.push((name.to_owned(), value.trim().to_owned()));
+            }
+            _ => return Err(ClientError::InvalidCgiHeader { line: line.into() }),
+        }
+    }
+    Ok(headers)
+}
+
+/// Parses a `Status:` value such as `404 Not Found` or `404`.
+fn parse_status(value: &str) -> ClientResult<(u16, Option<String>)> {
+    let (code, reason) = match value.split_once(' ') {
+        Some((code, reason)) => (code, Some(reason.trim())),
+        None => (value, None),
+    };
+
+    let status = match code.parse::<u16>() {
+        Ok(status) if code.len() == 3 && (100..=999).contains(&status) => status,
+        _ => {
+            return Err(ClientError::InvalidCgiHeader {
+                line: format!("Status: {value}"),
+            });
+        }
+    };
+
+    let reason = reason.filter(|reason| !reason.is_empty()).map(Into::into);
+    Ok((status, reason))
+}
+
 /// Content type from a FastCGI response stream.
 ///
 /// This enum represents the different types of content that can be
@@ -68,6 +359,32 @@ pub enum Content {
     Stderr(Bytes),
 }
 
+/// An event of a FastCGI response, yielded by [ResponseEvents] in the order
+/// it arrived.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// Standard output content from the FastCGI server
+    Stdout(Bytes),
+    /// Standard error content from the FastCGI server
+    Stderr(Bytes),
+    /// The `EndRequest` of a request the server completed
+    End {
+        /// The application status, the exit code of the script
+        app_status: u32,
+    },
+}
+
+/// State a response stream shares with the keep alive client that sent its
+/// request.
+#[derive(Debug, Default)]
+pub(crate) struct StreamShared {
+    /// The number of records of other requests skipped by the streams
+    pub(crate) skipped_records: AtomicU64,
+    /// Whether a stream was dropped before the end of its response, leaving
+    /// the connection out of sync
+    pub(crate) broken: AtomicBool,
+}
+
 /// A streaming response from a FastCGI server.
 ///
 /// Generated by
@@ -75,12 +392,36 @@ pub enum Content {
 /// [Client::execute_stream](crate::client::Client::execute_stream).
 ///
 /// This stream yields `Content` items as they are received from the server.
+///
+/// Dropping the stream before it is exhausted leaves the rest of the
+/// response on the connection, and marks the client broken, see
+/// [Client::is_broken](crate::client::Client::is_broken); use
+/// [ResponseStream::abort] to cancel the request and drain it instead.
 pub struct ResponseStream<S: AsyncRead + Unpin> {
-    stream: ReaderStream<S>,
+    stream: S,
     id: u16,
+    role: Role,
     eof: bool,
+    /// Whether the `EndRequest` was read, with nothing after it.
+    ended: bool,
+    /// The application status of the `EndRequest`, once it is received.
+    app_status: Option<u32>,
     header: Option<Header>,
     buf: BytesMut,
+    read_timeout: Option<Duration>,
+    strict_padding: bool,
+    strict: bool,
+    /// The ID of the previous request on the connection, 0 if none, whose
+    /// records arriving late are discarded
+    previous_id: u16,
+    skip_unknown_ids: bool,
+    /// Shared with the client, `None` once the client is consumed
+    shared: Option<Arc<StreamShared>>,
+    on_record: Option<RecordTap>,
+    /// Deadline of the pending read, armed while waiting for data.
+    deadline: Option<Pin<Box<Sleep>>>,
+    /// The span of the request, entered while polling.
+    span: Span,
 }
 
 impl<S: AsyncRead + Unpin> ResponseStream<S> {
@@ -90,43 +431,123 @@ impl<S: AsyncRead + Unpin> ResponseStream<S> {
     ///
     /// * `stream` - The underlying stream to read from
     /// * `id` - The request ID for this response
+    /// * `role` - The role of the request, reported if it is rejected
+    /// * `config` - The client settings, for the read timeout, padding check
+    ///   and record tap
+    /// * `span` - The span of the request
     #[inline]
-    pub(crate) fn new(stream: S, id: u16) -> Self {
+    pub(crate) fn new(stream: S, id: u16, role: Role, config: &Config, span: Span) -> Self {
         Self {
-            stream: ReaderStream::new(stream),
+            stream,
             id,
+            role,
             eof: false,
+            ended: false,
+            app_status: None,
             header: None,
             buf: BytesMut::new(),
+            read_timeout: config.read_timeout,
+            strict_padding: config.strict_padding,
+            strict: config.strict,
+            previous_id: 0,
+            skip_unknown_ids: config.skip_unknown_ids,
+            shared: None,
+            on_record: config.on_record.clone(),
+            deadline: None,
+            span,
         }
     }
 
-    /// Reads a FastCGI header from the buffer.
+    /// Sets the bytes of the response read while the request was being
+    /// sent, parsed before reading the stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - The bytes read ahead
+    #[inline]
+    pub(crate) fn read_ahead(mut self, buf: BytesMut) -> Self {
+        self.buf = buf;
+        self
+    }
+
+    /// Sets the client state shared with the stream, to discard the late
+    /// records of the previous request and count the skipped records.
+    ///
+    /// # Arguments
     ///
-    /// Returns `None` if there isn't enough data in the buffer.
+    /// * `previous_id` - The ID of the previous request, 0 if none
+    /// * `shared` - The state shared with the client
     #[inline]
-    fn read_header(&mut self) -> Option<Header> {
-        if self.buf.len() < HEADER_LEN {
-            return None;
+    pub(crate) fn shared(mut self, previous_id: u16, shared: Arc<StreamShared>) -> Self {
+        self.previous_id = previous_id;
+        self.shared = Some(shared);
+        self
+    }
+
+    /// Returns the request id the response belongs to.
+    pub fn request_id(&self) -> u16 {
+        self.id
+    }
+
+    /// Converts the stream into a [ResponseReader] over stdout, to use it with
+    /// `tokio::io::copy`, `read_line` and the like.
+    pub fn into_reader(self) -> ResponseReader<S> {
+        ResponseReader {
+            stream: self,
+            stdout: Bytes::new(),
+            stderr: BytesMut::new(),
+        }
+    }
+
+    /// Converts the stream into a [ResponseBody] over stdout, to forward it
+    /// as an `http_body::Body` such as the body of a hyper response.
+    #[cfg(feature = "http")]
+    pub fn into_body(self) -> ResponseBody<S> {
+        ResponseBody {
+            stream: self,
+            stderr: BytesMut::new(),
+        }
+    }
+
+    /// Converts the stream into a [ResponseEvents] stream, which also yields
+    /// the `EndRequest` after the last stdout and stderr.
+    pub fn into_events(self) -> ResponseEvents<S> {
+        ResponseEvents {
+            stream: self,
+            ended: false,
         }
-        let buf = self.buf.split_to(HEADER_LEN);
-        Some(Header::from(buf))
     }
 
-    /// Reads content from the buffer based on the current header.
+    /// Passes the header of a record to the record tap, if one is set.
+    ///
+    /// # Arguments
     ///
-    /// Returns `None` if there isn't enough data in the buffer.
+    /// * `direction` - Whether the record is read or written
+    /// * `header` - The header of the record
     #[inline]
-    fn read_content(&mut self) -> Option<BytesMut> {
-        let header = self.header.as_ref().unwrap();
-        let block_length = header.content_length as usize + header.padding_length as usize;
-        if self.buf.len() < block_length {
-            return None;
+    fn tap(&self, direction: Direction, header: &Header) {
+        if let Some(on_record) = &self.on_record {
+            on_record(direction, header);
+        }
+    }
+
+    /// Builds the error for a connection closed before `EndRequest`, based
+    /// on what is left in the buffer.
+    fn connection_closed(&self) -> ClientError {
+        match &self.header {
+            Some(header) => ClientError::ConnectionClosed {
+                expected: header.content_length as usize + header.padding_length as usize,
+                received: self.buf.len(),
+                request_type: Some(header.r#type),
+                stdout: None,
+            },
+            None => ClientError::ConnectionClosed {
+                expected: HEADER_LEN,
+                received: self.buf.len(),
+                request_type: None,
+                stdout: None,
+            },
         }
-        let content = self.buf.split_to(header.content_length as usize);
-        let _ = self.buf.split_to(header.padding_length as usize);
-        self.header = None;
-        Some(content)
     }
 
     /// Processes a complete FastCGI message from the buffer.
@@ -134,50 +555,115 @@ impl<S: AsyncRead + Unpin> ResponseStream<S> {
     /// Returns `Ok(Some(Content))` if a complete message was processed,
     /// `Ok(None)` if more data is needed, or an error if processing failed.
     fn process_message(&mut self) -> Result<Option<Content>, ClientError> {
-        if self.buf.is_empty() {
-            return Ok(None);
+        let result = self.decode_message();
+        if result.is_err() {
+            self.eof = true;
         }
-        if self.header.is_none() {
-            match self.read_header() {
-                Some(header) => self.header = Some(header),
-                None => return Ok(None),
-            }
-        }
-        let header = self.header.as_ref().unwrap();
-        match header.r#type.clone() {
-            RequestType::Stdout => {
-                if let Some(data) = self.read_content() {
-                    return Ok(Some(Content::Stdout(data.freeze())));
+        result
+    }
+
+    /// Decodes the records in the buffer up to the next content of the
+    /// response or its end.
+    fn decode_message(&mut self) -> ClientResult<Option<Content>> {
+        loop {
+            let Some((header, content)) =
+                decode_record(&mut self.buf, &mut self.header, self.strict_padding)?
+            else {
+                return Ok(None);
+            };
+            header.trace("Read record.");
+            self.tap(Direction::Read, &header);
+
+            if header.request_id != self.id && header.r#type != RequestType::UnknownType {
+                if self.strict {
+                    return Err(ClientError::UnexpectedRecord {
+                        request_type: header.r#type,
+                        request_id: header.request_id,
+                    });
                 }
-            }
-            RequestType::Stderr => {
-                if let Some(data) = self.read_content() {
-                    return Ok(Some(Content::Stderr(data.freeze())));
+                let previous = header.request_id == self.previous_id && self.previous_id != 0;
+                if !previous && !self.skip_unknown_ids {
+                    return Err(ClientError::ResponseNotFound { id: self.id });
                 }
+                warning!(id = self.id, ?header, "Discard record of another request.");
+                if let Some(shared) = &self.shared {
+                    shared.skipped_records.fetch_add(1, Ordering::Relaxed);
+                }
+                continue;
             }
-            RequestType::EndRequest => {
-                let header = header.clone();
-                let Some(data) = self.read_content() else {
-                    return Ok(None);
-                };
-
-                let end = EndRequestRec::new_from_buf(header, data);
-                debug!(id = self.id, ?end, "Receive from stream.");
-
-                self.eof = true;
-                end.end_request
-                    .protocol_status
-                    .convert_to_client_result(end.end_request.app_status)?;
-                return Ok(None);
-            }
-            r#type => {
-                self.eof = true;
-                return Err(ClientError::UnknownRequestType {
+
+            return match header.r#type {
+                RequestType::Stdout => Ok(Some(Content::Stdout(content.freeze()))),
+                RequestType::Stderr => Ok(Some(Content::Stderr(content.freeze()))),
+                RequestType::EndRequest => {
+                    let end = EndRequestRec::new_from_buf(header, content);
+                    debug!(id = self.id, ?end, "Receive from stream.");
+
+                    self.eof = true;
+                    // Bytes already read after the `EndRequest` are lost.
+                    self.ended = self.buf.is_empty();
+                    end.end_request.protocol_status.convert_to_client_result(
+                        self.role,
+                        end.end_request.app_status,
+                        None,
+                        None,
+                    )?;
+                    self.app_status = Some(end.end_request.app_status);
+                    Ok(None)
+                }
+                RequestType::UnknownType => {
+                    let unknown_type = UnknownTypeRec::new_from_buf(header, content);
+                    debug!(id = self.id, ?unknown_type, "Receive from stream.");
+                    Err(ClientError::UnknownType {
+                        type_id: unknown_type.unknown_type.r#type,
+                    })
+                }
+                r#type => Err(ClientError::UnknownRequestType {
                     request_type: r#type,
-                });
+                }),
+            };
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> Drop for ResponseStream<S> {
+    fn drop(&mut self) {
+        if self.ended {
+            return;
+        }
+        if let Some(shared) = &self.shared {
+            debug!(id = self.id, "Drop a response stream before its end.");
+            shared.broken.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> ResponseStream<S> {
+    /// Cancels the request by sending `FCGI_ABORT_REQUEST`, then reads and
+    /// discards the remaining records until the `EndRequest` arrives.
+    ///
+    /// Does nothing if the response has already been read to the end.
+    pub async fn abort(mut self) -> ClientResult<()> {
+        if self.eof {
+            return Ok(());
+        }
+
+        debug!(id = self.id, "Abort request.");
+        let header = Header::new(RequestType::AbortRequest, self.id, &[]);
+        self.tap(Direction::Write, &header);
+        header.write_to_stream(&mut self.stream, &[]).await?;
+        self.stream.flush().await?;
+
+        while let Some(content) = self.next().await {
+            match content {
+                Ok(_)
+                | Err(ClientError::EndRequestCantMpxConn { .. })
+                | Err(ClientError::EndRequestOverloaded { .. })
+                | Err(ClientError::EndRequestUnknownRole { .. }) => {}
+                Err(err) => return Err(err),
             }
         }
-        Ok(None)
+        Ok(())
     }
 }
 
@@ -190,32 +676,258 @@ where
     fn poll_next(
         mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Option<Self::Item>> {
-        let mut pending = false;
+        let span = self.span.clone();
+        let _entered = span.enter();
+
         loop {
-            match Pin::new(&mut self.stream).poll_next(cx) {
-                Poll::Ready(Some(Ok(data))) => {
-                    self.buf.extend_from_slice(&data);
-
-                    match self.process_message() {
-                        Ok(Some(data)) => return Poll::Ready(Some(Ok(data))),
-                        Ok(None) if self.eof => return Poll::Ready(None),
-                        Ok(None) => continue,
-                        Err(err) => return Poll::Ready(Some(Err(err))),
-                    }
+            if self.eof {
+                return Poll::Ready(None);
+            }
+
+            match self.process_message() {
+                // Empty records only terminate the stdout or stderr stream.
+                Ok(Some(Content::Stdout(data) | Content::Stderr(data))) if data.is_empty() => {
+                    continue;
+                }
+                Ok(Some(data)) => return Poll::Ready(Some(Ok(data))),
+                Ok(None) if self.eof => return Poll::Ready(None),
+                Ok(None) => {}
+                Err(err) => return Poll::Ready(Some(Err(err))),
+            }
+
+            let this = &mut *self;
+            this.buf.reserve(READ_CAPACITY);
+            match poll_read_buf(Pin::new(&mut this.stream), cx, &mut this.buf) {
+                Poll::Ready(Ok(0)) => {
+                    this.eof = true;
+                    return Poll::Ready(Some(Err(this.connection_closed())));
                 }
-                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err.into()))),
-                Poll::Ready(None) => break,
-                Poll::Pending => {
-                    pending = true;
-                    break;
+                Poll::Ready(Ok(_)) => {
+                    this.deadline = None;
+                    continue;
                 }
+                Poll::Ready(Err(err)) => return Poll::Ready(Some(Err(err.into()))),
+                Poll::Pending => {}
             }
+
+            let Some(timeout) = this.read_timeout else {
+                return Poll::Pending;
+            };
+            let deadline = this
+                .deadline
+                .get_or_insert_with(|| Box::pin(sleep(timeout)));
+            if deadline.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.eof = true;
+            return Poll::Ready(Some(Err(ClientError::ReadTimeout { timeout })));
         }
-        match self.process_message() {
-            Ok(Some(data)) => Poll::Ready(Some(Ok(data))),
-            Ok(None) if !self.eof && pending => Poll::Pending,
-            Ok(None) => Poll::Ready(None),
-            Err(err) => Poll::Ready(Some(Err(err))),
+    }
+}
+
+/// The events of a streamed response in the order they arrived, created by
+/// [ResponseStream::into_events].
+///
+/// Unlike [Response], which collects stdout and stderr separately, the
+/// events keep them interleaved, to tell where in the output an error was
+/// reported. The stream ends with [Event::End] unless the request failed.
+///
+/// # Examples
+///
+/// ```
+/// use fcgi_client::{response::Event, Client, Params, Request};
+/// use futures_util::StreamExt;
+/// use tokio::{io, net::TcpStream};
+///
+/// async fn events() {
+///     let stream = TcpStream::connect(("127.0.0.1", 9000)).await.unwrap();
+///     let mut events = Client::new(stream)
+///         .execute_once_stream(Request::new(Params::default(), &mut io::empty()))
+///         .await
+///         .unwrap()
+///         .into_events();
+///
+///     while let Some(event) = events.next().await {
+///         match event.unwrap() {
+///             Event::Stdout(out) => todo!(),
+///             Event::Stderr(err) => todo!(),
+///             Event::End { app_status } => todo!(),
+///         }
+///     }
+/// }
+/// ```
+pub struct ResponseEvents<S: AsyncRead + Unpin> {
+    stream: ResponseStream<S>,
+    /// Whether [Event::End] was yielded.
+    ended: bool,
+}
+
+impl<S: AsyncRead + Unpin> ResponseEvents<S> {
+    /// Returns the request id the response belongs to.
+    pub fn request_id(&self) -> u16 {
+        self.stream.id
+    }
+}
+
+impl<S: AsyncRead + Unpin> Stream for ResponseEvents<S> {
+    type Item = ClientResult<Event>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let event = match ready!(this.stream.poll_next_unpin(cx)) {
+            Some(Ok(Content::Stdout(stdout))) => Event::Stdout(stdout),
+            Some(Ok(Content::Stderr(stderr))) => Event::Stderr(stderr),
+            Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+            None => match this.stream.app_status {
+                Some(app_status) if !this.ended => {
+                    this.ended = true;
+                    Event::End { app_status }
+                }
+                _ => return Poll::Ready(None),
+            },
+        };
+        Poll::Ready(Some(Ok(event)))
+    }
+}
+
+/// Stdout of a streamed response as an `http_body::Body`, created by
+/// [ResponseStream::into_body].
+///
+/// Each stdout record becomes a data frame as it arrives, so the output is
+/// forwarded with backpressure and without buffering it whole. The body
+/// ends when the `EndRequest` arrives; trailers aren't used. Stderr records
+/// are collected separately, see [ResponseBody::stderr].
+///
+/// The stdout still starts with the CGI headers, which the caller reads
+/// before forwarding the rest, for example with [Response::parse] on a
+/// buffered response instead.
+#[cfg(feature = "http")]
+pub struct ResponseBody<S: AsyncRead + Unpin> {
+    stream: ResponseStream<S>,
+    stderr: BytesMut,
+}
+
+#[cfg(feature = "http")]
+impl<S: AsyncRead + Unpin> ResponseBody<S> {
+    /// Returns the request id the response belongs to.
+    pub fn request_id(&self) -> u16 {
+        self.stream.id
+    }
+
+    /// Returns the stderr received so far.
+    pub fn stderr(&self) -> &[u8] {
+        &self.stderr
+    }
+}
+
+#[cfg(feature = "http")]
+impl<S: AsyncRead + Unpin> http_body::Body for ResponseBody<S> {
+    type Data = Bytes;
+    type Error = ClientError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>, cx: &mut Context<'_>,
+    ) -> Poll<Option<ClientResult<http_body::Frame<Bytes>>>> {
+        let this = self.get_mut();
+        loop {
+            return match ready!(this.stream.poll_next_unpin(cx)) {
+                Some(Ok(Content::Stdout(stdout))) => {
+                    Poll::Ready(Some(Ok(http_body::Frame::data(stdout))))
+                }
+                Some(Ok(Content::Stderr(stderr))) => {
+                    this.stderr.extend_from_slice(&stderr);
+                    continue;
+                }
+                Some(Err(err)) => Poll::Ready(Some(Err(err))),
+                None => Poll::Ready(None),
+            };
         }
     }
+
+    fn is_end_stream(&self) -> bool {
+        self.stream.eof
+    }
+}
+
+/// Stdout of a streamed response as `AsyncRead` and `AsyncBufRead`, created by
+/// [ResponseStream::into_reader].
+///
+/// The reader reaches EOF when the `EndRequest` arrives. Stderr records are
+/// collected separately, see [ResponseReader::stderr]. Errors other than
+/// [ClientError::Io] are wrapped in an `io::Error` of kind `Other`.
+///
+/// # Examples
+///
+/// ```
+/// use fcgi_client::{Client, Params, Request};
+/// use tokio::{fs::File, io, net::TcpStream};
+///
+/// async fn download() {
+///     let stream = TcpStream::connect(("127.0.0.1", 9000)).await.unwrap();
+///     let mut reader = Client::new(stream)
+///         .execute_once_stream(Request::new(Params::default(), &mut io::empty()))
+///         .await
+///         .unwrap()
+///         .into_reader();
+///
+///     let mut file = File::create("page.html").await.unwrap();
+///     io::copy(&mut reader, &mut file).await.unwrap();
+/// }
+/// ```
+pub struct ResponseReader<S: AsyncRead + Unpin> {
+    stream: ResponseStream<S>,
+    /// The unread part of the current stdout record.
+    stdout: Bytes,
+    stderr: BytesMut,
+}
+
+impl<S: AsyncRead + Unpin> ResponseReader<S> {
+    /// Returns the request id the response belongs to.
+    pub fn request_id(&self) -> u16 {
+        self.stream.id
+    }
+
+    /// Returns the stderr received so far.
+    pub fn stderr(&self) -> &[u8] {
+        &self.stderr
+    }
+
+    /// Consumes the reader and returns the stderr received so far.
+    pub fn into_stderr(self) -> Bytes {
+        self.stderr.freeze()
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncBufRead for ResponseReader<S> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        loop {
+            if !this.stdout.is_empty() {
+                return Poll::Ready(Ok(&this.stdout));
+            }
+            match ready!(this.stream.poll_next_unpin(cx)) {
+                Some(Ok(Content::Stdout(stdout))) => this.stdout = stdout,
+                Some(Ok(Content::Stderr(stderr))) => this.stderr.extend_from_slice(&stderr),
+                Some(Err(ClientError::Io(err))) => return Poll::Ready(Err(err)),
+                Some(Err(err)) => return Poll::Ready(Err(io::Error::other(err))),
+                None => return Poll::Ready(Ok(&[])),
+            }
+        }
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        self.stdout.advance(amt);
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ResponseReader<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let stdout = ready!(self.as_mut().poll_fill_buf(cx))?;
+        let len = min(stdout.len(), buf.remaining());
+        buf.put_slice(&stdout[..len]);
+        self.consume(len);
+        Poll::Ready(Ok(()))
+    }
 }
@@ -0,0 +1,101 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Retry policy for transient failures.
+//!
+//! This module provides the `RetryPolicy` struct used by
+//! `Client::execute_with_retry` and `Pool::execute_with_retry`.
+
+use crate::ClientError;
+use std::time::Duration;
+
+/// How many times to send a request and how long to wait between attempts.
+///
+/// Only failures that happen before any stdout arrived are retried: a
+/// connection closed before the response, for example because the worker was
+/// recycled, and `EndRequest` with the `Overloaded` status. Retried requests
+/// must be idempotent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Creates a policy sending a request up to `max_attempts` times, waiting
+    /// 100ms before the first retry.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_attempts` - The maximum number of attempts, including the first
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            backoff: Duration::from_millis(100),
+        }
+    }
+
+    /// Set the wait before the first retry, doubled for each following one.
+    ///
+    /// # Arguments
+    ///
+    /// * `backoff` - The wait before the first retry
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Returns the maximum number of attempts.
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Returns the wait after the given failed attempt, counted from 1.
+    ///
+    /// # Arguments
+    ///
+    /// * `attempt` - The number of the failed attempt
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+    }
+
+    /// Returns whether a failed attempt can be retried.
+    ///
+    /// # Arguments
+    ///
+    /// * `attempt` - The number of the failed attempt, counted from 1
+    /// * `err` - The error of the attempt
+    /// * `response_started` - Whether stdout was received
+    /// * `reconnect` - Whether the next attempt uses a new connection
+    pub(crate) fn should_retry(
+        &self, attempt: u32, err: &ClientError, response_started: bool, reconnect: bool,
+    ) -> bool {
+        if attempt >= self.max_attempts || response_started {
+            return false;
+        }
+        match err {
+            ClientError::EndRequestOverloaded { .. } => true,
+            ClientError::ConnectionClosed { .. } => reconnect,
+            _ => false,
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts, waiting 100ms then 200ms.
+    fn default() -> Self {
+        Self::new(3)
+    }
+}
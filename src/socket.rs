@@ -0,0 +1,281 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Options of the TCP sockets opened by the connect helpers.
+//!
+//! This module provides the `SocketOptions` struct, applied to the socket
+//! before it connects, and the Happy Eyeballs connect all TCP connections
+//! go through.
+
+use crate::{trace::debug, ClientError, ClientResult};
+use futures_util::{
+    future::{select, Either},
+    pin_mut,
+    stream::{FuturesUnordered, StreamExt},
+};
+use socket2::{SockRef, TcpKeepalive};
+use std::{io, net::SocketAddr, time::Duration};
+use tokio::net::{lookup_host, TcpSocket, TcpStream, ToSocketAddrs};
+
+/// How long a connection attempt runs alone before the next address of the
+/// host is tried alongside it, as recommended by RFC 8305.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Options of a TCP socket, left to the system defaults unless set.
+///
+/// # Examples
+///
+/// ```no_run
+/// use fcgi_client::{Client, SocketOptions};
+/// use std::time::Duration;
+///
+/// async fn connect() {
+///     let stream = SocketOptions::default()
+///         .nodelay(true)
+///         .keepalive(Duration::from_secs(60), Duration::from_secs(10))
+///         .connect(("127.0.0.1", 9000), Duration::from_secs(5))
+///         .await
+///         .unwrap();
+///     let client = Client::new_keep_alive(stream);
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SocketOptions {
+    nodelay: Option<bool>,
+    keepalive: Option<(Duration, Duration)>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+    tos: Option<u32>,
+}
+
+impl SocketOptions {
+    /// Set `TCP_NODELAY`, disabling Nagle's algorithm so small records are
+    /// sent without delay.
+    ///
+    /// # Arguments
+    ///
+    /// * `nodelay` - Whether to disable Nagle's algorithm
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Enable `SO_KEEPALIVE`, probing connections that stay idle.
+    ///
+    /// # Arguments
+    ///
+    /// * `idle` - How long the connection stays idle before the first probe
+    /// * `interval` - The time between probes, ignored on platforms that can't
+    ///   set it
+    pub fn keepalive(mut self, idle: Duration, interval: Duration) -> Self {
+        self.keepalive = Some((idle, interval));
+        self
+    }
+
+    /// Set `SO_SNDBUF`, the size of the send buffer in bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - The requested size, which the system may adjust
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Set `SO_RCVBUF`, the size of the receive buffer in bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - The requested size, which the system may adjust
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Set `IP_TOS`, the type of service of the packets sent over IPv4.
+    ///
+    /// # Arguments
+    ///
+    /// * `tos` - The type of service byte, such as `0x10` for low delay
+    pub fn tos(mut self, tos: u32) -> Self {
+        self.tos = Some(tos);
+        self
+    }
+
+    /// Connects to the first address of the host that accepts the connection
+    /// within the timeout, with these options.
+    ///
+    /// The addresses are tried the Happy Eyeballs way (RFC 8305): alternating
+    /// between IPv6 and IPv4, and starting the next attempt when the previous
+    /// one fails or hasn't connected within 250ms, so an unreachable address
+    /// family doesn't delay the connection.
+    ///
+    /// Fails with [ClientError::Connect] carrying the error of the last
+    /// address that failed, or of the lookup if the host didn't resolve.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address of the server
+    /// * `timeout` - How long to wait for each address to connect
+    pub async fn connect(
+        &self, addr: impl ToSocketAddrs, timeout: Duration,
+    ) -> ClientResult<TcpStream> {
+        let addrs = lookup_host(addr)
+            .await
+            .map_err(|source| ClientError::Connect { addr: None, source })?;
+        let mut addrs = interleave_families(addrs).into_iter().peekable();
+
+        let mut last_err = ClientError::Connect {
+            addr: None,
+            source: io::Error::new(io::ErrorKind::InvalidInput, "no addresses to connect to"),
+        };
+        let mut attempts = FuturesUnordered::new();
+        loop {
+            if attempts.is_empty() {
+                match addrs.next() {
+                    Some(addr) => attempts.push(self.connect_addr(addr, timeout)),
+                    None => return Err(last_err),
+                }
+            }
+
+            let finished = if addrs.peek().is_some() {
+                let delay = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY);
+                pin_mut!(delay);
+                match select(attempts.next(), delay).await {
+                    Either::Left((finished, _)) => finished,
+                    Either::Right(_) => None,
+                }
+            } else {
+                attempts.next().await
+            };
+
+            match finished {
+                Some((_, Ok(stream))) => return Ok(stream),
+                Some((addr, Err(source))) => {
+                    debug!(%addr, %source, "Connect failed.");
+                    last_err = ClientError::Connect {
+                        addr: Some(addr),
+                        source,
+                    };
+                    if let Some(addr) = addrs.next() {
+                        attempts.push(self.connect_addr(addr, timeout));
+                    }
+                }
+                // The attempts in flight are slow, race the next address too.
+                None => {
+                    if let Some(addr) = addrs.next() {
+                        attempts.push(self.connect_addr(addr, timeout));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Connects to one address within the timeout.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address to connect to
+    /// * `timeout` - How long to wait for the connection
+    async fn connect_addr(
+        &self, addr: SocketAddr, timeout: Duration,
+    ) -> (SocketAddr, io::Result<TcpStream>) {
+        let connect = async {
+            let socket = match addr {
+                SocketAddr::V4(_) => TcpSocket::new_v4()?,
+                SocketAddr::V6(_) => TcpSocket::new_v6()?,
+            };
+            self.apply(&socket, addr)?;
+            socket.connect(addr).await
+        };
+        let result = match tokio::time::timeout(timeout, connect).await {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "connect timed out")),
+        };
+        (addr, result)
+    }
+
+    /// Sets the options on a socket before it connects.
+    ///
+    /// # Arguments
+    ///
+    /// * `socket` - The socket to configure
+    /// * `addr` - The address it connects to, for the IPv4 only options
+    fn apply(&self, socket: &TcpSocket, addr: SocketAddr) -> io::Result<()> {
+        let socket = SockRef::from(socket);
+        if let Some(nodelay) = self.nodelay {
+            socket.set_tcp_nodelay(nodelay)?;
+        }
+        if let Some((idle, interval)) = self.keepalive {
+            let keepalive = TcpKeepalive::new().with_time(idle);
+            #[cfg(any(
+                target_os = "android",
+                target_os = "freebsd",
+                target_os = "ios",
+                target_os = "linux",
+                target_os = "macos",
+                target_os = "netbsd",
+                target_os = "windows",
+            ))]
+            let keepalive = keepalive.with_interval(interval);
+            #[cfg(not(any(
+                target_os = "android",
+                target_os = "freebsd",
+                target_os = "ios",
+                target_os = "linux",
+                target_os = "macos",
+                target_os = "netbsd",
+                target_os = "windows",
+            )))]
+            let _ = interval;
+            socket.set_tcp_keepalive(&keepalive)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let (Some(tos), SocketAddr::V4(_)) = (self.tos, addr) {
+            socket.set_tos_v4(tos)?;
+        }
+        Ok(())
+    }
+}
+
+/// Orders the addresses of a host to alternate between address families,
+/// starting with the family of the first one.
+///
+/// # Arguments
+///
+/// * `addrs` - The addresses in the order of the resolver
+fn interleave_families(addrs: impl Iterator<Item = SocketAddr>) -> Vec<SocketAddr> {
+    let addrs = addrs.collect::<Vec<_>>();
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+    let first_is_ipv6 = first.is_ipv6();
+    let mut interleaved = Vec::with_capacity(addrs.len());
+    let (preferred, other): (Vec<SocketAddr>, Vec<SocketAddr>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_is_ipv6);
+
+    let (mut preferred, mut other) = (preferred.into_iter(), other.into_iter());
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => return interleaved,
+            (preferred, other) => interleaved.extend(preferred.into_iter().chain(other)),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tracing instrumentation behind the `tracing` feature.
+//!
+//! This module provides the `debug!` and `warning!` macros and the request
+//! span used across the crate. Without the feature they compile to nothing, so
+//! the rest of the crate doesn't need to be sprinkled with `cfg` attributes.
+
+use crate::meta::Role;
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::{Instrument, Span};
+
+/// Emits a debug event when the `tracing` feature is enabled.
+macro_rules! debug {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)+);
+    }};
+}
+
+pub(crate) use debug;
+
+/// Emits a warning event when the `tracing` feature is enabled.
+macro_rules! warning {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::warn!($($arg)+);
+    }};
+}
+
+pub(crate) use warning;
+
+/// Creates the span covering a request, from sending it to reading the
+/// `EndRequest`.
+///
+/// # Arguments
+///
+/// * `request_id` - The request ID
+/// * `role` - The role of the application
+#[cfg(feature = "tracing")]
+pub(crate) fn request_span(request_id: u16, role: Role) -> Span {
+    tracing::debug_span!("execute", request_id, ?role)
+}
+
+/// Creates the span covering a request, a no-op without the `tracing`
+/// feature.
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn request_span(_request_id: u16, _role: Role) -> Span {
+    Span
+}
+
+/// Stand-in for `tracing::Span` without the `tracing` feature.
+#[cfg(not(feature = "tracing"))]
+#[derive(Debug, Clone)]
+pub(crate) struct Span;
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    /// Does nothing, like entering a disabled span.
+    pub(crate) fn enter(&self) -> Entered {
+        Entered
+    }
+}
+
+/// Stand-in for `tracing::span::Entered` without the `tracing` feature.
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Entered;
+
+/// Stand-in for `tracing::Instrument` without the `tracing` feature.
+#[cfg(not(feature = "tracing"))]
+pub(crate) trait Instrument: Sized {
+    /// Returns the future unchanged.
+    fn instrument(self, _span: Span) -> Self {
+        self
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl<F: std::future::Future> Instrument for F {}
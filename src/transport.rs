@@ -0,0 +1,643 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Transports to reach servers that aren't directly connected, over TLS or
+//! through a SOCKS5 proxy.
+//!
+//! TLS is enabled by the `rustls` or the `native-tls` feature, for servers
+//! behind a TLS terminator such as stunnel or haproxy. Both backends
+//! implement `TlsConnector`: `TlsConfig` with rustls, and `NativeTlsConfig`
+//! with the platform trust store through native-tls.
+//!
+//! The `socks5` feature adds `Socks5Proxy`, tunnelling the connection through
+//! a proxy such as a bastion host. The tunnel is a plain TCP stream, so TLS
+//! can run over it with `TlsConnector::handshake`.
+
+use crate::{
+    conn::{KeepAlive, ShortConn},
+    Client, ClientError, ClientResult, SocketOptions,
+};
+#[cfg(feature = "rustls")]
+use rustls::{pki_types::ServerName, ClientConfig, RootCertStore};
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+use std::future::Future;
+#[cfg(feature = "socks5")]
+use std::net::IpAddr;
+#[cfg(feature = "rustls")]
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+use tokio::io::{self, AsyncRead, AsyncWrite};
+#[cfg(feature = "socks5")]
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+#[cfg(feature = "native-tls")]
+pub use tokio_native_tls::native_tls;
+#[cfg(feature = "rustls")]
+pub use tokio_rustls::rustls;
+
+/// The connect timeout used unless set otherwise.
+#[cfg(any(feature = "rustls", feature = "native-tls", feature = "socks5"))]
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Stream of a client connected with [TlsConfig].
+#[cfg(feature = "rustls")]
+pub type RustlsStream = tokio_rustls::client::TlsStream<TcpStream>;
+
+/// Stream of a client connected with [NativeTlsConfig].
+#[cfg(feature = "native-tls")]
+pub type NativeTlsStream = tokio_native_tls::TlsStream<TcpStream>;
+
+/// A TLS backend establishing the stream of [Client::connect_tls].
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+pub trait TlsConnector {
+    /// The stream the client runs over once connected.
+    type Stream: AsyncRead + AsyncWrite + Unpin;
+
+    /// Connects to the server and completes the TLS handshake.
+    ///
+    /// Fails with [ClientError::Connect] if no address of the host connects,
+    /// and with [ClientError::TlsHandshake] if the handshake fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The host name or IP address of the server
+    /// * `port` - The port of the server
+    fn connect(
+        &self, host: &str, port: u16,
+    ) -> impl Future<Output = ClientResult<Self::Stream>> + Send;
+
+    /// Completes the TLS handshake over an established connection, such as
+    /// one tunnelled through a proxy.
+    ///
+    /// Fails with [ClientError::TlsHandshake] if the handshake fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The host name or IP address the certificate is issued to
+    /// * `stream` - The connection to the server
+    fn handshake(
+        &self, host: &str, stream: TcpStream,
+    ) -> impl Future<Output = ClientResult<Self::Stream>> + Send;
+}
+
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+impl<S: AsyncRead + AsyncWrite + Unpin> Client<S, ShortConn> {
+    /// Connect to a fastcgi server over TLS under short connection mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The host name or IP address of the server
+    /// * `port` - The port of the server
+    /// * `connector` - The TLS backend and its settings
+    pub async fn connect_tls<C: TlsConnector<Stream = S>>(
+        host: &str, port: u16, connector: C,
+    ) -> ClientResult<Self> {
+        Ok(Self::new(connector.connect(host, port).await?))
+    }
+}
+
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+impl<S: AsyncRead + AsyncWrite + Unpin> Client<S, KeepAlive> {
+    /// Connect to a fastcgi server over TLS under keep alive connection mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The host name or IP address of the server
+    /// * `port` - The port of the server
+    /// * `connector` - The TLS backend and its settings
+    pub async fn connect_tls_keep_alive<C: TlsConnector<Stream = S>>(
+        host: &str, port: u16, connector: C,
+    ) -> ClientResult<Self> {
+        Ok(Self::new_keep_alive(connector.connect(host, port).await?))
+    }
+}
+
+/// Settings for connecting to a fastcgi server over TLS with rustls.
+///
+/// # Examples
+///
+/// ```no_run
+/// use fcgi_client::{
+///     transport::{rustls::RootCertStore, TlsConfig},
+///     Client,
+/// };
+///
+/// async fn connect(roots: RootCertStore) {
+///     let client = Client::connect_tls("php.internal", 9443, TlsConfig::new(roots))
+///         .await
+///         .unwrap();
+/// }
+/// ```
+#[cfg(feature = "rustls")]
+#[derive(Clone)]
+pub struct TlsConfig {
+    client_config: Arc<ClientConfig>,
+    server_name: Option<ServerName<'static>>,
+    connect_timeout: Duration,
+    socket_options: SocketOptions,
+}
+
+#[cfg(feature = "rustls")]
+impl TlsConfig {
+    /// Creates settings trusting the certificates of the store, without
+    /// client authentication.
+    ///
+    /// # Arguments
+    ///
+    /// * `roots` - The root certificates the server certificate must chain to
+    pub fn new(roots: RootCertStore) -> Self {
+        Self::from_client_config(Arc::new(
+            ClientConfig::builder()
+                .with_root_certificates(roots)
+                .with_no_client_auth(),
+        ))
+    }
+
+    /// Creates settings from a rustls client config, for client certificates
+    /// or custom verification.
+    ///
+    /// # Arguments
+    ///
+    /// * `client_config` - The rustls config used for the handshake
+    pub fn from_client_config(client_config: Arc<ClientConfig>) -> Self {
+        Self {
+            client_config,
+            server_name: None,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            socket_options: SocketOptions::default(),
+        }
+    }
+
+    /// Set the name the server certificate is verified against, the host
+    /// connected to by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `server_name` - The DNS name or IP address to verify
+    pub fn server_name(mut self, server_name: ServerName<'static>) -> Self {
+        self.server_name = Some(server_name);
+        self
+    }
+
+    /// Set how long to wait for each address to connect and for the
+    /// handshake, 10 seconds by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The connect and handshake timeout
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Set the options of the TCP socket, the system defaults by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `socket_options` - The socket options
+    pub fn socket_options(mut self, socket_options: SocketOptions) -> Self {
+        self.socket_options = socket_options;
+        self
+    }
+}
+
+#[cfg(feature = "rustls")]
+impl TlsConnector for TlsConfig {
+    type Stream = RustlsStream;
+
+    /// Also fails with [ClientError::InvalidServerName] before connecting if
+    /// no server name is set and the host isn't a valid one.
+    async fn connect(&self, host: &str, port: u16) -> ClientResult<RustlsStream> {
+        let server_name = self.server_name_of(host)?;
+        let stream = self
+            .socket_options
+            .connect((host, port), self.connect_timeout)
+            .await?;
+        self.handshake_with(server_name, stream).await
+    }
+
+    /// Also fails with [ClientError::InvalidServerName] if no server name is
+    /// set and the host isn't a valid one.
+    async fn handshake(&self, host: &str, stream: TcpStream) -> ClientResult<RustlsStream> {
+        let server_name = self.server_name_of(host)?;
+        self.handshake_with(server_name, stream).await
+    }
+}
+
+#[cfg(feature = "rustls")]
+impl TlsConfig {
+    /// Returns the name to verify the certificate of the host against.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The host connected to
+    fn server_name_of(&self, host: &str) -> ClientResult<ServerName<'static>> {
+        match &self.server_name {
+            Some(server_name) => Ok(server_name.clone()),
+            None => {
+                ServerName::try_from(host.to_owned()).map_err(|_| ClientError::InvalidServerName {
+                    name: host.to_owned(),
+                })
+            }
+        }
+    }
+
+    /// Completes the handshake over the stream within the connect timeout.
+    ///
+    /// # Arguments
+    ///
+    /// * `server_name` - The name to verify the certificate against
+    /// * `stream` - The connection to the server
+    async fn handshake_with(
+        &self, server_name: ServerName<'static>, stream: TcpStream,
+    ) -> ClientResult<RustlsStream> {
+        let connector = tokio_rustls::TlsConnector::from(self.client_config.clone());
+        with_handshake_timeout(self.connect_timeout, connector.connect(server_name, stream)).await
+    }
+}
+
+/// Settings for connecting to a fastcgi server over TLS with native-tls,
+/// which verifies certificates against the platform trust store.
+///
+/// # Examples
+///
+/// ```no_run
+/// use fcgi_client::{
+///     transport::{native_tls, NativeTlsConfig},
+///     Client,
+/// };
+///
+/// async fn connect() {
+///     let connector = native_tls::TlsConnector::new().unwrap();
+///     let client = Client::connect_tls("php.internal", 9443, NativeTlsConfig::new(connector))
+///         .await
+///         .unwrap();
+/// }
+/// ```
+#[cfg(feature = "native-tls")]
+#[derive(Clone)]
+pub struct NativeTlsConfig {
+    connector: tokio_native_tls::TlsConnector,
+    domain: Option<String>,
+    connect_timeout: Duration,
+    socket_options: SocketOptions,
+}
+
+#[cfg(feature = "native-tls")]
+impl NativeTlsConfig {
+    /// Creates settings from a native-tls connector.
+    ///
+    /// # Arguments
+    ///
+    /// * `connector` - The native-tls connector used for the handshake
+    pub fn new(connector: native_tls::TlsConnector) -> Self {
+        Self {
+            connector: connector.into(),
+            domain: None,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            socket_options: SocketOptions::default(),
+        }
+    }
+
+    /// Set the domain the server certificate is verified against, the host
+    /// connected to by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `domain` - The domain to verify
+    pub fn domain(mut self, domain: impl Into<String>) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// Set how long to wait for each address to connect and for the
+    /// handshake, 10 seconds by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The connect and handshake timeout
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Set the options of the TCP socket, the system defaults by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `socket_options` - The socket options
+    pub fn socket_options(mut self, socket_options: SocketOptions) -> Self {
+        self.socket_options = socket_options;
+        self
+    }
+}
+
+#[cfg(feature = "native-tls")]
+impl TlsConnector for NativeTlsConfig {
+    type Stream = NativeTlsStream;
+
+    async fn connect(&self, host: &str, port: u16) -> ClientResult<NativeTlsStream> {
+        let stream = self
+            .socket_options
+            .connect((host, port), self.connect_timeout)
+            .await?;
+        self.handshake(host, stream).await
+    }
+
+    async fn handshake(&self, host: &str, stream: TcpStream) -> ClientResult<NativeTlsStream> {
+        let domain = self.domain.as_deref().unwrap_or(host);
+        let handshake = async {
+            self.connector
+                .connect(domain, stream)
+                .await
+                .map_err(io::Error::other)
+        };
+        with_handshake_timeout(self.connect_timeout, handshake).await
+    }
+}
+
+/// Settings for tunnelling connections through a SOCKS5 proxy.
+///
+/// The proxy resolves host names itself, so names only known inside the
+/// private network can be reached. The tunnel is a plain TCP stream, TLS
+/// can be run over it with `TlsConnector::handshake`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use fcgi_client::{transport::Socks5Proxy, Client};
+///
+/// async fn connect() {
+///     let proxy = Socks5Proxy::new("bastion.example.com", 1080).credentials("deploy", "secret");
+///     let client = Client::connect_via_socks5(&proxy, ("php-fpm.internal", 9000))
+///         .await
+///         .unwrap();
+/// }
+/// ```
+#[cfg(feature = "socks5")]
+#[derive(Clone, Debug)]
+pub struct Socks5Proxy {
+    host: String,
+    port: u16,
+    credentials: Option<(String, String)>,
+    connect_timeout: Duration,
+    socket_options: SocketOptions,
+}
+
+#[cfg(feature = "socks5")]
+impl Socks5Proxy {
+    /// Creates settings for the proxy listening at the address, without
+    /// authentication.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The host name or IP address of the proxy
+    /// * `port` - The port of the proxy
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Self {
+            host: host.into(),
+            port,
+            credentials: None,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            socket_options: SocketOptions::default(),
+        }
+    }
+
+    /// Set the username and password to authenticate to the proxy with.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The username, up to 255 bytes
+    /// * `password` - The password, up to 255 bytes
+    pub fn credentials(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Set how long to wait for each address of the proxy to connect and for
+    /// the proxy to open the tunnel, 10 seconds by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The connect and handshake timeout
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Set the options of the TCP socket, the system defaults by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `socket_options` - The socket options
+    pub fn socket_options(mut self, socket_options: SocketOptions) -> Self {
+        self.socket_options = socket_options;
+        self
+    }
+
+    /// Opens a tunnel to the server through the proxy.
+    ///
+    /// Fails with [ClientError::Connect] if no address of the proxy connects,
+    /// and with [ClientError::Socks5] if the proxy refuses the tunnel.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The host name or IP address of the server, resolved by the
+    ///   proxy
+    /// * `port` - The port of the server
+    pub async fn connect(&self, host: &str, port: u16) -> ClientResult<TcpStream> {
+        let mut stream = self
+            .socket_options
+            .connect((self.host.as_str(), self.port), self.connect_timeout)
+            .await?;
+        match tokio::time::timeout(
+            self.connect_timeout,
+            self.handshake(&mut stream, host, port),
+        )
+        .await
+        {
+            Ok(result) => result.map(|()| stream),
+            Err(_) => Err(ClientError::Socks5 {
+                reason: "handshake timed out",
+            }),
+        }
+    }
+
+    /// Negotiates the authentication and asks the proxy to connect to the
+    /// server, see RFC 1928 and RFC 1929.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - The connection to the proxy
+    /// * `host` - The host name or IP address of the server
+    /// * `port` - The port of the server
+    async fn handshake(&self, stream: &mut TcpStream, host: &str, port: u16) -> ClientResult<()> {
+        let method = if self.credentials.is_some() { 2 } else { 0 };
+        stream.write_all(&[5, 1, method]).await?;
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != 5 {
+            return Err(ClientError::Socks5 {
+                reason: "not a SOCKS5 proxy",
+            });
+        }
+        if reply[1] != method {
+            return Err(ClientError::Socks5 {
+                reason: "no acceptable authentication method",
+            });
+        }
+
+        if let Some((username, password)) = &self.credentials {
+            let mut request = vec![1];
+            push_length_prefixed(&mut request, username.as_bytes(), "username too long")?;
+            push_length_prefixed(&mut request, password.as_bytes(), "password too long")?;
+            stream.write_all(&request).await?;
+            stream.read_exact(&mut reply).await?;
+            if reply[1] != 0 {
+                return Err(ClientError::Socks5 {
+                    reason: "authentication failed",
+                });
+            }
+        }
+
+        let mut request = vec![5, 1, 0];
+        match host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => {
+                request.push(1);
+                request.extend_from_slice(&ip.octets());
+            }
+            Ok(IpAddr::V6(ip)) => {
+                request.push(4);
+                request.extend_from_slice(&ip.octets());
+            }
+            Err(_) => {
+                request.push(3);
+                push_length_prefixed(&mut request, host.as_bytes(), "host name too long")?;
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut head = [0u8; 4];
+        stream.read_exact(&mut head).await?;
+        if head[1] != 0 {
+            return Err(ClientError::Socks5 {
+                reason: socks5_reply_reason(head[1]),
+            });
+        }
+        // The address the proxy bound, unused.
+        let bound_length = match head[3] {
+            1 => 4,
+            3 => stream.read_u8().await? as usize,
+            4 => 16,
+            _ => {
+                return Err(ClientError::Socks5 {
+                    reason: "invalid address type in reply",
+                })
+            }
+        };
+        let mut bound = vec![0u8; bound_length + 2];
+        stream.read_exact(&mut bound).await?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "socks5")]
+impl Client<TcpStream, ShortConn> {
+    /// Connect to a fastcgi server through a SOCKS5 proxy under short
+    /// connection mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `proxy` - The proxy settings
+    /// * `target` - The host name or IP address and the port of the server
+    pub async fn connect_via_socks5(
+        proxy: &Socks5Proxy, target: (&str, u16),
+    ) -> ClientResult<Self> {
+        Ok(Self::new(proxy.connect(target.0, target.1).await?))
+    }
+}
+
+#[cfg(feature = "socks5")]
+impl Client<TcpStream, KeepAlive> {
+    /// Connect to a fastcgi server through a SOCKS5 proxy under keep alive
+    /// connection mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `proxy` - The proxy settings
+    /// * `target` - The host name or IP address and the port of the server
+    pub async fn connect_via_socks5_keep_alive(
+        proxy: &Socks5Proxy, target: (&str, u16),
+    ) -> ClientResult<Self> {
+        Ok(Self::new_keep_alive(
+            proxy.connect(target.0, target.1).await?,
+        ))
+    }
+}
+
+/// Appends a field prefixed with its one byte length to a SOCKS5 message.
+///
+/// # Arguments
+///
+/// * `buf` - The message
+/// * `field` - The field to append
+/// * `reason` - The error reason if the field is longer than 255 bytes
+#[cfg(feature = "socks5")]
+fn push_length_prefixed(buf: &mut Vec<u8>, field: &[u8], reason: &'static str) -> ClientResult<()> {
+    let length = u8::try_from(field.len()).map_err(|_| ClientError::Socks5 { reason })?;
+    buf.push(length);
+    buf.extend_from_slice(field);
+    Ok(())
+}
+
+/// Describes the failure reply of a SOCKS5 connect request.
+///
+/// # Arguments
+///
+/// * `reply` - The reply code
+#[cfg(feature = "socks5")]
+fn socks5_reply_reason(reply: u8) -> &'static str {
+    match reply {
+        1 => "general server failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown failure",
+    }
+}
+
+/// Awaits a TLS handshake, failing with [ClientError::TlsHandshake] if it
+/// fails or takes longer than the timeout.
+///
+/// # Arguments
+///
+/// * `timeout` - The handshake timeout
+/// * `handshake` - The handshake to await
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+async fn with_handshake_timeout<S>(
+    timeout: Duration, handshake: impl Future<Output = io::Result<S>>,
+) -> ClientResult<S> {
+    match tokio::time::timeout(timeout, handshake).await {
+        Ok(result) => result.map_err(|source| ClientError::TlsHandshake { source }),
+        Err(_) => Err(ClientError::TlsHandshake {
+            source: io::Error::new(io::ErrorKind::TimedOut, "handshake timed out"),
+        }),
+    }
+}
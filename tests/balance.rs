@@ -0,0 +1,189 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use fcgi_client::{
+    balance::{BackendLoad, HealthCheck, LeastOutstanding, RandomTwoChoices, RoundRobin, Strategy},
+    BalancedClient, ClientError, Params, Pool, Request,
+};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use tokio::io::{self, DuplexStream};
+
+mod mock;
+
+/// Returns the loads of backends with the given outstanding requests and
+/// weights.
+fn loads(backends: &[(usize, u32)]) -> Vec<BackendLoad> {
+    backends
+        .iter()
+        .map(|&(outstanding, weight)| BackendLoad {
+            outstanding,
+            weight,
+        })
+        .collect()
+}
+
+/// Creates a balanced client over backends named `a`, `b` and `c`.
+fn balanced() -> (BalancedClient<DuplexStream>, [mock::Server; 3]) {
+    let servers = [(); 3].map(|_| mock::Server::new());
+    let client = BalancedClient::new(
+        ["a", "b", "c"]
+            .into_iter()
+            .zip(servers.iter().map(|server| Pool::new(server.connector()))),
+    );
+    (client, servers)
+}
+
+/// Sends `n` requests, returning how many each backend received.
+async fn spread(
+    client: &BalancedClient<DuplexStream>, servers: &[mock::Server; 3], n: usize,
+) -> [usize; 3] {
+    for _ in 0..n {
+        client
+            .execute(Request::new(Params::default(), &mut io::empty()))
+            .await
+            .unwrap();
+    }
+    servers
+        .each_ref()
+        .map(|server| server.served.swap(0, Ordering::Relaxed))
+}
+
+#[tokio::test]
+async fn spread_requests() {
+    let (client, servers) = balanced();
+    assert_eq!(spread(&client, &servers, 6).await, [2, 2, 2]);
+    assert_eq!(client.backends().outstanding("a"), Some(0));
+}
+
+#[tokio::test]
+async fn change_backends() {
+    let (client, servers) = balanced();
+    let backends = client.backends();
+
+    assert!(backends.set_weight("b", 2));
+    assert_eq!(spread(&client, &servers, 8).await, [2, 4, 2]);
+
+    assert!(backends.set_weight("c", 0));
+    assert_eq!(spread(&client, &servers, 6).await, [2, 4, 0]);
+
+    assert!(backends.remove("a"));
+    assert!(!backends.remove("a"));
+    assert_eq!(backends.names(), ["b", "c"]);
+    assert_eq!(spread(&client, &servers, 2).await, [0, 2, 0]);
+
+    backends.add("a", Pool::new(servers[0].connector()), 1);
+    backends.add("c", Pool::new(servers[2].connector()), 2);
+    assert_eq!(backends.weight("c"), Some(2));
+    assert_eq!(spread(&client, &servers, 10).await, [2, 4, 4]);
+}
+
+#[tokio::test]
+async fn no_backend() {
+    let (client, _servers) = balanced();
+    for name in ["a", "b", "c"] {
+        client.backends().set_weight(name, 0);
+    }
+    let err = client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::NoBackend));
+}
+
+/// Creates a pool whose connections answer requests and pings while `alive`
+/// is set, and close otherwise.
+fn probed_backend(alive: Arc<AtomicBool>) -> Pool<DuplexStream> {
+    Pool::new(mock::Server::new().alive(alive).connector())
+}
+
+#[tokio::test]
+async fn evict_unhealthy_backend() {
+    let alive = [(); 2].map(|_| Arc::new(AtomicBool::new(true)));
+    let client = BalancedClient::new(
+        ["a", "b"]
+            .into_iter()
+            .zip(alive.iter().cloned().map(probed_backend)),
+    )
+    .health_check(HealthCheck::new().unhealthy_after(2).healthy_after(2));
+    let backends = client.backends();
+
+    client.check_health().await;
+    assert_eq!(backends.is_healthy("a"), Some(true));
+
+    alive[0].store(false, Ordering::Relaxed);
+    client.check_health().await;
+    assert_eq!(backends.is_healthy("a"), Some(true));
+    client.check_health().await;
+    assert_eq!(backends.is_healthy("a"), Some(false));
+    assert_eq!(backends.is_healthy("b"), Some(true));
+    for _ in 0..3 {
+        client
+            .execute(Request::new(Params::default(), &mut io::empty()))
+            .await
+            .unwrap();
+    }
+
+    alive[0].store(true, Ordering::Relaxed);
+    client.check_health().await;
+    assert_eq!(backends.is_healthy("a"), Some(false));
+    client.check_health().await;
+    assert_eq!(backends.is_healthy("a"), Some(true));
+}
+
+#[tokio::test]
+async fn probe_with_request() {
+    let alive = Arc::new(AtomicBool::new(false));
+    let client = BalancedClient::new([("a", probed_backend(alive.clone()))])
+        .health_check(HealthCheck::new().request(|| Params::default().script_name("/ping")));
+
+    client.check_health().await;
+    assert_eq!(client.backends().is_healthy("a"), Some(false));
+    let err = client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::NoBackend));
+
+    alive.store(true, Ordering::Relaxed);
+    client.check_health().await;
+    assert_eq!(client.backends().is_healthy("a"), Some(true));
+}
+
+#[test]
+fn round_robin() {
+    let strategy = RoundRobin::default();
+    let backends = loads(&[(0, 1), (0, 2), (0, 1)]);
+    let picks = (0..5).map(|_| strategy.pick(&backends)).collect::<Vec<_>>();
+    assert_eq!(picks, [0, 1, 1, 2, 0]);
+}
+
+#[test]
+fn least_outstanding() {
+    assert_eq!(LeastOutstanding.pick(&loads(&[(2, 1), (0, 1), (1, 1)])), 1);
+    assert_eq!(LeastOutstanding.pick(&loads(&[(1, 1), (1, 1), (1, 1)])), 0);
+    assert_eq!(LeastOutstanding.pick(&loads(&[(2, 1), (3, 2)])), 1);
+}
+
+#[test]
+fn random_two_choices() {
+    let strategy = RandomTwoChoices::default();
+    for _ in 0..100 {
+        assert_eq!(strategy.pick(&loads(&[(5, 1), (0, 1)])), 1);
+        assert_ne!(strategy.pick(&loads(&[(0, 1), (0, 1), (9, 1)])), 2);
+        assert_eq!(strategy.pick(&loads(&[(3, 1)])), 0);
+    }
+}
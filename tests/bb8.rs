@@ -0,0 +1,67 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "bb8")]
+
+use fcgi_client::{
+    bb8::{Manager, Pool},
+    Params, Request,
+};
+use std::sync::atomic::Ordering;
+use tokio::io;
+
+mod mock;
+
+#[tokio::test]
+async fn ping_on_check_out() {
+    let server = mock::Server::new();
+    let manager = Manager::new(server.connector());
+    let pool = Pool::builder().build(manager).await.unwrap();
+    for _ in 0..3 {
+        pool.get()
+            .await
+            .unwrap()
+            .execute(Request::new(Params::default(), &mut io::empty()))
+            .await
+            .unwrap();
+    }
+    assert_eq!(server.connections(), 1);
+    assert_eq!(server.pings.load(Ordering::Relaxed), 3);
+}
+
+#[tokio::test]
+async fn drop_broken_connection() {
+    let server = mock::Server::new().reply(|_, id| {
+        // A record of an unsupported version.
+        let mut reply = mock::end_request(id, 0, 0);
+        reply[0] = 2;
+        reply
+    });
+    let manager = Manager::new(server.connector());
+    let pool = Pool::builder()
+        .test_on_check_out(false)
+        .build(manager)
+        .await
+        .unwrap();
+    for _ in 0..2 {
+        assert!(pool
+            .get()
+            .await
+            .unwrap()
+            .execute(Request::new(Params::default(), &mut io::empty()))
+            .await
+            .is_err());
+    }
+    assert_eq!(server.connections(), 2);
+}
@@ -0,0 +1,121 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "blocking")]
+
+use fcgi_client::{blocking::BlockingClient, Params};
+use std::{io, net::TcpStream, thread};
+use tokio::{io::AsyncWriteExt, net::TcpListener, runtime::Runtime};
+
+mod mock;
+
+#[test]
+fn blocking_keep_alive() {
+    let runtime = Runtime::new().unwrap();
+    let listener = runtime
+        .block_on(TcpListener::bind(("127.0.0.1", 0)))
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        runtime.block_on(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut bodies = Vec::new();
+            for _ in 0..2 {
+                let records = mock::read_request(&mut stream).await;
+                let id = records[0].request_id;
+                bodies.push(
+                    records
+                        .iter()
+                        .filter(|record| record.r#type == mock::STDIN)
+                        .flat_map(|record| record.content.clone())
+                        .collect::<Vec<_>>(),
+                );
+                stream
+                    .write_all(&mock::record(mock::STDOUT, id, b"hello"))
+                    .await
+                    .unwrap();
+                stream
+                    .write_all(&mock::end_request(id, 0, 0))
+                    .await
+                    .unwrap();
+            }
+            bodies
+        })
+    });
+
+    let mut client = BlockingClient::new_keep_alive(TcpStream::connect(addr).unwrap()).unwrap();
+    let output = client
+        .execute(Params::default(), &mut &b"p1=3&p2=4"[..])
+        .unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"hello"[..]));
+    let output = client.execute(Params::default(), &mut io::empty()).unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"hello"[..]));
+
+    let bodies = server.join().unwrap();
+    assert_eq!(bodies[0], b"p1=3&p2=4");
+    assert!(bodies[1].is_empty());
+}
+
+/// Body recording the largest read asked of it.
+struct Body {
+    remaining: usize,
+    largest_read: usize,
+}
+
+impl io::Read for Body {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.largest_read = self.largest_read.max(buf.len());
+        let read = buf.len().min(self.remaining);
+        buf[..read].fill(b'x');
+        self.remaining -= read;
+        Ok(read)
+    }
+}
+
+#[test]
+fn blocking_streams_body() {
+    let runtime = Runtime::new().unwrap();
+    let listener = runtime
+        .block_on(TcpListener::bind(("127.0.0.1", 0)))
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        runtime.block_on(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let records = mock::read_request(&mut stream).await;
+            stream
+                .write_all(&mock::end_request(records[0].request_id, 0, 0))
+                .await
+                .unwrap();
+            records
+                .iter()
+                .filter(|record| record.r#type == mock::STDIN)
+                .map(|record| record.content.len())
+                .sum::<usize>()
+        })
+    });
+
+    let mut body = Body {
+        remaining: 1 << 20,
+        largest_read: 0,
+    };
+    BlockingClient::new(TcpStream::connect(addr).unwrap())
+        .unwrap()
+        .execute_once(Params::default(), &mut body)
+        .unwrap();
+    assert_eq!(server.join().unwrap(), 1 << 20);
+    assert!(body.largest_read <= 0xffff);
+}
@@ -0,0 +1,107 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use fcgi_client::{meta::Role, ClientBuilder, ClientError, Params, Request};
+use futures_util::StreamExt;
+use std::time::Duration;
+use tokio::io::{self, AsyncWriteExt};
+
+mod mock;
+
+#[tokio::test]
+async fn keep_alive_and_default_role() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        let mut begin_requests = Vec::new();
+        for _ in 0..2 {
+            let records = mock::read_request(&mut server_side).await;
+            server_side
+                .write_all(&mock::end_request(records[0].request_id, 0, 0))
+                .await
+                .unwrap();
+            begin_requests.push(records[0].content.clone());
+        }
+        begin_requests
+    });
+
+    let mut client = ClientBuilder::new(client_side)
+        .keep_alive()
+        .default_role(Role::Authorizer)
+        .build();
+    for _ in 0..2 {
+        client
+            .execute(Request::new(Params::default(), &mut io::empty()))
+            .await
+            .unwrap();
+    }
+
+    for content in server.await.unwrap() {
+        // Role 2 is `Authorizer`, flag 1 is `FCGI_KEEP_CONN`.
+        assert_eq!(&content[..3], &[0, 2, 1]);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn read_timeout() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::record(mock::STDOUT, 1, b"partial"))
+            .await
+            .unwrap();
+        server_side
+    });
+
+    let output = ClientBuilder::new(client_side)
+        .read_timeout(Some(Duration::from_secs(5)))
+        .build()
+        .execute_once(Request::new(Params::default(), &mut io::empty()))
+        .await;
+    assert!(matches!(
+        output,
+        Err(ClientError::ReadTimeout { timeout }) if timeout == Duration::from_secs(5)
+    ));
+    drop(server.await.unwrap());
+}
+
+#[tokio::test(start_paused = true)]
+async fn read_timeout_stream() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::record(mock::STDOUT, 1, b"partial"))
+            .await
+            .unwrap();
+        server_side
+    });
+
+    let mut stream = ClientBuilder::new(client_side)
+        .read_timeout(Some(Duration::from_secs(5)))
+        .build()
+        .execute_once_stream(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert!(stream.next().await.unwrap().is_ok());
+    assert!(matches!(
+        stream.next().await,
+        Some(Err(ClientError::ReadTimeout { .. }))
+    ));
+    assert!(stream.next().await.is_none());
+    drop(server.await.unwrap());
+}
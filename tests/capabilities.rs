@@ -0,0 +1,253 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bytes::BytesMut;
+use fcgi_client::{
+    meta::{ParamPair, FCGI_MAX_CONNS, FCGI_MAX_REQS, FCGI_MPXS_CONNS},
+    Capabilities, Client, ClientBuilder, ClientError,
+};
+use std::time::Duration;
+use tokio::io::{self, AsyncWriteExt, DuplexStream};
+
+mod mock;
+
+/// Encodes a `GetValuesResult` record.
+fn get_values_result(values: &[(&str, &str)]) -> Vec<u8> {
+    let mut content = BytesMut::new();
+    for (name, value) in values {
+        ParamPair::new(name.as_bytes().into(), value.as_bytes().into())
+            .unwrap()
+            .encode(&mut content);
+    }
+    mock::record(mock::GET_VALUES_RESULT, 0, &content)
+}
+
+/// Encodes the `UnknownType` reply to a `GetValues` record.
+fn unknown_type() -> Vec<u8> {
+    mock::record(
+        mock::UNKNOWN_TYPE,
+        0,
+        &[mock::GET_VALUES, 0, 0, 0, 0, 0, 0, 0],
+    )
+}
+
+#[tokio::test]
+async fn get_values() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        let record = mock::read_record(&mut server_side).await;
+        server_side
+            .write_all(&get_values_result(&[(FCGI_MAX_REQS, "50")]))
+            .await
+            .unwrap();
+        record
+    });
+
+    let values = Client::new_keep_alive(client_side)
+        .get_values(&[FCGI_MAX_REQS, "UNKNOWN"])
+        .await
+        .unwrap();
+    assert_eq!(values.len(), 1);
+    assert_eq!(values[FCGI_MAX_REQS], "50");
+
+    let record = server.await.unwrap();
+    assert_eq!(record.r#type, mock::GET_VALUES);
+    assert_eq!(record.request_id, 0);
+    assert_eq!(
+        mock::decode_params(&record.content),
+        [
+            (FCGI_MAX_REQS.as_bytes().to_vec(), vec![]),
+            (b"UNKNOWN".to_vec(), vec![]),
+        ]
+    );
+}
+
+/// Answers a `GetValues` record with the tail of request 1 first.
+async fn answer_after_late_records(mut server_side: DuplexStream) -> DuplexStream {
+    mock::read_record(&mut server_side).await;
+    let mut bytes = mock::record(mock::STDOUT, 1, b"late");
+    bytes.extend(mock::end_request(1, 0, 0));
+    bytes.extend(get_values_result(&[(FCGI_MAX_REQS, "50")]));
+    server_side.write_all(&bytes).await.unwrap();
+    server_side
+}
+
+#[tokio::test]
+async fn get_values_skips_request_records() {
+    let (client_side, server_side) = io::duplex(1 << 16);
+    let server = tokio::spawn(answer_after_late_records(server_side));
+    let mut client = Client::new_keep_alive(client_side);
+    let values = client.get_values(&[FCGI_MAX_REQS]).await.unwrap();
+    assert_eq!(values[FCGI_MAX_REQS], "50");
+    assert_eq!(client.skipped_records(), 2);
+    drop(server.await.unwrap());
+
+    let (client_side, server_side) = io::duplex(1 << 16);
+    let server = tokio::spawn(answer_after_late_records(server_side));
+    let mut client = ClientBuilder::new(client_side)
+        .keep_alive()
+        .strict(true)
+        .build();
+    let err = client.get_values(&[FCGI_MAX_REQS]).await.unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::UnexpectedRecord { request_id: 1, .. }
+    ));
+    assert!(client.is_broken());
+    drop(server.await.unwrap());
+}
+
+#[tokio::test]
+async fn server_values() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        let record = mock::read_record(&mut server_side).await;
+        server_side
+            .write_all(&get_values_result(&[
+                (FCGI_MAX_REQS, "50"),
+                (FCGI_MPXS_CONNS, "yes"),
+                ("PHP_VERSION", "8.3"),
+            ]))
+            .await
+            .unwrap();
+        record
+    });
+
+    let values = Client::new_keep_alive(client_side)
+        .server_values(&["PHP_VERSION", FCGI_MAX_REQS])
+        .await
+        .unwrap();
+    assert_eq!(values.max_conns, None);
+    assert_eq!(values.max_reqs, Some(50));
+    assert_eq!(values.mpxs_conns, None);
+    assert_eq!(values.other.len(), 2);
+    assert_eq!(values.other["PHP_VERSION"], "8.3");
+    // A malformed well-known value is kept as is.
+    assert_eq!(values.other[FCGI_MPXS_CONNS], "yes");
+    assert_eq!(
+        values.capabilities(),
+        Capabilities {
+            max_conns: None,
+            max_reqs: 50,
+            mpxs_conns: false,
+        }
+    );
+
+    let record = server.await.unwrap();
+    let names = mock::decode_params(&record.content)
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        [
+            FCGI_MAX_CONNS.as_bytes(),
+            FCGI_MAX_REQS.as_bytes(),
+            FCGI_MPXS_CONNS.as_bytes(),
+            b"PHP_VERSION",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn capabilities_cached() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        mock::read_record(&mut server_side).await;
+        server_side
+            .write_all(&get_values_result(&[
+                (FCGI_MAX_CONNS, "10"),
+                (FCGI_MAX_REQS, "50"),
+                (FCGI_MPXS_CONNS, "1"),
+            ]))
+            .await
+            .unwrap();
+        server_side
+    });
+
+    let mut client = Client::new_keep_alive(client_side);
+    let expected = Capabilities {
+        max_conns: Some(10),
+        max_reqs: 50,
+        mpxs_conns: true,
+    };
+    assert_eq!(*client.capabilities().await.unwrap(), expected);
+
+    // The server answers only once, a second query would wait forever.
+    let server_side = server.await.unwrap();
+    assert_eq!(*client.capabilities().await.unwrap(), expected);
+    drop(server_side);
+}
+
+#[tokio::test]
+async fn capabilities_defaults() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        mock::read_record(&mut server_side).await;
+        server_side.write_all(&unknown_type()).await.unwrap();
+        server_side
+    });
+
+    let mut client = Client::new(client_side);
+    assert_eq!(
+        *client.capabilities().await.unwrap(),
+        Capabilities::default()
+    );
+    assert_eq!(Capabilities::default().max_reqs, 1);
+    assert!(!Capabilities::default().mpxs_conns);
+
+    let mut server_side = server.await.unwrap();
+    let server = tokio::spawn(async move {
+        mock::read_record(&mut server_side).await;
+        server_side.write_all(&unknown_type()).await.unwrap();
+        server_side
+    });
+    let err = client.get_values(&[FCGI_MAX_REQS]).await.unwrap_err();
+    assert!(matches!(err, ClientError::UnknownType { type_id: 9 }));
+    drop(server.await.unwrap());
+}
+
+#[tokio::test(start_paused = true)]
+async fn ping() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        let record = mock::read_record(&mut server_side).await;
+        assert_eq!(record.r#type, mock::GET_VALUES);
+        assert!(record.content.is_empty());
+        server_side
+            .write_all(&get_values_result(&[]))
+            .await
+            .unwrap();
+
+        mock::read_record(&mut server_side).await;
+        server_side.write_all(&unknown_type()).await.unwrap();
+        server_side
+    });
+
+    let mut client = Client::new_keep_alive(client_side);
+    let built = client.last_used();
+    tokio::time::advance(Duration::from_secs(5)).await;
+
+    client.ping().await.unwrap();
+    assert_eq!(client.last_used() - built, Duration::from_secs(5));
+    // An application without management records is alive too.
+    client.ping().await.unwrap();
+
+    drop(server.await.unwrap());
+}
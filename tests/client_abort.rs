@@ -0,0 +1,271 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use fcgi_client::{response::Content, Client, ClientBuilder, ClientError, Params, Request};
+use futures_util::stream::StreamExt;
+use std::time::Duration;
+use tokio::{
+    io::{self, AsyncReadExt, AsyncWriteExt},
+    sync::oneshot,
+    time,
+};
+use tokio_util::sync::CancellationToken;
+
+mod mock;
+
+#[tokio::test]
+async fn abort_stream() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        let records = mock::read_request(&mut server_side).await;
+        assert_eq!(records[0].r#type, mock::BEGIN_REQUEST);
+        server_side
+            .write_all(&mock::record(mock::STDOUT, 1, b"partial"))
+            .await
+            .unwrap();
+
+        let abort = mock::read_record(&mut server_side).await;
+        assert_eq!(abort.r#type, mock::ABORT_REQUEST);
+        assert_eq!(abort.request_id, 1);
+
+        server_side
+            .write_all(&mock::end_request(1, 0, 0))
+            .await
+            .unwrap();
+    });
+
+    let mut client = Client::new_keep_alive(client_side);
+    let mut stream = client
+        .execute_stream(Request::new(Params::default(), &mut &b"body"[..]))
+        .await
+        .unwrap();
+
+    match stream.next().await.unwrap().unwrap() {
+        Content::Stdout(out) => assert_eq!(&out[..], b"partial"),
+        Content::Stderr(_) => panic!("stderr should not happened"),
+    }
+    stream.abort().await.unwrap();
+
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn abort_after_end_request_queued() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::record(mock::STDOUT, 1, b"done"))
+            .await
+            .unwrap();
+        server_side
+            .write_all(&mock::end_request(1, 0, 0))
+            .await
+            .unwrap();
+
+        let abort = mock::read_record(&mut server_side).await;
+        assert_eq!(abort.r#type, mock::ABORT_REQUEST);
+
+        let records = mock::read_request(&mut server_side).await;
+        assert_eq!(records[0].request_id, 2);
+        server_side
+            .write_all(&mock::record(mock::STDOUT, 2, b"next"))
+            .await
+            .unwrap();
+        server_side
+            .write_all(&mock::end_request(2, 0, 0))
+            .await
+            .unwrap();
+    });
+
+    let mut client = Client::new_keep_alive(client_side);
+    let stream = client
+        .execute_stream(Request::new(Params::default(), &mut &b"body"[..]))
+        .await
+        .unwrap();
+    let id = stream.request_id();
+    drop(stream);
+    client.abort(id).await.unwrap();
+
+    let output = client
+        .execute(Request::new(Params::default(), &mut &b"body"[..]))
+        .await
+        .unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"next"[..]));
+    assert_eq!(output.request_id, 2);
+
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn abort_finished_request() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::end_request(1, 0, 0))
+            .await
+            .unwrap();
+
+        // The next record is the next request, not an abort.
+        let records = mock::read_request(&mut server_side).await;
+        assert_eq!(records[0].r#type, mock::BEGIN_REQUEST);
+        assert_eq!(records[0].request_id, 2);
+        server_side
+            .write_all(&mock::end_request(2, 0, 0))
+            .await
+            .unwrap();
+    });
+
+    let mut client = Client::new_keep_alive(client_side);
+    let output = client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    client.abort(output.request_id).await.unwrap();
+    client.abort(7).await.unwrap();
+
+    let output = client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(output.request_id, 2);
+
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn abort_management_request_id() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+    let mut client = Client::new_keep_alive(client_side);
+
+    let err = client.abort(0).await.unwrap_err();
+    assert!(matches!(err, ClientError::InvalidRequestId { id: 0 }));
+
+    drop(client);
+    let mut written = Vec::new();
+    server_side.read_to_end(&mut written).await.unwrap();
+    assert!(written.is_empty());
+}
+
+#[tokio::test]
+async fn cancel_before_stdout() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+    let (sent_tx, sent_rx) = oneshot::channel();
+
+    let server = tokio::spawn(async move {
+        let records = mock::read_request(&mut server_side).await;
+        assert!(records.iter().any(|record| record.r#type == mock::STDIN));
+        sent_tx.send(()).unwrap();
+
+        let abort = mock::read_record(&mut server_side).await;
+        assert_eq!(abort.r#type, mock::ABORT_REQUEST);
+        assert_eq!(abort.request_id, 1);
+        server_side
+            .write_all(&mock::record(mock::STDOUT, 1, b"late"))
+            .await
+            .unwrap();
+        server_side
+            .write_all(&mock::end_request(1, 0, 0))
+            .await
+            .unwrap();
+
+        let records = mock::read_request(&mut server_side).await;
+        assert_eq!(records[0].request_id, 2);
+        server_side
+            .write_all(&mock::record(mock::STDOUT, 2, b"next"))
+            .await
+            .unwrap();
+        server_side
+            .write_all(&mock::end_request(2, 0, 0))
+            .await
+            .unwrap();
+    });
+
+    let token = CancellationToken::new();
+    let canceller = token.clone();
+    tokio::spawn(async move {
+        sent_rx.await.unwrap();
+        canceller.cancel();
+    });
+
+    let mut client = Client::new_keep_alive(client_side);
+    let err = client
+        .execute_with_cancel(Request::new(Params::default(), &mut &b"body"[..]), &token)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::Cancelled { id: 1 }));
+
+    let output = client
+        .execute(Request::new(Params::default(), &mut &b"body"[..]))
+        .await
+        .unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"next"[..]));
+
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn cancel_before_send() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+    let mut client = Client::new_keep_alive(client_side);
+
+    let token = CancellationToken::new();
+    token.cancel();
+    let err = client
+        .execute_with_cancel(Request::new(Params::default(), &mut io::empty()), &token)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::Cancelled { id: 0 }));
+
+    drop(client);
+    let mut written = Vec::new();
+    server_side.read_to_end(&mut written).await.unwrap();
+    assert!(written.is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn cancel_unanswered() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        let abort = mock::read_record(&mut server_side).await;
+        assert_eq!(abort.r#type, mock::ABORT_REQUEST);
+        server_side
+    });
+
+    let token = CancellationToken::new();
+    let canceller = token.clone();
+    tokio::spawn(async move {
+        time::sleep(Duration::from_secs(1)).await;
+        canceller.cancel();
+    });
+
+    let mut client = ClientBuilder::new(client_side)
+        .keep_alive()
+        .read_timeout(Some(Duration::from_secs(5)))
+        .build();
+    let err = client
+        .execute_with_cancel(Request::new(Params::default(), &mut io::empty()), &token)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::Cancelled { id: 1 }));
+    // The rest of the response may still arrive.
+    assert!(client.is_broken());
+    drop(server.await.unwrap());
+}
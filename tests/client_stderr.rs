@@ -0,0 +1,62 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use fcgi_client::{request::Request, Client, Params};
+use std::env::current_dir;
+use tokio::{io, net::TcpStream};
+
+mod common;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn stderr_with_output() {
+    common::setup();
+
+    let document_root = current_dir().unwrap().join("tests").join("php");
+    let document_root = document_root.to_str().unwrap();
+    let script_name = current_dir()
+        .unwrap()
+        .join("tests")
+        .join("php")
+        .join("stderr.php");
+    let script_name = script_name.to_str().unwrap();
+
+    let params = Params::default()
+        .request_method("GET")
+        .document_root(document_root)
+        .script_name("/stderr.php")
+        .script_filename(script_name)
+        .request_uri("/stderr.php")
+        .document_uri("/stderr.php")
+        .remote_addr("127.0.0.1")
+        .remote_port(12345)
+        .server_addr("127.0.0.1")
+        .server_port(80)
+        .server_name("jmjoy-pc")
+        .content_type("")
+        .content_length(0);
+
+    let stream = TcpStream::connect(("127.0.0.1", 9000)).await.unwrap();
+    let output = Client::new(stream)
+        .execute_once(Request::new(params, &mut io::empty()))
+        .await
+        .unwrap();
+
+    let stdout = String::from_utf8(output.stdout.unwrap_or_default().to_vec()).unwrap();
+    assert!(stdout.contains("hello"));
+
+    let stderr = String::from_utf8(output.stderr.unwrap().to_vec()).unwrap();
+    let one = stderr.find("warning one").unwrap();
+    let two = stderr.find("warning two").unwrap();
+    assert!(one < two);
+}
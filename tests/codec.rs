@@ -0,0 +1,112 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "codec")]
+
+use bytes::BytesMut;
+use fcgi_client::{
+    codec::{FastCgiCodec, Record},
+    meta::RequestType,
+    ClientError,
+};
+use futures_util::StreamExt;
+use tokio::io::{self, AsyncWriteExt};
+use tokio_util::codec::{Decoder, Encoder, FramedRead};
+
+mod mock;
+
+#[test]
+fn encode_records() {
+    let mut buf = BytesMut::new();
+    let mut codec = FastCgiCodec::new();
+    codec
+        .encode(Record::new(RequestType::Stdout, 1, &b"hello"[..]), &mut buf)
+        .unwrap();
+    codec
+        .encode(Record::new(RequestType::Stdout, 1, &b""[..]), &mut buf)
+        .unwrap();
+
+    let mut expected = mock::record(mock::STDOUT, 1, b"hello");
+    expected.extend(mock::record(mock::STDOUT, 1, b""));
+    assert_eq!(&buf[..], &expected[..]);
+    assert_eq!(buf.len() % 8, 0);
+}
+
+#[test]
+fn reject_oversized_content() {
+    let content = vec![0; 0x10000];
+    let err = FastCgiCodec::new()
+        .encode(
+            Record::new(RequestType::Stdin, 1, content),
+            &mut BytesMut::new(),
+        )
+        .unwrap_err();
+    assert!(matches!(err, ClientError::Io(_)));
+}
+
+#[test]
+fn decode_partial_records() {
+    let bytes = mock::record(mock::STDERR, 3, b"oops");
+    let mut codec = FastCgiCodec::new();
+    let mut buf = BytesMut::new();
+    for chunk in bytes.chunks(3) {
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(chunk);
+    }
+    assert_eq!(
+        codec.decode(&mut buf).unwrap(),
+        Some(Record::new(RequestType::Stderr, 3, &b"oops"[..]))
+    );
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn decode_invalid_records() {
+    let mut bytes = mock::record(mock::STDOUT, 1, b"x");
+    bytes[0] = 2;
+    let err = FastCgiCodec::new()
+        .decode(&mut BytesMut::from(&bytes[..]))
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::UnsupportedVersion { version: 2 }
+    ));
+
+    let mut bytes = mock::record(mock::STDOUT, 1, b"x");
+    *bytes.last_mut().unwrap() = 1;
+    let mut buf = BytesMut::from(&bytes[..]);
+    assert!(FastCgiCodec::new().decode(&mut buf.clone()).is_ok());
+    let err = FastCgiCodec::new()
+        .strict_padding(true)
+        .decode(&mut buf)
+        .unwrap_err();
+    assert!(matches!(err, ClientError::InvalidPadding { .. }));
+}
+
+#[tokio::test]
+async fn framed_read() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+    let mut bytes = mock::record(mock::STDOUT, 1, b"hello");
+    bytes.extend(mock::end_request(1, 0, 0));
+    server_side.write_all(&bytes).await.unwrap();
+    drop(server_side);
+
+    let records = FramedRead::new(client_side, FastCgiCodec::new())
+        .map(Result::unwrap)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].content, &b"hello"[..]);
+    assert_eq!(records[1].request_type, RequestType::EndRequest);
+}
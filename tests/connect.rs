@@ -0,0 +1,193 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use fcgi_client::{Client, ClientError, Params, Request};
+#[cfg(unix)]
+use std::env::temp_dir;
+use std::{
+    io::ErrorKind,
+    time::{Duration, Instant},
+};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::{
+    io::{self, AsyncWriteExt},
+    net::{TcpListener, TcpSocket, TcpStream},
+};
+
+mod mock;
+
+#[tokio::test]
+async fn connect_tcp() {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let id = mock::read_request(&mut stream).await[0].request_id;
+        stream
+            .write_all(&mock::record(mock::STDOUT, id, b"hello"))
+            .await
+            .unwrap();
+        stream
+            .write_all(&mock::end_request(id, 0, 0))
+            .await
+            .unwrap();
+    });
+
+    // localhost may resolve to ::1 first, which nothing listens on.
+    let output = Client::connect(("localhost", port), Duration::from_secs(5))
+        .await
+        .unwrap()
+        .execute_once(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"hello"[..]));
+
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn connect_tcp_refused() {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+
+    let err = Client::connect_keep_alive(addr, Duration::from_secs(5))
+        .await
+        .err()
+        .unwrap();
+    assert!(err
+        .to_string()
+        .starts_with(&format!("Failed to connect to {addr}")));
+    match err {
+        ClientError::Connect {
+            addr: Some(tried),
+            source,
+        } => {
+            assert_eq!(tried, addr);
+            assert_eq!(source.kind(), ErrorKind::ConnectionRefused);
+        }
+        err => panic!("unexpected error: {err}"),
+    }
+}
+
+#[tokio::test]
+async fn connect_tcp_races_slow_address() {
+    // A listener with a full accept queue drops SYNs, like a broken path.
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind(([127, 0, 0, 1], 0).into()).unwrap();
+    let full = socket.listen(0).unwrap();
+    let slow = full.local_addr().unwrap();
+    let _queued = TcpStream::connect(slow).await.unwrap();
+    assert!(
+        tokio::time::timeout(Duration::from_millis(500), TcpStream::connect(slow))
+            .await
+            .is_err()
+    );
+
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let fast = listener.local_addr().unwrap();
+
+    let started = Instant::now();
+    Client::connect(&[slow, fast][..], Duration::from_secs(10))
+        .await
+        .unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn connect_tcp_unresolved() {
+    let err = Client::connect("fcgi-client.invalid:9000", Duration::from_secs(5))
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, ClientError::Connect { addr: None, .. }));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn connect_unix() {
+    let path = temp_dir().join(format!("fcgi-client-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        for _ in 0..2 {
+            let id = mock::read_request(&mut stream).await[0].request_id;
+            stream
+                .write_all(&mock::record(mock::STDOUT, id, b"hello"))
+                .await
+                .unwrap();
+            stream
+                .write_all(&mock::end_request(id, 0, 0))
+                .await
+                .unwrap();
+        }
+    });
+
+    let mut client = Client::connect_unix_keep_alive(&path).await.unwrap();
+    for _ in 0..2 {
+        let output = client
+            .execute(Request::new(Params::default(), &mut io::empty()))
+            .await
+            .unwrap();
+        assert_eq!(output.stdout.as_deref(), Some(&b"hello"[..]));
+    }
+
+    server.await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn connect_unix_missing_socket() {
+    let path = temp_dir().join("fcgi-client-missing.sock");
+    assert!(Client::connect_unix(&path).await.is_err());
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[tokio::test]
+async fn connect_unix_abstract() {
+    let name = format!("fcgi-client-{}", std::process::id());
+    let listener = UnixListener::bind(format!("\0{name}")).unwrap();
+
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let id = mock::read_request(&mut stream).await[0].request_id;
+        stream
+            .write_all(&mock::record(mock::STDOUT, id, b"hello"))
+            .await
+            .unwrap();
+        stream
+            .write_all(&mock::end_request(id, 0, 0))
+            .await
+            .unwrap();
+    });
+
+    let output = Client::connect_unix_abstract(&name)
+        .await
+        .unwrap()
+        .execute_once(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"hello"[..]));
+    server.await.unwrap();
+
+    // The name is free again once the listener is dropped.
+    assert!(Client::connect_unix_abstract_keep_alive(&name)
+        .await
+        .is_err());
+}
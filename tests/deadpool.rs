@@ -0,0 +1,75 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "deadpool")]
+
+use fcgi_client::{
+    deadpool::{Manager, Pool},
+    Params, Request,
+};
+use std::time::Duration;
+use tokio::{
+    io::{self, DuplexStream},
+    time,
+};
+
+mod mock;
+
+async fn execute(pool: &Pool<DuplexStream>) {
+    pool.get()
+        .await
+        .unwrap()
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn reuse_connection() {
+    let server = mock::Server::new().requests(3);
+    let manager = Manager::new(server.connector());
+    let pool = Pool::builder(manager).build().unwrap();
+    for _ in 0..3 {
+        execute(&pool).await;
+    }
+    assert_eq!(server.connections(), 1);
+}
+
+#[tokio::test]
+async fn reconnect_dead_connection() {
+    let server = mock::Server::new().requests(1);
+    let manager = Manager::new(server.connector());
+    let pool = Pool::builder(manager).build().unwrap();
+    execute(&pool).await;
+    server.pop_connection().await.unwrap();
+
+    execute(&pool).await;
+    assert_eq!(server.connections(), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn reconnect_on_failed_ping() {
+    let server = mock::Server::new().requests(2).close_on_ping();
+    let manager = Manager::new(server.connector());
+    let manager = manager.probe_after(Some(Duration::from_secs(10)));
+    let pool = Pool::builder(manager).build().unwrap();
+
+    execute(&pool).await;
+    execute(&pool).await;
+    assert_eq!(server.connections(), 1);
+
+    time::advance(Duration::from_secs(11)).await;
+    execute(&pool).await;
+    assert_eq!(server.connections(), 2);
+}
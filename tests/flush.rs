@@ -0,0 +1,142 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use fcgi_client::{Client, Params, Request};
+use std::{
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf};
+
+mod mock;
+
+/// Stream counting how often it is written to and flushed.
+struct FlushCounting {
+    inner: DuplexStream,
+    writes: Arc<AtomicUsize>,
+    flushes: Arc<AtomicUsize>,
+}
+
+impl AsyncRead for FlushCounting {
+    fn poll_read(
+        mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for FlushCounting {
+    fn poll_write(
+        mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.flushes.fetch_add(1, Ordering::Relaxed);
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Sends a request with the body and returns the number of writes and
+/// flushes.
+async fn count_writes(body: &[u8], flush_every_record: bool) -> (usize, usize) {
+    let (client_side, mut server_side) = tokio::io::duplex(1 << 16);
+    let writes = Arc::new(AtomicUsize::new(0));
+    let flushes = Arc::new(AtomicUsize::new(0));
+    let stream = FlushCounting {
+        inner: client_side,
+        writes: writes.clone(),
+        flushes: flushes.clone(),
+    };
+
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::end_request(1, 0, 0))
+            .await
+            .unwrap();
+        server_side
+    });
+
+    Client::builder(stream)
+        .flush_every_record(flush_every_record)
+        .build()
+        .execute_once(Request::new(Params::default(), &mut &body[..]))
+        .await
+        .unwrap();
+    drop(server.await.unwrap());
+
+    (
+        writes.load(Ordering::Relaxed),
+        flushes.load(Ordering::Relaxed),
+    )
+}
+
+#[tokio::test]
+async fn flush_after_params_and_stdin() {
+    assert_eq!(count_writes(b"body", false).await.1, 2);
+}
+
+#[tokio::test]
+async fn flush_every_record() {
+    // BeginRequest, two Params and two Stdin records.
+    assert_eq!(count_writes(b"body", true).await.1, 5);
+}
+
+#[tokio::test]
+async fn empty_body_in_one_write() {
+    assert_eq!(count_writes(b"", false).await.0, 1);
+}
+
+#[tokio::test]
+async fn params_not_held_back_by_body() {
+    let (client_side, mut server_side) = tokio::io::duplex(1 << 16);
+    let (mut body_reader, mut body_writer) = tokio::io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        let begin = mock::read_record(&mut server_side).await;
+        assert_eq!(begin.r#type, mock::BEGIN_REQUEST);
+        while !mock::read_record(&mut server_side).await.content.is_empty() {}
+
+        // The params arrived before any of the body was written.
+        body_writer.write_all(b"body").await.unwrap();
+        drop(body_writer);
+
+        let stdin = mock::read_record(&mut server_side).await;
+        assert_eq!(stdin.r#type, mock::STDIN);
+        assert_eq!(stdin.content, b"body");
+        assert!(mock::read_record(&mut server_side).await.content.is_empty());
+        server_side
+            .write_all(&mock::end_request(1, 0, 0))
+            .await
+            .unwrap();
+    });
+
+    Client::new(client_side)
+        .execute_once(Request::new(Params::default(), &mut body_reader))
+        .await
+        .unwrap();
+    server.await.unwrap();
+}
@@ -0,0 +1,82 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "futures-io")]
+
+use fcgi_client::{ClientBuilder, Params, Request};
+use std::{
+    future::Future,
+    pin::pin,
+    sync::Arc,
+    task::{Context, Poll, Wake},
+    thread::{self, Thread},
+};
+use tokio::{io::AsyncWriteExt, runtime::Runtime};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+mod mock;
+
+/// Wakes the thread blocked in [block_on].
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs a future to completion on the current thread, without a tokio
+/// runtime.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Arc::new(ThreadWaker(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[test]
+fn execute_without_tokio_runtime() {
+    let (client_side, mut server_side) = tokio::io::duplex(1 << 16);
+
+    let server = thread::spawn(move || {
+        Runtime::new().unwrap().block_on(async move {
+            mock::read_request(&mut server_side).await;
+            server_side
+                .write_all(&mock::record(mock::STDOUT, 1, b"hello"))
+                .await
+                .unwrap();
+            server_side
+                .write_all(&mock::end_request(1, 0, 0))
+                .await
+                .unwrap();
+        })
+    });
+
+    // A futures::io stream, as an async-std or smol socket would be.
+    let stream = client_side.compat();
+    let output = block_on(
+        ClientBuilder::from_futures_io(stream)
+            .build()
+            .execute_once(Request::new(Params::default(), &mut tokio::io::empty())),
+    )
+    .unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"hello"[..]));
+
+    server.join().unwrap();
+}
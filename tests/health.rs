@@ -0,0 +1,215 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use fcgi_client::{Client, Params, Request};
+use futures_util::StreamExt;
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+
+mod mock;
+
+#[tokio::test]
+async fn healthy_while_idle() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+    let mut client = Client::new_keep_alive(client_side);
+    assert!(client.is_healthy());
+
+    let server = tokio::spawn(async move {
+        let id = mock::read_request(&mut server_side).await[0].request_id;
+        server_side
+            .write_all(&mock::end_request(id, 0, 0))
+            .await
+            .unwrap();
+        server_side
+    });
+    client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    let mut server_side = server.await.unwrap();
+    assert!(client.is_healthy());
+
+    let mut stream = client.into_inner();
+    stream.write_all(b"raw").await.unwrap();
+    let mut buf = [0u8; 3];
+    server_side.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"raw");
+}
+
+#[tokio::test]
+async fn unhealthy_when_closed() {
+    let (client_side, server_side) = io::duplex(1 << 16);
+    let mut client = Client::new_keep_alive(client_side);
+    drop(server_side);
+    assert!(!client.is_healthy());
+}
+
+#[tokio::test]
+async fn unhealthy_with_stray_bytes() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+    let mut client = Client::new_keep_alive(client_side);
+    server_side
+        .write_all(&mock::record(mock::STDOUT, 1, b"stray"))
+        .await
+        .unwrap();
+    assert!(!client.is_healthy());
+}
+
+#[tokio::test]
+async fn broken_after_malformed_record() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        let id = mock::read_request(&mut server_side).await[0].request_id;
+        server_side
+            .write_all(&mock::end_request(id, 0, 2))
+            .await
+            .unwrap();
+        let id = mock::read_request(&mut server_side).await[0].request_id;
+        let mut reply = mock::end_request(id, 0, 0);
+        reply[0] = 2;
+        server_side.write_all(&reply).await.unwrap();
+        server_side
+    });
+
+    let mut client = Client::new_keep_alive(client_side);
+    assert!(client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .is_err());
+    assert!(!client.is_broken());
+    assert!(client.is_healthy());
+
+    assert!(client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .is_err());
+    let _server_side = server.await.unwrap();
+    assert!(client.is_broken());
+    assert!(!client.is_healthy());
+}
+
+#[tokio::test]
+async fn keep_alive_override() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        let mut flags = Vec::new();
+        for _ in 0..2 {
+            let records = mock::read_request(&mut server_side).await;
+            server_side
+                .write_all(&mock::end_request(records[0].request_id, 0, 0))
+                .await
+                .unwrap();
+            flags.push(records[0].content[2]);
+        }
+        (server_side, flags)
+    });
+
+    let mut client = Client::new_keep_alive(client_side);
+    client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert!(client.is_reusable());
+
+    client
+        .execute(Request::new(Params::default(), &mut io::empty()).with_keep_alive(false))
+        .await
+        .unwrap();
+    assert!(!client.is_reusable());
+    assert!(!client.is_healthy());
+
+    let (_server_side, flags) = server.await.unwrap();
+    assert_eq!(flags, [1, 0]);
+}
+
+#[tokio::test]
+async fn shutdown_discards_pending_records() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        let id = mock::read_request(&mut server_side).await[0].request_id;
+        let mut reply = mock::end_request(id, 0, 0);
+        reply.extend(mock::record(mock::STDOUT, id, b"stray"));
+        reply.extend(mock::end_request(id, 0, 0));
+        server_side.write_all(&reply).await.unwrap();
+
+        let mut rest = Vec::new();
+        server_side.read_to_end(&mut rest).await.unwrap();
+        rest
+    });
+
+    let mut client = Client::new_keep_alive(client_side);
+    client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    client.shutdown().await.unwrap();
+    assert!(!client.is_reusable());
+
+    // The server sees the connection closed without any further bytes.
+    assert!(server.await.unwrap().is_empty());
+
+    let mut stream = client.into_inner();
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).await.unwrap();
+    assert!(rest.is_empty());
+}
+
+#[tokio::test]
+async fn shutdown_with_nothing_pending() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+    let mut client = Client::new_keep_alive(client_side);
+    client.shutdown().await.unwrap();
+
+    let mut rest = Vec::new();
+    server_side.read_to_end(&mut rest).await.unwrap();
+    assert!(rest.is_empty());
+}
+
+#[tokio::test]
+async fn broken_after_dropping_stream_early() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        let id = mock::read_request(&mut server_side).await[0].request_id;
+        let mut bytes = mock::record(mock::STDOUT, id, b"whole");
+        bytes.extend_from_slice(&mock::end_request(id, 0, 0));
+        server_side.write_all(&bytes).await.unwrap();
+        let id = mock::read_request(&mut server_side).await[0].request_id;
+        server_side
+            .write_all(&mock::record(mock::STDOUT, id, b"partial"))
+            .await
+            .unwrap();
+        server_side
+    });
+
+    let mut client = Client::new_keep_alive(client_side);
+    let stream = client
+        .execute_stream(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(stream.count().await, 1);
+    assert!(!client.is_broken());
+
+    let mut stream = client
+        .execute_stream(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert!(stream.next().await.unwrap().is_ok());
+    drop(stream);
+    assert!(client.is_broken());
+    assert!(!client.is_healthy());
+    drop(server.await.unwrap());
+}
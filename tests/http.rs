@@ -0,0 +1,179 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "http")]
+
+use bytes::Bytes;
+use fcgi_client::{Client, ClientError, Params, Request, Response};
+use http_body::Body;
+use std::{future::poll_fn, pin::Pin};
+use tokio::io::{self, AsyncWriteExt};
+
+mod mock;
+
+fn param<'a>(params: &'a Params<'_>, name: &str) -> Option<&'a [u8]> {
+    params.get(name.as_bytes()).map(|value| value.as_ref())
+}
+
+#[test]
+fn from_http() {
+    let request = http::Request::post("http://example.com/index.php?a=1&b=2")
+        .version(http::Version::HTTP_10)
+        .header("Host", "example.com")
+        .header("Content-Type", "application/json")
+        .header("Content-Length", "2")
+        .header("X-Forwarded-For", "10.0.0.1")
+        .header("Accept", "text/html")
+        .header("Accept", "application/xml")
+        .header("Cookie", "a=1")
+        .header("Cookie", "b=2")
+        .body("{}")
+        .unwrap();
+    let params = Params::from_http(&request);
+
+    assert_eq!(param(&params, "REQUEST_METHOD"), Some(&b"POST"[..]));
+    assert_eq!(
+        param(&params, "REQUEST_URI"),
+        Some(&b"/index.php?a=1&b=2"[..])
+    );
+    assert_eq!(param(&params, "DOCUMENT_URI"), Some(&b"/index.php"[..]));
+    assert_eq!(param(&params, "QUERY_STRING"), Some(&b"a=1&b=2"[..]));
+    assert_eq!(param(&params, "SERVER_PROTOCOL"), Some(&b"HTTP/1.0"[..]));
+    assert_eq!(
+        param(&params, "CONTENT_TYPE"),
+        Some(&b"application/json"[..])
+    );
+    assert_eq!(param(&params, "CONTENT_LENGTH"), Some(&b"2"[..]));
+    assert_eq!(param(&params, "HTTP_HOST"), Some(&b"example.com"[..]));
+    assert_eq!(
+        param(&params, "HTTP_X_FORWARDED_FOR"),
+        Some(&b"10.0.0.1"[..])
+    );
+    assert_eq!(
+        param(&params, "HTTP_ACCEPT"),
+        Some(&b"text/html, application/xml"[..])
+    );
+    assert_eq!(param(&params, "HTTP_COOKIE"), Some(&b"a=1; b=2"[..]));
+    assert_eq!(param(&params, "HTTP_CONTENT_TYPE"), None);
+    assert_eq!(
+        param(&params, "GATEWAY_INTERFACE"),
+        Some(&b"FastCGI/1.0"[..])
+    );
+}
+
+#[test]
+fn from_http_drops_unsafe_headers() {
+    let request = http::Request::get("/")
+        .header("Proxy", "http://evil.example.com")
+        .header("X-Foo", "dash")
+        .header("X_Foo", "underscore")
+        .header("X_Bar", "underscore")
+        .body(())
+        .unwrap();
+    let params = Params::from_http(&request);
+
+    assert_eq!(param(&params, "HTTP_PROXY"), None);
+    assert_eq!(param(&params, "HTTP_X_FOO"), Some(&b"dash"[..]));
+    assert_eq!(param(&params, "HTTP_X_BAR"), None);
+}
+
+#[test]
+fn from_http_without_query() {
+    let request = http::Request::get("/").body(()).unwrap();
+    let params = Params::from_http(&request);
+
+    assert_eq!(param(&params, "REQUEST_URI"), Some(&b"/"[..]));
+    assert_eq!(param(&params, "QUERY_STRING"), Some(&b""[..]));
+    assert_eq!(param(&params, "SERVER_PROTOCOL"), Some(&b"HTTP/1.1"[..]));
+}
+
+fn response(stdout: &'static [u8]) -> Response {
+    let mut response = Response::default();
+    response.stdout = Some(stdout.into());
+    response
+}
+
+#[test]
+fn into_http() {
+    let parts = response(
+        b"Status: 404 Not Found\r\nContent-Type: text/html\r\nSet-Cookie: a=1\r\n\
+          Set-Cookie: b=2\r\n\r\nmissing",
+    )
+    .parse()
+    .unwrap();
+    let http_response = parts.into_http().unwrap();
+    assert_eq!(http_response.status(), http::StatusCode::NOT_FOUND);
+    assert_eq!(http_response.headers()["content-type"], "text/html");
+    assert_eq!(
+        http_response
+            .headers()
+            .get_all("set-cookie")
+            .iter()
+            .collect::<Vec<_>>(),
+        ["a=1", "b=2"]
+    );
+    assert_eq!(&http_response.body()[..], b"missing");
+
+    let parts = response(b"X-Bad: a\x7fb\r\n\r\n").parse().unwrap();
+    assert!(matches!(
+        parts.header_map(),
+        Err(ClientError::InvalidCgiHeader { .. })
+    ));
+}
+
+#[test]
+fn http_response_from_response() {
+    let cgi_response = response(
+        b"Status: 201 Created\r\nLocation: /item/1\r\nConnection: close\r\n\
+          Transfer-Encoding: chunked\r\n\r\ncreated",
+    );
+    let http_response = http::Response::<Bytes>::try_from(cgi_response).unwrap();
+    assert_eq!(http_response.status(), http::StatusCode::CREATED);
+    assert_eq!(http_response.headers().len(), 1);
+    assert_eq!(http_response.headers()["location"], "/item/1");
+    assert_eq!(&http_response.body()[..], b"created");
+
+    assert!(matches!(
+        http::Response::<Bytes>::try_from(response(b"Status: abc\r\n\r\n")),
+        Err(ClientError::InvalidCgiHeader { .. })
+    ));
+}
+
+#[tokio::test]
+async fn response_body() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        let mut bytes = mock::record(mock::STDOUT, 1, b"Content-type: text/plain\r\n\r\n");
+        bytes.extend_from_slice(&mock::record(mock::STDERR, 1, b"warning"));
+        bytes.extend_from_slice(&mock::record(mock::STDOUT, 1, b"body"));
+        bytes.extend_from_slice(&mock::end_request(1, 0, 0));
+        server_side.write_all(&bytes).await.unwrap();
+        server_side
+    });
+
+    let mut body = Client::new(client_side)
+        .execute_once_stream(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap()
+        .into_body();
+    let mut data = Vec::new();
+    while let Some(frame) = poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await {
+        data.push(frame.unwrap().into_data().unwrap());
+    }
+    assert_eq!(data, [&b"Content-type: text/plain\r\n\r\n"[..], b"body"]);
+    assert_eq!(body.stderr(), b"warning");
+    assert!(body.is_end_stream());
+    drop(server.await.unwrap());
+}
@@ -0,0 +1,313 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! In-memory FastCGI server helpers for tests that don't need php-fpm.
+
+#![allow(dead_code)]
+
+use std::{
+    future::{ready, Ready},
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream},
+    task::JoinHandle,
+};
+
+pub const BEGIN_REQUEST: u8 = 1;
+pub const ABORT_REQUEST: u8 = 2;
+pub const END_REQUEST: u8 = 3;
+pub const PARAMS: u8 = 4;
+pub const STDIN: u8 = 5;
+pub const STDOUT: u8 = 6;
+pub const STDERR: u8 = 7;
+pub const DATA: u8 = 8;
+pub const GET_VALUES: u8 = 9;
+pub const GET_VALUES_RESULT: u8 = 10;
+pub const UNKNOWN_TYPE: u8 = 11;
+
+/// A record read back from the client side of the connection.
+#[derive(Debug)]
+pub struct Record {
+    pub r#type: u8,
+    pub request_id: u16,
+    pub content: Vec<u8>,
+    pub padding: Vec<u8>,
+}
+
+/// Encodes a record with zero padding up to a multiple of 8 bytes.
+pub fn record(r#type: u8, request_id: u16, content: &[u8]) -> Vec<u8> {
+    let padding_length = (8 - content.len() % 8) % 8;
+    let mut buf = vec![1, r#type];
+    buf.extend_from_slice(&request_id.to_be_bytes());
+    buf.extend_from_slice(&(content.len() as u16).to_be_bytes());
+    buf.push(padding_length as u8);
+    buf.push(0);
+    buf.extend_from_slice(content);
+    buf.extend(std::iter::repeat_n(0, padding_length));
+    buf
+}
+
+/// Encodes an `EndRequest` record.
+pub fn end_request(request_id: u16, app_status: u32, protocol_status: u8) -> Vec<u8> {
+    let mut content = app_status.to_be_bytes().to_vec();
+    content.extend_from_slice(&[protocol_status, 0, 0, 0]);
+    record(END_REQUEST, request_id, &content)
+}
+
+/// Reads one record.
+pub async fn read_record<R: AsyncRead + Unpin>(reader: &mut R) -> Record {
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header).await.unwrap();
+    let content_length = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let mut content = vec![0u8; content_length];
+    reader.read_exact(&mut content).await.unwrap();
+    let mut padding = vec![0u8; header[6] as usize];
+    reader.read_exact(&mut padding).await.unwrap();
+    Record {
+        r#type: header[1],
+        request_id: u16::from_be_bytes([header[2], header[3]]),
+        content,
+        padding,
+    }
+}
+
+/// Reads the records of one application request, up to and including the
+/// empty `Stdin` record that terminates it.
+pub async fn read_request<R: AsyncRead + Unpin>(reader: &mut R) -> Vec<Record> {
+    let mut records = Vec::new();
+    loop {
+        let record = read_record(reader).await;
+        let done = record.r#type == STDIN && record.content.is_empty();
+        records.push(record);
+        if done {
+            return records;
+        }
+    }
+}
+
+/// Decodes a FastCGI name-value pair block.
+pub fn decode_params(mut buf: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+    fn length(buf: &mut &[u8]) -> usize {
+        if buf[0] & 0x80 == 0 {
+            let length = buf[0] as usize;
+            *buf = &buf[1..];
+            length
+        } else {
+            let length = u32::from_be_bytes([buf[0] & 0x7f, buf[1], buf[2], buf[3]]) as usize;
+            *buf = &buf[4..];
+            length
+        }
+    }
+
+    let mut pairs = Vec::new();
+    while !buf.is_empty() {
+        let name_length = length(&mut buf);
+        let value_length = length(&mut buf);
+        let name = buf[..name_length].to_vec();
+        let value = buf[name_length..name_length + value_length].to_vec();
+        buf = &buf[name_length + value_length..];
+        pairs.push((name, value));
+    }
+    pairs
+}
+
+/// Concatenates the content of all `Params` records and decodes it.
+pub fn request_params(records: &[Record]) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let content = records
+        .iter()
+        .filter(|record| record.r#type == PARAMS)
+        .flat_map(|record| record.content.iter().copied())
+        .collect::<Vec<_>>();
+    decode_params(&content)
+}
+
+/// Reply to a request, given the index of the connection and the request ID.
+type Reply = Arc<dyn Fn(usize, u16) -> Vec<u8> + Send + Sync>;
+
+/// Server behind the connections opened by a pool, see [Server::connector].
+///
+/// By default every connection answers each request with a successful
+/// `EndRequest` and each ping with an empty `GetValuesResult`, until the
+/// client closes it.
+#[derive(Clone)]
+pub struct Server {
+    requests: Option<usize>,
+    close_on_ping: bool,
+    alive: Arc<AtomicBool>,
+    reply: Reply,
+    /// The number of requests answered
+    pub served: Arc<AtomicUsize>,
+    /// The number of pings received
+    pub pings: Arc<AtomicUsize>,
+    connections: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self {
+            requests: None,
+            close_on_ping: false,
+            alive: Arc::new(AtomicBool::new(true)),
+            reply: Arc::new(|_, id| end_request(id, 0, 0)),
+            served: Arc::default(),
+            pings: Arc::default(),
+            connections: Arc::default(),
+        }
+    }
+
+    /// Closes each connection after `requests` requests.
+    pub fn requests(mut self, requests: usize) -> Self {
+        self.requests = Some(requests);
+        self
+    }
+
+    /// Closes the connection on a ping, like a worker that was restarted.
+    pub fn close_on_ping(mut self) -> Self {
+        self.close_on_ping = true;
+        self
+    }
+
+    /// Closes the connections on their next record while `alive` is unset.
+    pub fn alive(mut self, alive: Arc<AtomicBool>) -> Self {
+        self.alive = alive;
+        self
+    }
+
+    /// Replies to requests with `reply`, called with the index of the
+    /// connection and the request ID.
+    pub fn reply(mut self, reply: impl Fn(usize, u16) -> Vec<u8> + Send + Sync + 'static) -> Self {
+        self.reply = Arc::new(reply);
+        self
+    }
+
+    /// Returns the number of connections opened.
+    pub fn connections(&self) -> usize {
+        self.connections.lock().unwrap().len()
+    }
+
+    /// Takes the task serving the last connection opened.
+    pub fn pop_connection(&self) -> JoinHandle<()> {
+        self.connections.lock().unwrap().pop().unwrap()
+    }
+
+    /// Returns a connect function for a pool, opening a connection served
+    /// by a spawned task.
+    pub fn connector(
+        &self,
+    ) -> impl Fn() -> Ready<io::Result<DuplexStream>> + Send + Sync + 'static {
+        let server = self.clone();
+        move || {
+            let (client_side, server_side) = tokio::io::duplex(1 << 16);
+            let mut connections = server.connections.lock().unwrap();
+            let index = connections.len();
+            connections.push(tokio::spawn(server.clone().serve(index, server_side)));
+            ready(Ok(client_side))
+        }
+    }
+
+    /// Serves the connection of the given index.
+    async fn serve(self, index: usize, mut stream: DuplexStream) {
+        let mut requests = 0;
+        while self.requests != Some(requests) {
+            let first = read_record(&mut stream).await;
+            if !self.alive.load(Ordering::Relaxed) {
+                return;
+            }
+            if first.r#type == GET_VALUES {
+                self.pings.fetch_add(1, Ordering::Relaxed);
+                if self.close_on_ping {
+                    return;
+                }
+                stream
+                    .write_all(&record(GET_VALUES_RESULT, 0, b""))
+                    .await
+                    .unwrap();
+                continue;
+            }
+            loop {
+                let next = read_record(&mut stream).await;
+                if next.r#type == STDIN && next.content.is_empty() {
+                    break;
+                }
+            }
+            requests += 1;
+            self.served.fetch_add(1, Ordering::Relaxed);
+            let reply = (self.reply)(index, first.request_id);
+            stream.write_all(&reply).await.unwrap();
+        }
+    }
+}
+
+/// What a client asked the mock SOCKS5 proxy for.
+#[derive(Debug, Default)]
+pub struct Socks5Request {
+    pub methods: Vec<u8>,
+    pub credentials: Option<(Vec<u8>, Vec<u8>)>,
+    pub host: Vec<u8>,
+    pub port: u16,
+}
+
+/// Plays a SOCKS5 proxy on the stream, selecting the authentication
+/// `method`, answering the credentials with `auth_status` and the connect
+/// request with `reply`. Stops after the first refusal.
+pub async fn accept_socks5<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S, method: u8, auth_status: u8, reply: u8,
+) -> Socks5Request {
+    let mut request = Socks5Request::default();
+
+    assert_eq!(stream.read_u8().await.unwrap(), 5);
+    let count = stream.read_u8().await.unwrap();
+    request.methods = vec![0; count as usize];
+    stream.read_exact(&mut request.methods).await.unwrap();
+    stream.write_all(&[5, method]).await.unwrap();
+    if method == 0xff {
+        return request;
+    }
+
+    if method == 2 {
+        assert_eq!(stream.read_u8().await.unwrap(), 1);
+        let mut username = vec![0; stream.read_u8().await.unwrap() as usize];
+        stream.read_exact(&mut username).await.unwrap();
+        let mut password = vec![0; stream.read_u8().await.unwrap() as usize];
+        stream.read_exact(&mut password).await.unwrap();
+        request.credentials = Some((username, password));
+        stream.write_all(&[1, auth_status]).await.unwrap();
+        if auth_status != 0 {
+            return request;
+        }
+    }
+
+    let mut head = [0; 4];
+    stream.read_exact(&mut head).await.unwrap();
+    assert_eq!(head[..3], [5, 1, 0]);
+    request.host = match head[3] {
+        1 => vec![0; 4],
+        3 => vec![0; stream.read_u8().await.unwrap() as usize],
+        4 => vec![0; 16],
+        atyp => panic!("unexpected address type {atyp}"),
+    };
+    stream.read_exact(&mut request.host).await.unwrap();
+    request.port = stream.read_u16().await.unwrap();
+
+    stream
+        .write_all(&[5, reply, 0, 1, 127, 0, 0, 1, 0x1f, 0x40])
+        .await
+        .unwrap();
+    request
+}
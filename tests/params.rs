@@ -0,0 +1,362 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bytes::{Bytes, BytesMut};
+use fcgi_client::{
+    meta::{encode_params, ParamLength, ParamPair, MAX_PARAM_LENGTH},
+    Client, ClientBuilder, ClientError, Params, Request,
+};
+use std::borrow::Cow;
+use tokio::io::{self, AsyncWriteExt};
+
+mod mock;
+
+async fn sent_params(params: Params<'static>) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let (client_side, mut server_side) = io::duplex(1 << 20);
+
+    let server = tokio::spawn(async move {
+        let records = mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::end_request(1, 0, 0))
+            .await
+            .unwrap();
+        mock::request_params(&records)
+    });
+
+    Client::new(client_side)
+        .execute_once(Request::new(params, &mut &b"body"[..]))
+        .await
+        .unwrap();
+
+    server.await.unwrap()
+}
+
+#[tokio::test]
+async fn params_keep_insertion_order() {
+    let params = Params::default()
+        .request_method("GET")
+        .script_name("/index.php")
+        .custom("Z_LAST", "1")
+        .custom("A_FIRST", "2");
+
+    let names = sent_params(params)
+        .await
+        .into_iter()
+        .map(|(name, _)| String::from_utf8(name).unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        names,
+        [
+            "GATEWAY_INTERFACE",
+            "SERVER_SOFTWARE",
+            "SERVER_PROTOCOL",
+            "REQUEST_METHOD",
+            "SCRIPT_NAME",
+            "Z_LAST",
+            "A_FIRST",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn params_overwrite_in_place() {
+    let params = Params::default()
+        .request_method("GET")
+        .script_name("/index.php")
+        .request_method("POST");
+
+    let pairs = sent_params(params).await;
+
+    assert_eq!(pairs[3], (b"REQUEST_METHOD".to_vec(), b"POST".to_vec()));
+    assert_eq!(pairs[4].0, b"SCRIPT_NAME");
+    assert_eq!(pairs.len(), 5);
+}
+
+#[tokio::test]
+async fn params_binary_values() {
+    let params = Params::default()
+        .set_bytes(&b"HTTP_X_RAW"[..], &b"\xff\x00\xfe"[..])
+        .set_bytes(b"\xc3\x28".to_vec(), b"value".to_vec());
+
+    let pairs = sent_params(params).await;
+
+    assert_eq!(pairs[3], (b"HTTP_X_RAW".to_vec(), b"\xff\x00\xfe".to_vec()));
+    assert_eq!(pairs[4], (b"\xc3\x28".to_vec(), b"value".to_vec()));
+}
+
+#[tokio::test]
+async fn params_split_across_records() {
+    let (client_side, mut server_side) = io::duplex(1 << 20);
+    let cookie = "c".repeat(70_000);
+    let params = Params::default().custom("HTTP_COOKIE", cookie.clone());
+
+    let server = tokio::spawn(async move {
+        let records = mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::end_request(1, 0, 0))
+            .await
+            .unwrap();
+        records
+    });
+
+    Client::new(client_side)
+        .execute_once(Request::new(params, &mut io::empty()))
+        .await
+        .unwrap();
+
+    let records = server.await.unwrap();
+    let lengths = records
+        .iter()
+        .filter(|record| record.r#type == mock::PARAMS)
+        .map(|record| record.content.len())
+        .collect::<Vec<_>>();
+    assert_eq!(lengths.len(), 3);
+    assert_eq!(lengths[0], 0xffff);
+    assert_eq!(lengths[2], 0);
+
+    let pairs = mock::request_params(&records);
+    assert_eq!(pairs[3], (b"HTTP_COOKIE".to_vec(), cookie.into_bytes()));
+}
+
+#[tokio::test]
+async fn params_records_keep_length_prefixes_whole() {
+    let (client_side, mut server_side) = io::duplex(1 << 20);
+    let mut params = Params::default();
+    params.clear();
+    let params = params
+        .custom("NAME", "value")
+        .custom("LONG", "x".repeat(200));
+
+    let server = tokio::spawn(async move {
+        let records = mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::end_request(1, 0, 0))
+            .await
+            .unwrap();
+        records
+    });
+
+    ClientBuilder::new(client_side)
+        .record_size(12)
+        .build()
+        .execute_once(Request::new(params, &mut io::empty()))
+        .await
+        .unwrap();
+
+    let records = server.await.unwrap();
+    let params = records
+        .iter()
+        .filter(|record| record.r#type == mock::PARAMS)
+        .collect::<Vec<_>>();
+    // The 5 byte prefix of the second pair doesn't fit after the 11 bytes of
+    // the first one, so the first record ends early.
+    assert_eq!(params[0].content.len(), 11);
+    assert_eq!(params[1].content[..5], [4, 0x80, 0, 0, 200]);
+    assert!(params[1..params.len() - 2]
+        .iter()
+        .all(|record| record.content.len() == 12));
+    assert!(params.last().unwrap().content.is_empty());
+
+    let pairs = mock::request_params(&records);
+    assert_eq!(pairs[0], (b"NAME".to_vec(), b"value".to_vec()));
+    assert_eq!(pairs[1], (b"LONG".to_vec(), vec![b'x'; 200]));
+}
+
+#[tokio::test]
+async fn owned_request_in_spawned_task() {
+    let script_name = String::from("/index.php");
+    let request = Request::new(
+        Params::default().script_name(script_name.as_str()),
+        io::empty(),
+    )
+    .into_owned();
+    drop(script_name);
+
+    let (client_side, mut server_side) = io::duplex(1 << 20);
+    let server = tokio::spawn(async move {
+        let records = mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::end_request(1, 0, 0))
+            .await
+            .unwrap();
+        mock::request_params(&records)
+    });
+
+    tokio::spawn(Client::new(client_side).execute_once(request))
+        .await
+        .unwrap()
+        .unwrap();
+
+    let params = server.await.unwrap();
+    assert!(params.contains(&(b"SCRIPT_NAME".to_vec(), b"/index.php".to_vec())));
+}
+
+#[tokio::test]
+async fn raw_params_sent_verbatim() {
+    let value = vec![b'v'; 70_000];
+    let mut raw = vec![3];
+    raw.extend_from_slice(&(70_000u32 | 1 << 31).to_be_bytes());
+    raw.extend_from_slice(b"KEY");
+    raw.extend_from_slice(&value);
+    let raw = Bytes::from(raw);
+
+    let (client_side, mut server_side) = io::duplex(1 << 20);
+    let server = tokio::spawn(async move {
+        let records = mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::end_request(1, 0, 0))
+            .await
+            .unwrap();
+        records
+    });
+
+    let request = Request::new(Params::default(), io::empty()).with_raw_params(raw.clone());
+    assert_eq!(request.raw_params(), Some(&raw));
+    Client::new(client_side)
+        .execute_once(request)
+        .await
+        .unwrap();
+
+    let records = server.await.unwrap();
+    let params = records
+        .iter()
+        .filter(|record| record.r#type == mock::PARAMS)
+        .collect::<Vec<_>>();
+    assert_eq!(params.len(), 3);
+    assert_eq!(params[0].content.len(), 0xffff);
+    assert!(params[2].content.is_empty());
+    let sent = params
+        .iter()
+        .flat_map(|record| record.content.iter().copied())
+        .collect::<Vec<_>>();
+    assert_eq!(sent, raw);
+    assert_eq!(mock::request_params(&records), [(b"KEY".to_vec(), value)]);
+}
+
+#[test]
+fn param_length_round_trip() {
+    for length in [0, 127, 128, 100_000] {
+        let mut content = ParamLength::new(length).unwrap().content().freeze();
+        assert_eq!(content.len(), if length < 128 { 1 } else { 4 });
+        assert_eq!(ParamLength::decode(&mut content), Some(length));
+        assert!(content.is_empty());
+    }
+
+    let mut short = &[0x80, 0, 1][..];
+    assert_eq!(ParamLength::decode(&mut short), None);
+    assert_eq!(short.len(), 3);
+    assert_eq!(ParamLength::decode(&mut &[][..]), None);
+}
+
+#[test]
+fn param_pair_round_trip() {
+    let mut buf = BytesMut::new();
+    for length in [0, 127, 128, 100_000] {
+        let value = vec![b'v'; length];
+        ParamPair::new(b"NAME"[..].into(), value.into())
+            .unwrap()
+            .encode(&mut buf);
+    }
+
+    let mut content = &buf[..];
+    for length in [0, 127, 128, 100_000] {
+        let pair = ParamPair::decode(&mut content).unwrap();
+        assert_eq!(pair.name(), b"NAME");
+        assert_eq!(pair.value(), vec![b'v'; length]);
+    }
+    assert!(content.is_empty());
+
+    let mut truncated = &buf[..buf.len() - 1];
+    for _ in 0..3 {
+        ParamPair::decode(&mut truncated).unwrap();
+    }
+    let remaining = truncated.len();
+    assert!(ParamPair::decode(&mut truncated).is_none());
+    assert_eq!(truncated.len(), remaining);
+
+    // Both lengths at their maximum, with nothing after them.
+    let mut huge = &[0xff; 8][..];
+    assert!(ParamPair::decode(&mut huge).is_none());
+    assert_eq!(huge.len(), 8);
+}
+
+#[test]
+fn param_length_bounds() {
+    let mut content = ParamLength::new(MAX_PARAM_LENGTH).unwrap().content();
+    assert_eq!(&content[..], [0xff, 0xff, 0xff, 0xff]);
+    assert_eq!(ParamLength::decode(&mut content), Some((1 << 31) - 1));
+
+    assert!(matches!(
+        ParamLength::new(1 << 31),
+        Err(ClientError::ParamTooLong { length }) if length == 1 << 31
+    ));
+}
+
+#[tokio::test]
+async fn params_encoded_from_iterator() {
+    let long = "v".repeat(200);
+    let pairs: Vec<(Cow<str>, Cow<str>)> = vec![
+        ("SCRIPT_NAME".into(), "/index.php".into()),
+        ("EMPTY".into(), "".into()),
+        ("LONG".into(), long.as_str().into()),
+    ];
+
+    let mut content = BytesMut::new();
+    encode_params(
+        pairs
+            .iter()
+            .map(|(name, value)| (name.as_bytes(), value.as_bytes())),
+        &mut content,
+    )
+    .unwrap();
+    let mut expected = BytesMut::new();
+    for (name, value) in &pairs {
+        ParamPair::new(name.as_bytes().into(), value.as_bytes().into())
+            .unwrap()
+            .encode(&mut expected);
+    }
+    assert_eq!(content, expected);
+
+    let (client_side, mut server_side) = io::duplex(1 << 20);
+    let server = tokio::spawn(async move {
+        let records = mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::end_request(1, 0, 0))
+            .await
+            .unwrap();
+        records
+    });
+    let request = Request::new(Params::default(), io::empty()).with_raw_params(content.freeze());
+    Client::new(client_side)
+        .execute_once(request)
+        .await
+        .unwrap();
+
+    let sent = mock::request_params(&server.await.unwrap());
+    let expected = pairs
+        .iter()
+        .map(|(name, value)| (name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+        .collect::<Vec<_>>();
+    assert_eq!(sent, expected);
+}
+
+#[test]
+fn param_length_encode() {
+    for length in [0, 127, 128, MAX_PARAM_LENGTH] {
+        let mut buf = BytesMut::new();
+        ParamLength::new(length).unwrap().encode(&mut buf);
+        assert_eq!(buf, ParamLength::new(length).unwrap().content());
+    }
+}
@@ -0,0 +1,264 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use fcgi_client::{ClientError, Params, Pool, Request};
+use std::{sync::atomic::Ordering, time::Duration};
+use tokio::{
+    io::{self, DuplexStream},
+    time,
+};
+
+mod mock;
+
+async fn execute(pool: &Pool<DuplexStream>) {
+    pool.get()
+        .await
+        .unwrap()
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn reuse_connection() {
+    let server = mock::Server::new().requests(3);
+    let pool = Pool::new(server.connector());
+    for _ in 0..3 {
+        execute(&pool).await;
+    }
+    assert_eq!(server.connections(), 1);
+    assert_eq!(pool.idle_count(), 1);
+}
+
+#[tokio::test]
+async fn reconnect_dead_connection() {
+    let server = mock::Server::new().requests(1);
+    let pool = Pool::new(server.connector());
+    execute(&pool).await;
+    server.pop_connection().await.unwrap();
+
+    execute(&pool).await;
+    assert_eq!(server.connections(), 1);
+}
+
+#[tokio::test]
+async fn discard_on_error() {
+    let server = mock::Server::new().requests(0);
+    let pool = Pool::new(server.connector());
+    let mut client = pool.get().await.unwrap();
+    assert!(client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .is_err());
+    drop(client);
+    assert_eq!(pool.idle_count(), 0);
+    assert_eq!(server.connections(), 1);
+}
+
+#[tokio::test]
+async fn keep_on_error_status() {
+    // Protocol status `Overloaded`.
+    let server = mock::Server::new().reply(|_, id| mock::end_request(id, 0, 2));
+    let pool = Pool::new(server.connector());
+    let mut client = pool.get().await.unwrap();
+    let err = client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::EndRequestOverloaded { .. }));
+    drop(client);
+    assert_eq!(pool.idle_count(), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn discard_on_cancelled_request() {
+    let server = mock::Server::new().requests(1);
+    let pool = Pool::new(server.connector());
+    let mut client = pool.get().await.unwrap();
+    // Stdin that never ends, so the request is dropped halfway through.
+    let (mut stdin, _writer) = io::duplex(1);
+    let request = Request::new(Params::default(), &mut stdin);
+    assert!(
+        time::timeout(Duration::from_secs(1), client.execute(request))
+            .await
+            .is_err()
+    );
+    drop(client);
+    assert_eq!(pool.idle_count(), 0);
+    assert_eq!(server.connections(), 1);
+}
+
+#[tokio::test]
+async fn execute_on_pool() {
+    let server = mock::Server::new().requests(1);
+    let pool = Pool::new(server.connector());
+    pool.execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(pool.idle_count(), 1);
+    assert_eq!(server.connections(), 1);
+
+    let server = mock::Server::new().requests(0);
+    let pool = Pool::new(server.connector());
+    assert!(pool
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .is_err());
+    assert_eq!(pool.idle_count(), 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn wait_for_max_size() {
+    let server = mock::Server::new().requests(1);
+    let pool = Pool::new(server.connector());
+    let pool = pool.max_size(1);
+
+    let client = pool.get().await.unwrap();
+    assert!(time::timeout(Duration::from_secs(1), pool.get())
+        .await
+        .is_err());
+    drop(client);
+    assert!(time::timeout(Duration::from_secs(1), pool.get())
+        .await
+        .is_ok());
+}
+
+#[tokio::test(start_paused = true)]
+async fn close_idle_connection() {
+    let server = mock::Server::new().requests(2);
+    let pool = Pool::new(server.connector());
+    let pool = pool.idle_timeout(Some(Duration::from_secs(10)));
+
+    execute(&pool).await;
+    time::advance(Duration::from_secs(11)).await;
+    execute(&pool).await;
+    assert_eq!(server.connections(), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn close_expired_connection() {
+    let server = mock::Server::new().requests(3);
+    let pool = Pool::new(server.connector());
+    let pool = pool.max_lifetime(Some(Duration::from_secs(60)));
+
+    execute(&pool).await;
+    time::advance(Duration::from_secs(40)).await;
+    execute(&pool).await;
+    assert_eq!(server.connections(), 1);
+
+    // A connection expiring while in use finishes its request first.
+    let mut client = pool.get().await.unwrap();
+    time::advance(Duration::from_secs(40)).await;
+    client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    drop(client);
+    assert_eq!(pool.idle_count(), 0);
+
+    execute(&pool).await;
+    assert_eq!(server.connections(), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn ping_before_reuse() {
+    let server = mock::Server::new();
+    let pool = Pool::new(server.connector());
+    let pool = pool.probe_after(Some(Duration::from_secs(10)));
+
+    execute(&pool).await;
+    execute(&pool).await;
+    assert_eq!(server.pings.load(Ordering::Relaxed), 0);
+
+    time::advance(Duration::from_secs(11)).await;
+    execute(&pool).await;
+    assert_eq!(server.pings.load(Ordering::Relaxed), 1);
+    assert_eq!(server.connections(), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn reconnect_on_failed_ping() {
+    let server = mock::Server::new().close_on_ping();
+    let pool = Pool::new(server.connector());
+    let pool = pool.probe_after(Some(Duration::from_secs(10)));
+
+    execute(&pool).await;
+    time::advance(Duration::from_secs(11)).await;
+    execute(&pool).await;
+    assert_eq!(server.pings.load(Ordering::Relaxed), 1);
+    assert_eq!(server.connections(), 2);
+}
+
+#[tokio::test]
+async fn warm_up() {
+    let server = mock::Server::new().requests(1);
+    let pool = Pool::new(server.connector());
+    let pool = pool.max_size(4);
+    pool.warm_up(3, false).await.unwrap();
+    assert_eq!(server.connections(), 3);
+    assert_eq!(pool.idle_count(), 3);
+
+    pool.warm_up(8, false).await.unwrap();
+    assert_eq!(server.connections(), 4);
+    assert_eq!(pool.idle_count(), 4);
+
+    execute(&pool).await;
+    assert_eq!(server.connections(), 4);
+}
+
+#[tokio::test]
+async fn warm_up_with_capabilities() {
+    let server = mock::Server::new();
+    let pool = Pool::new(server.connector());
+    pool.warm_up(2, true).await.unwrap();
+    assert_eq!(server.connections(), 2);
+    assert_eq!(server.pings.load(Ordering::Relaxed), 2);
+
+    let server = mock::Server::new().close_on_ping();
+    let pool = Pool::new(server.connector());
+    assert!(pool.warm_up(2, true).await.is_err());
+    assert_eq!(pool.idle_count(), 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn probe_idle_connections() {
+    let server = mock::Server::new();
+    let pool = Pool::new(server.connector());
+    let pool = pool.probe_after(Some(Duration::from_secs(10)));
+
+    execute(&pool).await;
+    pool.probe_idle().await;
+    assert_eq!(server.pings.load(Ordering::Relaxed), 0);
+
+    time::advance(Duration::from_secs(11)).await;
+    pool.probe_idle().await;
+    assert_eq!(server.pings.load(Ordering::Relaxed), 1);
+    assert_eq!(pool.idle_count(), 1);
+
+    // The ping counts as a use, so the connection isn't probed again.
+    pool.probe_idle().await;
+    assert_eq!(server.pings.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn probe_idle_drops_dead_connections() {
+    let server = mock::Server::new().close_on_ping();
+    let pool = Pool::new(server.connector());
+    let pool = pool.probe_after(Some(Duration::from_secs(10)));
+
+    execute(&pool).await;
+    time::advance(Duration::from_secs(11)).await;
+    pool.probe_idle().await;
+    assert_eq!(pool.idle_count(), 0);
+}
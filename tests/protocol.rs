@@ -0,0 +1,615 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use fcgi_client::{
+    meta::{Header, Padding, RequestType},
+    response::Content,
+    Client, ClientBuilder, ClientError, Params, Request,
+};
+use futures_util::stream::StreamExt;
+use std::sync::Arc;
+use tokio::io::{self, AsyncWriteExt};
+
+mod mock;
+
+const HTTP_RESPONSE: &[u8] = b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n";
+
+#[test]
+fn header_from_short_buffer() {
+    let err = Header::try_from(&[1, 6][..]).unwrap_err();
+    assert!(matches!(err, ClientError::TruncatedHeader { length: 2 }));
+
+    let header = Header::try_from(&mock::record(mock::STDOUT, 3, b"out")[..8]).unwrap();
+    assert_eq!(header.request_type(), RequestType::Stdout);
+    assert_eq!(header.request_id(), 3);
+}
+
+#[tokio::test]
+async fn reject_unknown_version() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        server_side.write_all(HTTP_RESPONSE).await.unwrap();
+    });
+
+    let err = Client::new(client_side)
+        .execute_once(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::UnsupportedVersion { version: b'H' }
+    ));
+
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn reject_unknown_version_stream() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        server_side.write_all(HTTP_RESPONSE).await.unwrap();
+    });
+
+    let mut stream = Client::new(client_side)
+        .execute_once_stream(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    let err = stream.next().await.unwrap().err().unwrap();
+    assert!(matches!(
+        err,
+        ClientError::UnsupportedVersion { version: b'H' }
+    ));
+    assert!(stream.next().await.is_none());
+
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn reject_invalid_request_type() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::record(42, 1, b"garbage"))
+            .await
+            .unwrap();
+    });
+
+    let err = Client::new(client_side)
+        .execute_once(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::InvalidRequestType { request_type: 42 }
+    ));
+
+    server.await.unwrap();
+}
+
+#[test]
+fn request_type_from_u8() {
+    assert!(matches!(
+        RequestType::try_from_u8(11),
+        Ok(RequestType::UnknownType)
+    ));
+    assert!(matches!(RequestType::try_from_u8(0), Err(0)));
+    assert!(matches!(RequestType::try_from_u8(42), Err(42)));
+    assert!(matches!(RequestType::from_u8(42), RequestType::UnknownType));
+}
+
+#[tokio::test]
+async fn request_ids_increment() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            let records = mock::read_request(&mut server_side).await;
+            let id = records[0].request_id;
+            assert!(records.iter().all(|record| record.request_id == id));
+            server_side
+                .write_all(&mock::end_request(id, 0, 0))
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+        ids
+    });
+
+    let mut client = Client::new_keep_alive(client_side);
+    for expected in 1..=3 {
+        let output = client
+            .execute(Request::new(Params::default(), &mut io::empty()))
+            .await
+            .unwrap();
+        assert_eq!(output.request_id, expected);
+    }
+
+    assert_eq!(server.await.unwrap(), [1, 2, 3]);
+}
+
+async fn closing_server(bytes: Vec<u8>) -> io::DuplexStream {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+    tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        server_side.write_all(&bytes).await.unwrap();
+    });
+    client_side
+}
+
+#[tokio::test]
+async fn connection_closed_mid_record() {
+    let mut bytes = mock::record(mock::STDOUT, 1, b"complete");
+    bytes.extend_from_slice(&mock::record(mock::STDOUT, 1, b"truncated")[..12]);
+
+    let err = Client::new(closing_server(bytes.clone()).await)
+        .execute_once(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::ConnectionClosed {
+            expected: 16,
+            received: 4,
+            request_type: Some(RequestType::Stdout),
+            ..
+        }
+    ));
+    assert_eq!(err.partial_stdout().unwrap(), &b"complete"[..]);
+
+    let mut stream = Client::new(closing_server(bytes).await)
+        .execute_once_stream(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert!(stream.next().await.unwrap().is_ok());
+    let err = stream.next().await.unwrap().err().unwrap();
+    assert!(matches!(
+        err,
+        ClientError::ConnectionClosed {
+            expected: 16,
+            received: 4,
+            request_type: Some(RequestType::Stdout),
+            stdout: None,
+        }
+    ));
+    assert!(stream.next().await.is_none());
+}
+
+#[tokio::test]
+async fn connection_closed_between_records() {
+    let bytes = mock::record(mock::STDOUT, 1, b"complete");
+
+    let err = Client::new(closing_server(bytes).await)
+        .execute_once(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::ConnectionClosed {
+            expected: 8,
+            received: 0,
+            request_type: None,
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn partial_stdout_on_connection_closed() {
+    let mut bytes = mock::record(mock::STDOUT, 1, b"<h1>Report</h1>");
+    bytes.extend_from_slice(&mock::record(mock::STDOUT, 1, b"<p>Row 1</p>"));
+
+    let err = Client::new(closing_server(bytes).await)
+        .execute_once(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::ConnectionClosed { .. }));
+    assert_eq!(
+        err.partial_stdout().unwrap(),
+        &b"<h1>Report</h1><p>Row 1</p>"[..]
+    );
+}
+
+#[tokio::test]
+async fn unknown_type_record() {
+    let bytes = mock::record(
+        mock::UNKNOWN_TYPE,
+        0,
+        &[mock::GET_VALUES, 0, 0, 0, 0, 0, 0, 0],
+    );
+
+    let err = Client::new(closing_server(bytes.clone()).await)
+        .execute_once(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::UnknownType { type_id: 9 }));
+
+    let mut stream = Client::new(closing_server(bytes).await)
+        .execute_once_stream(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    let err = stream.next().await.unwrap().err().unwrap();
+    assert!(matches!(err, ClientError::UnknownType { type_id: 9 }));
+}
+
+/// A stdout record padded with non-zero bytes, then `EndRequest`.
+fn dirty_padding() -> Vec<u8> {
+    let mut bytes = vec![1, mock::STDOUT, 0, 1, 0, 2, 6, 0];
+    bytes.extend_from_slice(b"hi");
+    bytes.extend_from_slice(&[0xff; 6]);
+    bytes.extend_from_slice(&mock::end_request(1, 0, 0));
+    bytes
+}
+
+#[tokio::test]
+async fn ignore_padding_by_default() {
+    let output = Client::new(closing_server(dirty_padding()).await)
+        .execute_once(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"hi"[..]));
+}
+
+#[tokio::test]
+async fn reject_dirty_padding() {
+    let err = ClientBuilder::new(closing_server(dirty_padding()).await)
+        .strict_padding(true)
+        .build()
+        .execute_once(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::InvalidPadding {
+            request_type: RequestType::Stdout
+        }
+    ));
+
+    let mut stream = ClientBuilder::new(closing_server(dirty_padding()).await)
+        .strict_padding(true)
+        .build()
+        .execute_once_stream(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert!(matches!(
+        stream.next().await,
+        Some(Err(ClientError::InvalidPadding { .. }))
+    ));
+    assert!(stream.next().await.is_none());
+}
+
+/// Sends a request with a body through a client using `padding`, returning
+/// the records the server received.
+async fn padded_records(padding: Padding) -> Vec<mock::Record> {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+    let server = tokio::spawn(async move {
+        let records = mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::end_request(1, 0, 0))
+            .await
+            .unwrap();
+        records
+    });
+
+    ClientBuilder::new(client_side)
+        .padding(padding)
+        .build()
+        .execute_once(Request::new(Params::default(), &mut &b"body"[..]))
+        .await
+        .unwrap();
+    server.await.unwrap()
+}
+
+#[tokio::test]
+async fn padding_strategies() {
+    let records = padded_records(Padding::default()).await;
+    assert!(records
+        .iter()
+        .all(|record| (record.content.len() + record.padding.len()) % 8 == 0));
+    assert_eq!(records.last().unwrap().padding.len(), 0);
+
+    let records = padded_records(Padding::None).await;
+    assert!(records.iter().all(|record| record.padding.is_empty()));
+
+    let records = padded_records(Padding::Custom(Arc::new(|_| 200))).await;
+    assert!(records.iter().all(|record| record.padding == [0; 200]));
+}
+
+#[tokio::test]
+async fn drain_records_after_end_request() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        let mut bytes = mock::record(mock::STDOUT, 1, b"early");
+        bytes.extend_from_slice(&mock::end_request(1, 0, 0));
+        bytes.extend_from_slice(&mock::record(mock::STDOUT, 1, b"late"));
+        bytes.extend_from_slice(&mock::record(mock::STDERR, 1, b"late"));
+        server_side.write_all(&bytes).await.unwrap();
+
+        mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::record(mock::STDOUT, 2, b"next"))
+            .await
+            .unwrap();
+        server_side
+            .write_all(&mock::end_request(2, 0, 0))
+            .await
+            .unwrap();
+        server_side
+    });
+
+    let mut client = Client::new_keep_alive(client_side);
+    let output = client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"early"[..]));
+    assert!(client.is_healthy());
+
+    let output = client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"next"[..]));
+
+    drop(server.await.unwrap());
+}
+
+#[tokio::test]
+async fn discard_late_records_of_previous_request() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::end_request(1, 0, 0))
+            .await
+            .unwrap();
+
+        mock::read_request(&mut server_side).await;
+        let mut bytes = mock::record(mock::STDOUT, 1, b"late");
+        bytes.extend_from_slice(&mock::record(mock::STDOUT, 2, b"next"));
+        bytes.extend_from_slice(&mock::end_request(2, 0, 0));
+        server_side.write_all(&bytes).await.unwrap();
+        server_side
+    });
+
+    let mut client = Client::new_keep_alive(client_side);
+    let output = client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(output.stdout, None);
+
+    let output = client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"next"[..]));
+    assert_eq!(output.request_id, 2);
+
+    drop(server.await.unwrap());
+}
+
+#[tokio::test]
+async fn stream_discards_late_records_of_previous_request() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::end_request(1, 0, 0))
+            .await
+            .unwrap();
+
+        mock::read_request(&mut server_side).await;
+        let mut bytes = mock::record(mock::STDOUT, 1, b"late");
+        bytes.extend_from_slice(&mock::end_request(1, 0, 0));
+        bytes.extend_from_slice(&mock::record(mock::STDOUT, 2, b"next"));
+        bytes.extend_from_slice(&mock::record(mock::STDOUT, 7, b"other"));
+        bytes.extend_from_slice(&mock::end_request(2, 0, 0));
+        server_side.write_all(&bytes).await.unwrap();
+        server_side
+    });
+
+    let mut client = Client::new_keep_alive(client_side);
+    client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+
+    let mut stream = client
+        .execute_stream(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    let content = stream.next().await.unwrap().unwrap();
+    assert!(matches!(content, Content::Stdout(stdout) if stdout == "next"));
+    let err = stream.next().await.unwrap();
+    assert!(matches!(err, Err(ClientError::ResponseNotFound { id: 2 })));
+    drop(stream);
+    assert_eq!(client.skipped_records(), 2);
+
+    drop(server.await.unwrap());
+}
+
+#[tokio::test]
+async fn stream_skips_records_of_unknown_ids() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        let mut bytes = mock::record(mock::STDOUT, 9, b"leaked");
+        bytes.extend_from_slice(&mock::record(mock::STDOUT, 1, b"ok"));
+        bytes.extend_from_slice(&mock::end_request(1, 0, 0));
+        server_side.write_all(&bytes).await.unwrap();
+        server_side
+    });
+
+    let mut client = ClientBuilder::new(client_side)
+        .skip_unknown_ids(true)
+        .keep_alive()
+        .build();
+    let stdout = client
+        .execute_stream(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap()
+        .map(|content| match content.unwrap() {
+            Content::Stdout(stdout) => stdout,
+            Content::Stderr(stderr) => panic!("unexpected stderr: {stderr:?}"),
+        })
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(stdout, ["ok"]);
+    assert_eq!(client.skipped_records(), 1);
+
+    drop(server.await.unwrap());
+}
+
+#[tokio::test]
+async fn strict_rejects_other_request_id() {
+    let other_id = || {
+        let mut bytes = mock::record(mock::STDOUT, 7, b"other");
+        bytes.extend_from_slice(&mock::end_request(1, 0, 0));
+        bytes
+    };
+
+    let err = ClientBuilder::new(closing_server(other_id()).await)
+        .strict(true)
+        .build()
+        .execute_once(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::UnexpectedRecord {
+            request_type: RequestType::Stdout,
+            request_id: 7
+        }
+    ));
+
+    let mut stream = ClientBuilder::new(closing_server(other_id()).await)
+        .strict(true)
+        .build()
+        .execute_once_stream(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert!(matches!(
+        stream.next().await,
+        Some(Err(ClientError::UnexpectedRecord { request_id: 7, .. }))
+    ));
+    assert!(stream.next().await.is_none());
+}
+
+#[tokio::test]
+async fn strict_rejects_records_after_end_request() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        let mut bytes = mock::end_request(1, 0, 0);
+        bytes.extend_from_slice(&mock::record(mock::STDOUT, 1, b"late"));
+        server_side.write_all(&bytes).await.unwrap();
+        server_side
+    });
+
+    let mut client = ClientBuilder::new(client_side)
+        .strict(true)
+        .keep_alive()
+        .build();
+    let err = client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::UnexpectedRecord {
+            request_type: RequestType::Stdout,
+            request_id: 1
+        }
+    ));
+    assert!(client.is_broken());
+
+    drop(server.await.unwrap());
+}
+
+#[tokio::test]
+async fn strict_rejects_truncated_params() {
+    // A pair announcing a 5 byte name and a 1 byte value, cut after "ab".
+    let truncated = || mock::record(mock::GET_VALUES_RESULT, 0, &[5, 1, b'a', b'b']);
+
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+    tokio::spawn(async move {
+        mock::read_record(&mut server_side).await;
+        server_side.write_all(&truncated()).await.unwrap();
+        server_side
+    });
+    let mut client = Client::new_keep_alive(client_side);
+    assert!(client.get_values(&["NAME"]).await.unwrap().is_empty());
+
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+    tokio::spawn(async move {
+        mock::read_record(&mut server_side).await;
+        server_side.write_all(&truncated()).await.unwrap();
+        server_side
+    });
+    let mut client = ClientBuilder::new(client_side)
+        .strict(true)
+        .keep_alive()
+        .build();
+    let err = client.get_values(&["NAME"]).await.unwrap_err();
+    assert!(matches!(err, ClientError::TruncatedParams { remaining: 4 }));
+}
+
+#[tokio::test]
+async fn skip_records_of_unknown_ids() {
+    let leaky = || {
+        let mut bytes = mock::record(mock::STDOUT, 9, b"leaked");
+        bytes.extend_from_slice(&mock::record(mock::STDERR, 5, b"leaked"));
+        bytes.extend_from_slice(&mock::record(mock::STDOUT, 1, b"ok"));
+        bytes.extend_from_slice(&mock::end_request(1, 0, 0));
+        bytes
+    };
+
+    let err = Client::new(closing_server(leaky()).await)
+        .execute_once(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::ResponseNotFound { id: 1 }));
+
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        server_side.write_all(&leaky()).await.unwrap();
+        server_side
+    });
+    let mut client = ClientBuilder::new(client_side)
+        .skip_unknown_ids(true)
+        .keep_alive()
+        .build();
+    let output = client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"ok"[..]));
+    assert_eq!(output.stderr, None);
+    assert_eq!(client.skipped_records(), 2);
+    drop(server.await.unwrap());
+}
@@ -0,0 +1,122 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use fcgi_client::{
+    conn::KeepAlive,
+    meta::{Direction, RequestType},
+    Client, ClientBuilder, Params, Request,
+};
+use futures_util::stream::StreamExt;
+use std::sync::{Arc, Mutex};
+use tokio::io::{self, AsyncWriteExt, DuplexStream};
+
+mod mock;
+
+/// Records seen by the tap, as direction, type, request id and content
+/// length.
+type Seen = Arc<Mutex<Vec<(Direction, u8, u16, u16)>>>;
+
+/// Builds a keep alive client over the stream that records every header.
+fn tapped_client(stream: DuplexStream) -> (Client<DuplexStream, KeepAlive>, Seen) {
+    let seen = Seen::default();
+    let tap = seen.clone();
+    let client = ClientBuilder::new(stream)
+        .keep_alive()
+        .on_record(move |direction, header| {
+            tap.lock().unwrap().push((
+                direction,
+                header.request_type() as u8,
+                header.request_id(),
+                header.content_length(),
+            ));
+        })
+        .build();
+    (client, seen)
+}
+
+async fn reply(mut server_side: DuplexStream) {
+    mock::read_request(&mut server_side).await;
+    server_side
+        .write_all(&mock::record(mock::STDOUT, 1, b"hi"))
+        .await
+        .unwrap();
+    server_side
+        .write_all(&mock::end_request(1, 0, 0))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn tap_written_and_read_records() {
+    let (client_side, server_side) = io::duplex(1 << 16);
+    let server = tokio::spawn(reply(server_side));
+
+    let (mut client, seen) = tapped_client(client_side);
+    client
+        .execute(Request::new(Params::default(), &mut &b"body"[..]))
+        .await
+        .unwrap();
+    server.await.unwrap();
+
+    let seen = seen.lock().unwrap();
+    let types: Vec<_> = seen
+        .iter()
+        .map(|(direction, r#type, ..)| (*direction, *r#type))
+        .collect();
+    assert_eq!(
+        types,
+        [
+            (Direction::Write, RequestType::BeginRequest as u8),
+            (Direction::Write, RequestType::Params as u8),
+            (Direction::Write, RequestType::Params as u8),
+            (Direction::Write, RequestType::Stdin as u8),
+            (Direction::Write, RequestType::Stdin as u8),
+            (Direction::Read, RequestType::Stdout as u8),
+            (Direction::Read, RequestType::EndRequest as u8),
+        ]
+    );
+    assert!(seen.iter().all(|(_, _, id, _)| *id == 1));
+    assert_eq!(seen[3].3, 4);
+    assert_eq!(seen[5].3, 2);
+}
+
+#[tokio::test]
+async fn tap_stream() {
+    let (client_side, server_side) = io::duplex(1 << 16);
+    let server = tokio::spawn(reply(server_side));
+
+    let (mut client, seen) = tapped_client(client_side);
+    let mut stream = client
+        .execute_stream(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    while stream.next().await.is_some() {}
+    drop(stream);
+    server.await.unwrap();
+
+    let reads: Vec<_> = seen
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(direction, ..)| *direction == Direction::Read)
+        .map(|(_, r#type, _, content_length)| (*r#type, *content_length))
+        .collect();
+    assert_eq!(
+        reads,
+        [
+            (RequestType::Stdout as u8, 2),
+            (RequestType::EndRequest as u8, 8),
+        ]
+    );
+}
@@ -0,0 +1,637 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bytes::Bytes;
+use fcgi_client::{
+    meta::RequestType,
+    response::{Content, Event},
+    Client, ClientBuilder, ClientError, ClientResult, Params, Request, Response,
+};
+use futures_util::StreamExt;
+use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt, DuplexStream};
+
+mod mock;
+
+#[tokio::test]
+async fn app_status_on_success() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::record(mock::STDOUT, 1, b"partial page"))
+            .await
+            .unwrap();
+        server_side
+            .write_all(&mock::end_request(1, 255, 0))
+            .await
+            .unwrap();
+    });
+
+    let output = Client::new(client_side)
+        .execute_once(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+
+    assert_eq!(output.app_status, 255);
+    assert_eq!(output.stdout.as_deref(), Some(&b"partial page"[..]));
+
+    server.await.unwrap();
+}
+
+fn response(stdout: &'static [u8]) -> Response {
+    let mut response = Response::default();
+    response.stdout = Some(Bytes::from_static(stdout));
+    response
+}
+
+#[test]
+fn parse_headers_and_body() {
+    let parts = response(
+        b"X-Powered-By: PHP/7.1.30\r\nStatus: 404 Not Found\r\nContent-type: \
+          text/html\r\n\r\nnot found\r\n\r\nagain",
+    )
+    .parse()
+    .unwrap();
+
+    assert_eq!(parts.status, 404);
+    assert_eq!(parts.reason.as_deref(), Some("Not Found"));
+    assert_eq!(
+        parts.headers,
+        [
+            ("X-Powered-By".to_owned(), "PHP/7.1.30".to_owned()),
+            ("Content-type".to_owned(), "text/html".to_owned()),
+        ]
+    );
+    assert_eq!(&parts.body[..], b"not found\r\n\r\nagain");
+}
+
+#[test]
+fn parse_headers_only() {
+    let parts = response(b"Status: 204\r\nX-Empty: 1\r\n\r\n")
+        .parse()
+        .unwrap();
+    assert_eq!(parts.status, 204);
+    assert_eq!(parts.reason, None);
+    assert!(parts.body.is_empty());
+
+    let parts = response(b"Location: /next\n").parse().unwrap();
+    assert_eq!(parts.status, 200);
+    assert_eq!(parts.headers, [("Location".to_owned(), "/next".to_owned())]);
+    assert!(parts.body.is_empty());
+}
+
+#[test]
+fn parse_folded_headers() {
+    let parts = response(b"X-Long: first\n  second\n\tthird\nX-Next: 1\n\nbody")
+        .parse()
+        .unwrap();
+    assert_eq!(
+        parts.headers,
+        [
+            ("X-Long".to_owned(), "first second third".to_owned()),
+            ("X-Next".to_owned(), "1".to_owned()),
+        ]
+    );
+    assert_eq!(&parts.body[..], b"body");
+}
+
+#[test]
+fn parse_malformed() {
+    assert!(matches!(
+        response(b"no colon here\r\n\r\n").parse(),
+        Err(ClientError::InvalidCgiHeader { .. })
+    ));
+    assert!(matches!(
+        response(b"Status: abc\r\n\r\n").parse(),
+        Err(ClientError::InvalidCgiHeader { .. })
+    ));
+}
+
+#[test]
+fn status_code() {
+    let status = response(b"Status: 500 Internal Server Error\r\n\r\noops").status_code();
+    assert_eq!(status.unwrap(), 500);
+    assert_eq!(response(b"Status: 404\n\n").status_code().unwrap(), 404);
+    assert_eq!(
+        response(b"Content-type: text/html\r\n\r\nok")
+            .status_code()
+            .unwrap(),
+        200
+    );
+    assert_eq!(Response::default().status_code().unwrap(), 200);
+    assert!(matches!(
+        response(b"Status: 20x OK\r\n\r\n").status_code(),
+        Err(ClientError::InvalidCgiHeader { .. })
+    ));
+}
+
+async fn execute_mock(records: Vec<Vec<u8>>, limit: Option<usize>) -> ClientResult<Response> {
+    execute_configured(records, |builder| builder.max_response_bytes(limit)).await
+}
+
+/// Executes a request against a server answering `records`, with a client
+/// set up by `configure`.
+async fn execute_configured(
+    records: Vec<Vec<u8>>,
+    configure: impl FnOnce(ClientBuilder<DuplexStream>) -> ClientBuilder<DuplexStream>,
+) -> ClientResult<Response> {
+    let (client_side, mut server_side) = io::duplex(1 << 20);
+
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        for record in records {
+            server_side.write_all(&record).await.unwrap();
+        }
+        server_side
+    });
+
+    let output = configure(ClientBuilder::new(client_side))
+        .build()
+        .execute_once(Request::new(Params::default(), &mut io::empty()))
+        .await;
+    server.await.unwrap();
+    output
+}
+
+#[tokio::test]
+async fn max_response_bytes() {
+    let output = execute_mock(
+        vec![
+            mock::record(mock::STDOUT, 1, &[b'.'; 6]),
+            mock::record(mock::STDOUT, 1, &[b'.'; 4]),
+            mock::end_request(1, 0, 0),
+        ],
+        Some(10),
+    )
+    .await
+    .unwrap();
+    assert_eq!(output.stdout.unwrap().len(), 10);
+
+    let err = execute_mock(
+        vec![
+            mock::record(mock::STDOUT, 1, &[b'.'; 6]),
+            mock::record(mock::STDOUT, 1, &[b'.'; 5]),
+            mock::end_request(1, 0, 0),
+        ],
+        Some(10),
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, ClientError::ResponseTooLarge { limit: 10 }));
+
+    let err = execute_mock(
+        vec![mock::record(mock::STDOUT, 1, &[b'.'; 60000])],
+        Some(1024),
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, ClientError::ResponseTooLarge { limit: 1024 }));
+}
+
+#[tokio::test]
+async fn max_stderr_bytes() {
+    let records = || {
+        vec![
+            mock::record(mock::STDOUT, 1, &[b'.'; 20]),
+            mock::record(mock::STDERR, 1, &[b'!'; 6]),
+            mock::record(mock::STDERR, 1, &[b'!'; 5]),
+            mock::end_request(1, 0, 0),
+        ]
+    };
+    let output = execute_configured(records(), |builder| builder.max_stderr_bytes(Some(11)))
+        .await
+        .unwrap();
+    assert_eq!(output.stderr.unwrap().len(), 11);
+
+    let err = execute_configured(records(), |builder| builder.max_stderr_bytes(Some(10)))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::ResponseTooLarge { limit: 10 }));
+}
+
+/// Executes `request` against a server answering `records`.
+async fn execute_request(
+    records: Vec<Vec<u8>>, request: Request<'static, io::Empty>,
+) -> ClientResult<Response> {
+    let (client_side, mut server_side) = io::duplex(1 << 20);
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        for record in records {
+            server_side.write_all(&record).await.unwrap();
+        }
+        server_side
+    });
+
+    let output = ClientBuilder::new(client_side)
+        .max_stderr_bytes(Some(4))
+        .build()
+        .execute_once(request)
+        .await;
+    server.await.unwrap();
+    output
+}
+
+#[tokio::test]
+async fn stderr_handler() {
+    let records = vec![
+        mock::record(mock::STDERR, 1, b"first"),
+        mock::record(mock::STDOUT, 1, b"out"),
+        mock::record(mock::STDERR, 1, b"second"),
+        mock::record(mock::STDERR, 1, b""),
+        mock::end_request(1, 0, 0),
+    ];
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let request = Request::new(Params::default(), io::empty())
+        .stderr_handler(move |stderr| sender.send(stderr).unwrap());
+    assert!(request.has_stderr_handler());
+    let output = execute_request(records, request).await.unwrap();
+
+    assert_eq!(output.stdout.as_deref(), Some(&b"out"[..]));
+    assert_eq!(output.stderr, None);
+    let stderr = receiver.iter().collect::<Vec<_>>();
+    assert_eq!(stderr, [Bytes::from("first"), Bytes::from("second")]);
+}
+
+#[tokio::test]
+async fn request_output_limits() {
+    let records = || {
+        vec![
+            mock::record(mock::STDOUT, 1, b"12345"),
+            mock::record(mock::STDERR, 1, b"oops"),
+            mock::record(mock::STDOUT, 1, b"67890"),
+            mock::end_request(1, 0, 0),
+        ]
+    };
+    let request = || Request::new(Params::default(), io::empty());
+
+    let output = execute_request(records(), request().with_max_stdout_bytes(10))
+        .await
+        .unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"1234567890"[..]));
+
+    let err = execute_request(records(), request().with_max_stdout_bytes(9))
+        .await
+        .unwrap_err();
+    assert_eq!(err.partial_stdout().unwrap(), &b"12345"[..]);
+    let ClientError::OutputTooLarge {
+        request_type,
+        limit,
+        stderr,
+        ..
+    } = err
+    else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!(request_type, RequestType::Stdout);
+    assert_eq!(limit, 9);
+    assert_eq!(stderr.as_deref(), Some(&b"oops"[..]));
+
+    let request = request().with_max_stderr_bytes(3).stderr_handler(|_| {});
+    assert_eq!(request.max_stderr_bytes(), Some(3));
+    let err = execute_request(records(), request).await.unwrap_err();
+    let ClientError::OutputTooLarge {
+        request_type,
+        stdout,
+        stderr: None,
+        ..
+    } = err
+    else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!(request_type, RequestType::Stderr);
+    assert_eq!(stdout.as_deref(), Some(&b"12345"[..]));
+}
+
+#[tokio::test]
+async fn execute_into_writer() {
+    let (client_side, mut server_side) = io::duplex(1 << 20);
+    let server = tokio::spawn(async move {
+        for id in 1..=3 {
+            mock::read_request(&mut server_side).await;
+            let records = [
+                mock::record(mock::STDOUT, id, b"hello "),
+                mock::record(mock::STDERR, id, b"warn"),
+                mock::record(mock::STDOUT, id, b"world"),
+                mock::end_request(id, 7, 0),
+            ];
+            for record in records {
+                server_side.write_all(&record).await.unwrap();
+            }
+        }
+        server_side
+    });
+
+    let mut client = ClientBuilder::new(client_side).keep_alive().build();
+    let mut sink = Vec::new();
+    let summary = client
+        .execute_into(Request::new(Params::default(), io::empty()), &mut sink)
+        .await
+        .unwrap();
+    assert_eq!(sink, b"hello world");
+    assert_eq!(summary.request_id, 1);
+    assert_eq!(summary.app_status, 7);
+    assert_eq!(summary.stdout_len, 11);
+    assert_eq!(summary.stderr.as_deref(), Some(&b"warn"[..]));
+
+    let output = client
+        .execute(Request::new(Params::default(), io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"hello world"[..]));
+
+    // A sink failing leaves the rest of the response on the connection.
+    let (mut closed, _) = io::duplex(1);
+    let err = client
+        .execute_into(Request::new(Params::default(), io::empty()), &mut closed)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::Io(_)));
+    assert!(client.is_broken());
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn max_records() {
+    let records = || {
+        let mut records = vec![mock::record(mock::STDOUT, 1, b"."); 3];
+        records.push(mock::end_request(1, 0, 0));
+        records
+    };
+    let output = execute_configured(records(), |builder| builder.max_records(Some(4)))
+        .await
+        .unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"..."[..]));
+
+    let err = execute_configured(records(), |builder| builder.max_records(Some(3)))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::TooManyRecords { limit: 3 }));
+}
+
+#[tokio::test]
+async fn stderr_in_end_request_error() {
+    let err = execute_mock(
+        vec![
+            mock::record(mock::STDERR, 1, b"PHP Fatal error: out of workers"),
+            mock::end_request(1, 1, 2),
+        ],
+        None,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(err.to_string().contains("PHP Fatal error: out of workers"));
+    match err {
+        ClientError::EndRequestOverloaded {
+            app_status,
+            stdout,
+            stderr,
+        } => {
+            assert_eq!(app_status, 1);
+            assert_eq!(stdout, None);
+            assert_eq!(
+                stderr.as_deref(),
+                Some(&b"PHP Fatal error: out of workers"[..])
+            );
+        }
+        err => panic!("unexpected error: {err}"),
+    }
+}
+
+#[tokio::test]
+async fn stdout_in_end_request_error() {
+    let err = execute_mock(
+        vec![
+            mock::record(mock::STDOUT, 1, b"<html>"),
+            mock::end_request(1, 1, 2),
+        ],
+        None,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(err, ClientError::EndRequestOverloaded { .. }));
+    assert_eq!(err.partial_stdout().unwrap(), &b"<html>"[..]);
+}
+
+#[tokio::test]
+async fn stdout_reader() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        let id = mock::read_request(&mut server_side).await[0].request_id;
+        for record in [
+            mock::record(mock::STDOUT, id, b"Content-type: text/plain\r\n"),
+            mock::record(mock::STDERR, id, b"notice"),
+            mock::record(mock::STDOUT, id, b"\r\nhello "),
+            mock::record(mock::STDOUT, id, b"world"),
+            mock::end_request(id, 0, 0),
+        ] {
+            server_side.write_all(&record).await.unwrap();
+        }
+        server_side
+    });
+
+    let mut reader = Client::new(client_side)
+        .execute_once_stream(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap()
+        .into_reader();
+
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "Content-type: text/plain\r\n");
+
+    let mut body = Vec::new();
+    io::copy(&mut reader, &mut body).await.unwrap();
+    assert_eq!(body, b"\r\nhello world");
+    assert_eq!(reader.stderr(), b"notice");
+    drop(server.await.unwrap());
+}
+
+#[tokio::test]
+async fn stdout_reader_error() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        let id = mock::read_request(&mut server_side).await[0].request_id;
+        server_side
+            .write_all(&mock::end_request(id, 0, 2))
+            .await
+            .unwrap();
+        server_side
+    });
+
+    let mut reader = Client::new(client_side)
+        .execute_once_stream(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap()
+        .into_reader();
+
+    let err = io::copy(&mut reader, &mut io::sink()).await.unwrap_err();
+    assert!(matches!(
+        err.into_inner().unwrap().downcast_ref::<ClientError>(),
+        Some(ClientError::EndRequestOverloaded { .. })
+    ));
+    drop(server.await.unwrap());
+}
+
+#[tokio::test]
+async fn stderr_on_success() {
+    let records = vec![
+        mock::record(mock::STDOUT, 1, b"hello "),
+        mock::record(mock::STDERR, 1, b"warning one\n"),
+        mock::record(mock::STDOUT, 1, b"world"),
+        mock::record(mock::STDERR, 1, b"warning two\n"),
+        mock::end_request(1, 0, 0),
+    ];
+    let output = execute_mock(records, None).await.unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"hello world"[..]));
+    assert_eq!(
+        output.stderr.as_deref(),
+        Some(&b"warning one\nwarning two\n"[..])
+    );
+
+    let records = vec![
+        mock::record(mock::STDOUT, 1, b"hello"),
+        mock::end_request(1, 0, 0),
+    ];
+    let output = execute_mock(records, None).await.unwrap();
+    assert_eq!(output.stderr, None);
+}
+
+/// Stdout and stderr records interleaved, each stream closed by an empty
+/// record.
+fn interleaved_records() -> Vec<Vec<u8>> {
+    vec![
+        mock::record(mock::STDOUT, 1, b"Content-type: text/plain\r\n\r\n"),
+        mock::record(mock::STDERR, 1, b"first warning\n"),
+        mock::record(mock::STDOUT, 1, b"body"),
+        mock::record(mock::STDERR, 1, b""),
+        mock::record(mock::STDOUT, 1, b""),
+        mock::end_request(1, 0, 0),
+    ]
+}
+
+#[tokio::test]
+async fn interleaved_stdout_and_stderr() {
+    let output = execute_mock(interleaved_records(), None).await.unwrap();
+    assert_eq!(
+        output.stdout.as_deref(),
+        Some(&b"Content-type: text/plain\r\n\r\nbody"[..])
+    );
+    assert_eq!(output.stderr.as_deref(), Some(&b"first warning\n"[..]));
+}
+
+#[tokio::test]
+async fn interleaved_stdout_and_stderr_stream() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        for record in interleaved_records() {
+            server_side.write_all(&record).await.unwrap();
+        }
+        server_side
+    });
+
+    let contents = Client::new(client_side)
+        .execute_once_stream(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap()
+        .map(|content| match content.unwrap() {
+            Content::Stdout(stdout) => ("stdout", stdout),
+            Content::Stderr(stderr) => ("stderr", stderr),
+        })
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(
+        contents,
+        [
+            (
+                "stdout",
+                Bytes::from_static(b"Content-type: text/plain\r\n\r\n")
+            ),
+            ("stderr", Bytes::from_static(b"first warning\n")),
+            ("stdout", Bytes::from_static(b"body")),
+        ]
+    );
+    drop(server.await.unwrap());
+}
+
+#[tokio::test]
+async fn ordered_events() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        mock::read_request(&mut server_side).await;
+        let mut records = interleaved_records();
+        *records.last_mut().unwrap() = mock::end_request(1, 3, 0);
+        for record in records {
+            server_side.write_all(&record).await.unwrap();
+        }
+        server_side
+    });
+
+    let events = Client::new(client_side)
+        .execute_once_stream(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap()
+        .into_events()
+        .map(Result::unwrap)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(
+        events,
+        [
+            Event::Stdout(Bytes::from_static(b"Content-type: text/plain\r\n\r\n")),
+            Event::Stderr(Bytes::from_static(b"first warning\n")),
+            Event::Stdout(Bytes::from_static(b"body")),
+            Event::End { app_status: 3 },
+        ]
+    );
+    drop(server.await.unwrap());
+}
+
+#[tokio::test]
+async fn expected_response_size_is_a_hint() {
+    for (expected, limit) in [(4, None), (1 << 20, None), (1 << 20, Some(10))] {
+        let (client_side, mut server_side) = io::duplex(1 << 16);
+
+        let server = tokio::spawn(async move {
+            mock::read_request(&mut server_side).await;
+            server_side
+                .write_all(&mock::record(mock::STDOUT, 1, b"0123456789"))
+                .await
+                .unwrap();
+            server_side
+                .write_all(&mock::end_request(1, 0, 0))
+                .await
+                .unwrap();
+        });
+
+        let request =
+            Request::new(Params::default(), io::empty()).with_expected_response_size(expected);
+        assert_eq!(request.expected_response_size(), Some(expected));
+        let output = Client::new(client_side)
+            .max_response_bytes(limit)
+            .execute_once(request)
+            .await
+            .unwrap();
+        assert_eq!(output.stdout.as_deref(), Some(&b"0123456789"[..]));
+
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use fcgi_client::{Client, ClientError, Params, Pool, Request, RetryPolicy};
+use tokio::io::{self, AsyncWriteExt, DuplexStream};
+
+mod mock;
+
+/// Protocol status `Overloaded`.
+const OVERLOADED: u8 = 2;
+
+fn request() -> Request<'static, io::Empty> {
+    Request::new(Params::default().request_method("GET"), io::empty())
+}
+
+#[tokio::test(start_paused = true)]
+async fn retry_overloaded() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        let mut ids = Vec::new();
+        for protocol_status in [OVERLOADED, 0] {
+            let id = mock::read_request(&mut server_side).await[0].request_id;
+            server_side
+                .write_all(&mock::end_request(id, 0, protocol_status))
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+        ids
+    });
+
+    let mut client = Client::new_keep_alive(client_side);
+    let output = client
+        .execute_with_retry(RetryPolicy::new(3), request)
+        .await
+        .unwrap();
+    assert_eq!(output.request_id, 2);
+    assert_eq!(server.await.unwrap(), [1, 2]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn give_up_after_max_attempts() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        for _ in 0..2 {
+            let id = mock::read_request(&mut server_side).await[0].request_id;
+            server_side
+                .write_all(&mock::end_request(id, 0, OVERLOADED))
+                .await
+                .unwrap();
+        }
+        server_side
+    });
+
+    let mut client = Client::new_keep_alive(client_side);
+    let output = client
+        .execute_with_retry(RetryPolicy::new(2), request)
+        .await;
+    assert!(matches!(
+        output,
+        Err(ClientError::EndRequestOverloaded { .. })
+    ));
+    drop(server.await.unwrap());
+}
+
+/// Creates a pool whose first connection replies `first` to the request and
+/// closes, and whose other connections reply successfully.
+fn pool(first: Vec<u8>) -> (Pool<DuplexStream>, mock::Server) {
+    let server = mock::Server::new()
+        .requests(1)
+        .reply(move |connection, id| match connection {
+            0 => first.clone(),
+            _ => mock::end_request(id, 0, 0),
+        });
+    (Pool::new(server.connector()), server)
+}
+
+#[tokio::test(start_paused = true)]
+async fn retry_connection_closed() {
+    let (pool, server) = pool(Vec::new());
+    pool.execute_with_retry(RetryPolicy::new(2), request)
+        .await
+        .unwrap();
+    assert_eq!(server.connections(), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn no_retry_after_response_started() {
+    let (pool, server) = pool(mock::record(mock::STDOUT, 1, b"partial"));
+    let output = pool.execute_with_retry(RetryPolicy::new(2), request).await;
+    assert!(matches!(output, Err(ClientError::ConnectionClosed { .. })));
+    assert_eq!(server.connections(), 1);
+}
@@ -0,0 +1,165 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use fcgi_client::{
+    meta::Role, response::AuthResponse, Client, ClientError, ClientResult, Params, Request,
+};
+use tokio::io::{self, AsyncWriteExt};
+
+mod mock;
+
+#[tokio::test]
+async fn filter_sends_data_stream() {
+    let (client_side, mut server_side) = io::duplex(1 << 20);
+    let file = b"<html>legacy</html>";
+
+    let server = tokio::spawn(async move {
+        let records = mock::read_request(&mut server_side).await;
+        let mut data = Vec::new();
+        loop {
+            let record = mock::read_record(&mut server_side).await;
+            assert_eq!(record.r#type, mock::DATA);
+            if record.content.is_empty() {
+                break;
+            }
+            data.extend_from_slice(&record.content);
+        }
+        server_side
+            .write_all(&mock::record(mock::STDOUT, 1, b"filtered"))
+            .await
+            .unwrap();
+        server_side
+            .write_all(&mock::end_request(1, 0, 0))
+            .await
+            .unwrap();
+        (records, data)
+    });
+
+    let params = Params::default()
+        .data_last_mod(1_700_000_000)
+        .data_length(file.len());
+    let output = Client::new(client_side)
+        .execute_once(Request::new_filter(params, &mut io::empty(), &file[..]))
+        .await
+        .unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"filtered"[..]));
+
+    let (records, data) = server.await.unwrap();
+    assert_eq!(records[0].r#type, mock::BEGIN_REQUEST);
+    assert_eq!(&records[0].content[..2], &3u16.to_be_bytes());
+    assert_eq!(data, file);
+
+    let params = mock::request_params(&records);
+    assert!(params.contains(&(b"FCGI_DATA_LAST_MOD".to_vec(), b"1700000000".to_vec())));
+    assert!(params.contains(&(b"FCGI_DATA_LENGTH".to_vec(), b"19".to_vec())));
+}
+
+#[test]
+fn role_from_u16() {
+    assert_eq!(Role::try_from(1), Ok(Role::Responder));
+    assert_eq!(Role::try_from(2), Ok(Role::Authorizer));
+    assert_eq!(Role::try_from(3), Ok(Role::Filter));
+    assert_eq!(Role::try_from(0), Err(0));
+    assert_eq!(Role::try_from(4), Err(4));
+}
+
+#[tokio::test]
+async fn unknown_role_reports_role() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        let records = mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::end_request(1, 0, 3))
+            .await
+            .unwrap();
+        let role = u16::from_be_bytes([records[0].content[0], records[0].content[1]]);
+        Role::try_from(role)
+    });
+
+    let err = Client::builder(client_side)
+        .default_role(Role::Authorizer)
+        .build()
+        .execute_once(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Role: Authorizer"));
+    assert!(matches!(
+        err,
+        ClientError::EndRequestUnknownRole {
+            role: Role::Authorizer,
+            ..
+        }
+    ));
+    assert_eq!(server.await.unwrap(), Ok(Role::Authorizer));
+}
+
+/// Runs an authorization against a fake authorizer replying `stdout`,
+/// returning the result and the records of the request.
+async fn authorize(stdout: &'static [u8]) -> (ClientResult<AuthResponse>, Vec<mock::Record>) {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        let records = mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::record(mock::STDOUT, 1, stdout))
+            .await
+            .unwrap();
+        server_side
+            .write_all(&mock::end_request(1, 0, 0))
+            .await
+            .unwrap();
+        records
+    });
+
+    let params = Params::default().request_uri("/private");
+    let result = Client::new_keep_alive(client_side).authorize(params).await;
+    (result, server.await.unwrap())
+}
+
+#[tokio::test]
+async fn authorizer_allows() {
+    let (result, records) = authorize(
+        b"Status: 200\r\nVariable-AUTH_USER: alice\r\nvariable-auth_level: 2\r\nX-Other: 1\r\n\r\n",
+    )
+    .await;
+    let auth = result.unwrap();
+    assert!(auth.allowed);
+    assert_eq!(auth.variables.len(), 2);
+    assert_eq!(auth.variables["AUTH_USER"], "alice");
+    assert_eq!(auth.variables["auth_level"], "2");
+
+    assert_eq!(&records[0].content[..2], &2u16.to_be_bytes());
+    let stdin = records
+        .iter()
+        .filter(|record| record.r#type == mock::STDIN)
+        .collect::<Vec<_>>();
+    assert_eq!(stdin.len(), 1);
+    assert!(stdin[0].content.is_empty());
+}
+
+#[tokio::test]
+async fn authorizer_denies() {
+    let (result, _) =
+        authorize(b"Status: 403 Forbidden\r\nWWW-Authenticate: Basic\r\n\r\nno access").await;
+    let auth = result.unwrap();
+    assert!(!auth.allowed);
+    assert_eq!(auth.parts.status, 403);
+    assert_eq!(
+        auth.parts.headers,
+        [("WWW-Authenticate".to_owned(), "Basic".to_owned())]
+    );
+    assert_eq!(&auth.parts.body[..], b"no access");
+    assert!(auth.variables.is_empty());
+}
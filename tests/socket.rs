@@ -0,0 +1,94 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use fcgi_client::{Params, Pool, Request, SocketOptions};
+use socket2::SockRef;
+use std::time::Duration;
+use tokio::{
+    io::{self, AsyncWriteExt},
+    net::TcpListener,
+};
+
+mod mock;
+
+#[tokio::test]
+async fn socket_options_applied() {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let stream = SocketOptions::default()
+        .nodelay(true)
+        .keepalive(Duration::from_secs(60), Duration::from_secs(10))
+        .send_buffer_size(1 << 16)
+        .recv_buffer_size(1 << 16)
+        .tos(0x10)
+        .connect(addr, Duration::from_secs(5))
+        .await
+        .unwrap();
+
+    let socket = SockRef::from(&stream);
+    assert!(socket.tcp_nodelay().unwrap());
+    assert!(socket.keepalive().unwrap());
+    assert!(socket.send_buffer_size().unwrap() >= 1 << 16);
+    assert_eq!(socket.tos_v4().unwrap(), 0x10);
+}
+
+#[tokio::test]
+async fn socket_options_default() {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let stream = SocketOptions::default()
+        .connect(addr, Duration::from_secs(5))
+        .await
+        .unwrap();
+    let socket = SockRef::from(&stream);
+    assert!(!socket.tcp_nodelay().unwrap());
+    assert!(!socket.keepalive().unwrap());
+}
+
+#[tokio::test]
+async fn pool_socket_options() {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let id = mock::read_request(&mut stream).await[0].request_id;
+        stream
+            .write_all(&mock::record(mock::STDOUT, id, b"hello"))
+            .await
+            .unwrap();
+        stream
+            .write_all(&mock::end_request(id, 0, 0))
+            .await
+            .unwrap();
+        stream
+    });
+
+    let pool = Pool::connect_tcp(
+        addr,
+        Duration::from_secs(5),
+        SocketOptions::default().nodelay(true),
+    );
+    let mut client = pool.get().await.unwrap();
+    assert!(client.nodelay().unwrap());
+    let output = client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"hello"[..]));
+
+    drop(server.await.unwrap());
+}
@@ -0,0 +1,119 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "socks5")]
+
+use fcgi_client::{transport::Socks5Proxy, Client, ClientError, Params, Request};
+use tokio::{
+    io::{self, AsyncWriteExt},
+    net::TcpListener,
+    task::JoinHandle,
+};
+
+mod mock;
+
+/// Starts a SOCKS5 proxy answering with `method`, `auth_status` and `reply`,
+/// which then plays the FastCGI server answering `hello` if the tunnel was
+/// opened. Returns its port and what the client asked for.
+async fn proxy(method: u8, auth_status: u8, reply: u8) -> (u16, JoinHandle<mock::Socks5Request>) {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let request = mock::accept_socks5(&mut stream, method, auth_status, reply).await;
+        if request.port != 0 && reply == 0 {
+            let id = mock::read_request(&mut stream).await[0].request_id;
+            stream
+                .write_all(&mock::record(mock::STDOUT, id, b"hello"))
+                .await
+                .unwrap();
+            stream
+                .write_all(&mock::end_request(id, 0, 0))
+                .await
+                .unwrap();
+        }
+        request
+    });
+    (port, server)
+}
+
+#[tokio::test]
+async fn connect_via_socks5() {
+    let (port, server) = proxy(0, 0, 0).await;
+
+    let output = Client::connect_via_socks5(
+        &Socks5Proxy::new("127.0.0.1", port),
+        ("php-fpm.internal", 9000),
+    )
+    .await
+    .unwrap()
+    .execute_once(Request::new(Params::default(), &mut io::empty()))
+    .await
+    .unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"hello"[..]));
+
+    let request = server.await.unwrap();
+    assert_eq!(request.methods, [0]);
+    assert_eq!(request.host, b"php-fpm.internal");
+    assert_eq!(request.port, 9000);
+}
+
+#[tokio::test]
+async fn connect_via_socks5_with_credentials() {
+    let (port, server) = proxy(2, 0, 0).await;
+
+    let proxy = Socks5Proxy::new("127.0.0.1", port).credentials("deploy", "secret");
+    let mut client = Client::connect_via_socks5_keep_alive(&proxy, ("10.0.0.7", 9000))
+        .await
+        .unwrap();
+    let output = client
+        .execute(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"hello"[..]));
+
+    let request = server.await.unwrap();
+    assert_eq!(request.methods, [2]);
+    assert_eq!(
+        request.credentials,
+        Some((b"deploy".to_vec(), b"secret".to_vec()))
+    );
+    assert_eq!(request.host, [10, 0, 0, 7]);
+}
+
+#[tokio::test]
+async fn socks5_refusals() {
+    let cases = [
+        ((0xff, 0, 0), "no acceptable authentication method"),
+        ((2, 1, 0), "authentication failed"),
+        ((0, 0, 5), "connection refused"),
+    ];
+    for ((method, auth_status, reply), reason) in cases {
+        let (port, server) = proxy(method, auth_status, reply).await;
+
+        let mut proxy = Socks5Proxy::new("127.0.0.1", port);
+        if method == 2 {
+            proxy = proxy.credentials("deploy", "wrong");
+        }
+        let err = Client::connect_via_socks5(&proxy, ("php-fpm.internal", 9000))
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            matches!(err, ClientError::Socks5 { reason: actual } if actual == reason),
+            "{err}"
+        );
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,366 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use fcgi_client::{response::Content, Client, ClientBuilder, ClientError, Params, Request};
+use futures_util::StreamExt;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{self, AsyncRead, AsyncReadExt, AsyncWriteExt, DuplexStream},
+    time,
+};
+
+mod mock;
+
+/// Largest content length of a single record.
+const MAX_LENGTH: usize = 0xffff;
+
+/// Sends `body` as stdin and returns the content lengths of the stdin records.
+async fn stdin_records<I: AsyncRead + Unpin>(body: I) -> Vec<usize> {
+    let (client_side, mut server_side) = io::duplex(1 << 20);
+
+    let server = tokio::spawn(async move {
+        let records = mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::end_request(records[0].request_id, 0, 0))
+            .await
+            .unwrap();
+        records
+    });
+
+    Client::new(client_side)
+        .execute_once(Request::new(Params::default(), body))
+        .await
+        .unwrap();
+
+    server
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|record| record.r#type == mock::STDIN)
+        .map(|record| record.content.len())
+        .collect()
+}
+
+#[tokio::test]
+async fn stdin_record_boundaries() {
+    let cases: [(usize, &[usize]); 5] = [
+        (0, &[0]),
+        (32768, &[32768, 0]),
+        (MAX_LENGTH - 1, &[MAX_LENGTH - 1, 0]),
+        (MAX_LENGTH, &[MAX_LENGTH, 0]),
+        (MAX_LENGTH + 1, &[MAX_LENGTH, 1, 0]),
+    ];
+    for (len, expected) in cases {
+        let body = vec![b'x'; len];
+        assert_eq!(
+            stdin_records(&body[..]).await,
+            expected,
+            "body of {len} bytes"
+        );
+    }
+}
+
+#[tokio::test]
+async fn stdin_records_ignore_read_chunking() {
+    let body = vec![b'x'; MAX_LENGTH + 1];
+    let (head, tail) = body.split_at(100);
+    assert_eq!(stdin_records(head.chain(tail)).await, [MAX_LENGTH, 1, 0]);
+}
+
+/// Sends `request` and returns the records of the request.
+async fn sent_records<I: AsyncRead + Unpin>(request: Request<'_, I>) -> Vec<mock::Record> {
+    let (client_side, mut server_side) = io::duplex(1 << 20);
+
+    let server = tokio::spawn(async move {
+        let records = mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::end_request(records[0].request_id, 0, 0))
+            .await
+            .unwrap();
+        records
+    });
+
+    Client::new(client_side)
+        .execute_once(request)
+        .await
+        .unwrap();
+    server.await.unwrap()
+}
+
+#[tokio::test]
+async fn body_bytes() {
+    let body = br#"{"name":"fcgi"}"#;
+    let records = sent_records(Request::with_body_bytes(Params::default(), &body[..])).await;
+
+    let stdin = records
+        .iter()
+        .filter(|record| record.r#type == mock::STDIN)
+        .flat_map(|record| record.content.iter().copied())
+        .collect::<Vec<_>>();
+    assert_eq!(stdin, body);
+    assert!(mock::request_params(&records).contains(&(
+        b"CONTENT_LENGTH".to_vec(),
+        body.len().to_string().into_bytes()
+    )));
+
+    let params = Params::default().content_length(3);
+    let records = sent_records(Request::with_body_bytes(params, vec![b'x'; 10])).await;
+    assert!(mock::request_params(&records).contains(&(b"CONTENT_LENGTH".to_vec(), b"3".to_vec())));
+}
+
+#[tokio::test]
+async fn configured_record_size() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+
+    let server = tokio::spawn(async move {
+        let records = mock::read_request(&mut server_side).await;
+        server_side
+            .write_all(&mock::end_request(records[0].request_id, 0, 0))
+            .await
+            .unwrap();
+        records
+    });
+
+    Client::builder(client_side)
+        .record_size(8)
+        .build()
+        .execute_once(Request::new(Params::default(), &mut &[b'x'; 20][..]))
+        .await
+        .unwrap();
+
+    let records = server.await.unwrap();
+    let stdin = records
+        .iter()
+        .filter(|record| record.r#type == mock::STDIN)
+        .map(|record| record.content.len())
+        .collect::<Vec<_>>();
+    assert_eq!(stdin, [8, 8, 4, 0]);
+    assert!(records
+        .iter()
+        .filter(|record| record.r#type == mock::PARAMS)
+        .all(|record| record.content.len() <= 8));
+}
+
+/// Answers a request right after its params without reading its stdin, like
+/// PHP rejecting an oversized upload, then closes the connection if `close`
+/// is set.
+async fn answer_early(mut server_side: DuplexStream, close: bool) -> Option<DuplexStream> {
+    loop {
+        let record = mock::read_record(&mut server_side).await;
+        if record.r#type == mock::PARAMS && record.content.is_empty() {
+            break;
+        }
+    }
+    let mut bytes = mock::record(mock::STDOUT, 1, b"too large");
+    bytes.extend_from_slice(&mock::end_request(1, 0, 0));
+    server_side.write_all(&bytes).await.unwrap();
+    (!close).then_some(server_side)
+}
+
+#[tokio::test]
+async fn stop_upload_when_request_ends_early() {
+    let body = vec![b'x'; 1 << 22];
+
+    let (client_side, server_side) = io::duplex(1 << 16);
+    let server = tokio::spawn(answer_early(server_side, false));
+    let mut client = Client::new_keep_alive(client_side);
+    let output = time::timeout(
+        Duration::from_secs(10),
+        client.execute(Request::new(Params::default(), &mut &body[..])),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"too large"[..]));
+    // The server may still expect the rest of the body.
+    assert!(client.is_broken());
+    drop(server.await.unwrap());
+
+    let (client_side, server_side) = io::duplex(1 << 16);
+    let server = tokio::spawn(answer_early(server_side, true));
+    let output = time::timeout(
+        Duration::from_secs(10),
+        Client::new(client_side).execute_once(Request::new(Params::default(), &mut &body[..])),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(output.stdout.as_deref(), Some(&b"too large"[..]));
+    server.await.unwrap();
+}
+
+/// Echoes each stdin record back as stdout right away, twice, so the output
+/// outgrows the connection buffers long before the body is read.
+async fn echo(mut server_side: DuplexStream) -> DuplexStream {
+    let id = mock::read_record(&mut server_side).await.request_id;
+    loop {
+        let record = mock::read_record(&mut server_side).await;
+        if record.r#type != mock::STDIN {
+            continue;
+        }
+        if record.content.is_empty() {
+            break;
+        }
+        for _ in 0..2 {
+            server_side
+                .write_all(&mock::record(mock::STDOUT, id, &record.content))
+                .await
+                .unwrap();
+        }
+    }
+    server_side
+        .write_all(&mock::end_request(id, 0, 0))
+        .await
+        .unwrap();
+    server_side
+}
+
+#[tokio::test]
+async fn read_response_while_sending_body() {
+    let body = vec![b'x'; 1 << 20];
+
+    let (client_side, server_side) = io::duplex(1 << 16);
+    let server = tokio::spawn(echo(server_side));
+    let mut client = Client::new_keep_alive(client_side);
+    let output = time::timeout(
+        Duration::from_secs(10),
+        client.execute(Request::new(Params::default(), &mut &body[..])),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(output.stdout.unwrap().len(), 2 << 20);
+    assert!(!client.is_broken());
+    drop(server.await.unwrap());
+
+    let (client_side, server_side) = io::duplex(1 << 16);
+    let server = tokio::spawn(echo(server_side));
+    let mut stream = time::timeout(
+        Duration::from_secs(10),
+        Client::new(client_side)
+            .execute_once_stream(Request::new(Params::default(), &mut &body[..])),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    let mut stdout = 0;
+    while let Some(content) = stream.next().await {
+        if let Content::Stdout(content) = content.unwrap() {
+            stdout += content.len();
+        }
+    }
+    assert_eq!(stdout, 2 << 20);
+    drop(server.await.unwrap());
+}
+
+#[tokio::test]
+async fn stop_upload_when_response_outgrows_limits() {
+    let body = vec![b'x'; 1 << 22];
+    let received = Arc::new(AtomicUsize::new(0));
+
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+    let server = tokio::spawn({
+        let received = received.clone();
+        async move {
+            loop {
+                let record = mock::read_record(&mut server_side).await;
+                if record.r#type != mock::STDIN {
+                    continue;
+                }
+                received.fetch_add(record.content.len(), Ordering::SeqCst);
+                for _ in 0..2 {
+                    server_side
+                        .write_all(&mock::record(mock::STDOUT, 1, &record.content))
+                        .await
+                        .unwrap();
+                }
+            }
+        }
+    });
+    let mut client = ClientBuilder::new(client_side)
+        .keep_alive()
+        .max_response_bytes(Some(1 << 18))
+        .build();
+    let result = time::timeout(
+        Duration::from_secs(10),
+        client.execute(Request::new(Params::default(), &mut &body[..])),
+    )
+    .await
+    .unwrap();
+    assert!(matches!(result, Err(ClientError::ResponseTooLarge { limit }) if limit == 1 << 18));
+    assert!(client.is_broken());
+    assert!(received.load(Ordering::SeqCst) < 1 << 20);
+    server.abort();
+
+    let (client_side, server_side) = io::duplex(1 << 16);
+    let server = tokio::spawn(echo(server_side));
+    let mut client = Client::new_keep_alive(client_side);
+    let result = time::timeout(
+        Duration::from_secs(10),
+        client.execute(
+            Request::new(Params::default(), &mut &body[..]).with_max_stdout_bytes(1 << 18),
+        ),
+    )
+    .await
+    .unwrap();
+    assert!(matches!(result, Err(ClientError::OutputTooLarge { limit, .. }) if limit == 1 << 18));
+    server.abort();
+}
+
+#[tokio::test]
+async fn send_slow_body_as_it_arrives() {
+    let (client_side, mut server_side) = io::duplex(1 << 16);
+    let (mut body_writer, mut body) = io::duplex(1 << 16);
+    body_writer.write_all(b"hello").await.unwrap();
+
+    let server = tokio::spawn(async move {
+        let mut stdin = Vec::new();
+        loop {
+            let record = mock::read_record(&mut server_side).await;
+            if record.r#type != mock::STDIN {
+                continue;
+            }
+            if record.content.is_empty() {
+                break;
+            }
+            stdin.push(record.content.clone());
+            if record.content == b"hello" {
+                // The rest of the body only follows the first record.
+                body_writer.write_all(b" world").await.unwrap();
+                body_writer.shutdown().await.unwrap();
+            }
+        }
+        server_side
+            .write_all(&mock::end_request(1, 0, 0))
+            .await
+            .unwrap();
+        stdin
+    });
+
+    let mut client = Client::new_keep_alive(client_side);
+    time::timeout(
+        Duration::from_secs(10),
+        client.execute(Request::new(Params::default(), &mut body)),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(server.await.unwrap(), [&b"hello"[..], &b" world"[..]]);
+}
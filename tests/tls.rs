@@ -0,0 +1,237 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(any(feature = "rustls", feature = "native-tls"))]
+
+use fcgi_client::{Client, ClientError, Params, Request};
+use rcgen::{CertifiedKey, KeyPair};
+use std::future::Future;
+use tokio::{
+    io::{self, AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+mod mock;
+
+/// Generates a self-signed certificate for `localhost`.
+fn certificate() -> CertifiedKey<KeyPair> {
+    rcgen::generate_simple_self_signed(["localhost".to_owned()]).unwrap()
+}
+
+/// Starts a TLS server answering one request with `hello`, returning its
+/// port and its task. Failed handshakes end the task.
+async fn tls_server<S, F>(
+    accept: impl FnOnce(TcpStream) -> F + Send + 'static,
+) -> (u16, JoinHandle<()>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+    F: Future<Output = Option<S>> + Send,
+{
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let Some(mut stream) = accept(stream).await else {
+            return;
+        };
+        let id = mock::read_request(&mut stream).await[0].request_id;
+        stream
+            .write_all(&mock::record(mock::STDOUT, id, b"hello"))
+            .await
+            .unwrap();
+        stream
+            .write_all(&mock::end_request(id, 0, 0))
+            .await
+            .unwrap();
+        stream.shutdown().await.unwrap();
+    });
+    (port, server)
+}
+
+#[cfg(feature = "rustls")]
+mod rustls {
+    use super::*;
+    use fcgi_client::transport::{
+        rustls::{
+            pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+            RootCertStore, ServerConfig,
+        },
+        TlsConfig,
+    };
+    use std::sync::Arc;
+    use tokio_rustls::TlsAcceptor;
+
+    /// Builds the acceptor of a rustls server and the certificate
+    /// authority trusting it.
+    fn rustls_acceptor() -> (TlsAcceptor, RootCertStore) {
+        let certified = certificate();
+        let mut roots = RootCertStore::empty();
+        roots.add(certified.cert.der().clone()).unwrap();
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+            certified.signing_key.serialize_der(),
+        ));
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![certified.cert.der().clone()], key)
+            .unwrap();
+        (TlsAcceptor::from(Arc::new(config)), roots)
+    }
+
+    /// Starts a rustls server, returning its port, its certificate authority
+    /// and its task.
+    async fn rustls_server() -> (u16, RootCertStore, JoinHandle<()>) {
+        let (acceptor, roots) = rustls_acceptor();
+        let (port, server) =
+            tls_server(move |stream| async move { acceptor.accept(stream).await.ok() }).await;
+        (port, roots, server)
+    }
+
+    #[tokio::test]
+    async fn connect_tls() {
+        let (port, roots, server) = rustls_server().await;
+
+        let output = Client::connect_tls("localhost", port, TlsConfig::new(roots))
+            .await
+            .unwrap()
+            .execute_once(Request::new(Params::default(), &mut io::empty()))
+            .await
+            .unwrap();
+        assert_eq!(output.stdout.as_deref(), Some(&b"hello"[..]));
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn untrusted_certificate() {
+        let (port, _, server) = rustls_server().await;
+
+        let err = Client::connect_tls_keep_alive(
+            "localhost",
+            port,
+            TlsConfig::new(RootCertStore::empty()),
+        )
+        .await
+        .err()
+        .unwrap();
+        assert!(matches!(err, ClientError::TlsHandshake { .. }), "{err}");
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn invalid_server_name() {
+        let err = Client::connect_tls("not a host", 9443, TlsConfig::new(RootCertStore::empty()))
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err,
+            ClientError::InvalidServerName { name } if name == "not a host"
+        ));
+    }
+
+    #[cfg(feature = "socks5")]
+    #[tokio::test]
+    async fn tls_via_socks5() {
+        use fcgi_client::transport::{Socks5Proxy, TlsConnector};
+
+        let (acceptor, roots) = rustls_acceptor();
+        let (port, server) = tls_server(move |mut stream| async move {
+            let request = mock::accept_socks5(&mut stream, 0, 0, 0).await;
+            assert_eq!(request.host, b"localhost");
+            acceptor.accept(stream).await.ok()
+        })
+        .await;
+
+        let stream = Socks5Proxy::new("127.0.0.1", port)
+            .connect("localhost", 9443)
+            .await
+            .unwrap();
+        let stream = TlsConfig::new(roots)
+            .handshake("localhost", stream)
+            .await
+            .unwrap();
+        let output = Client::new(stream)
+            .execute_once(Request::new(Params::default(), &mut io::empty()))
+            .await
+            .unwrap();
+        assert_eq!(output.stdout.as_deref(), Some(&b"hello"[..]));
+
+        server.await.unwrap();
+    }
+}
+
+#[cfg(feature = "native-tls")]
+mod native_tls {
+    use super::*;
+    use fcgi_client::transport::{
+        native_tls::{Certificate, Identity, TlsAcceptor, TlsConnector},
+        NativeTlsConfig,
+    };
+
+    /// Starts a native-tls server, returning its port, its certificate and
+    /// its task.
+    async fn native_tls_server() -> (u16, Certificate, JoinHandle<()>) {
+        let certified = certificate();
+        let identity = Identity::from_pkcs8(
+            certified.cert.pem().as_bytes(),
+            certified.signing_key.serialize_pem().as_bytes(),
+        )
+        .unwrap();
+        let acceptor = tokio_native_tls::TlsAcceptor::from(TlsAcceptor::new(identity).unwrap());
+
+        let (port, server) =
+            tls_server(move |stream| async move { acceptor.accept(stream).await.ok() }).await;
+        let certificate = Certificate::from_der(certified.cert.der()).unwrap();
+        (port, certificate, server)
+    }
+
+    #[tokio::test]
+    async fn connect_native_tls() {
+        let (port, certificate, server) = native_tls_server().await;
+
+        let connector = TlsConnector::builder()
+            .add_root_certificate(certificate)
+            .build()
+            .unwrap();
+        let output = Client::connect_tls("localhost", port, NativeTlsConfig::new(connector))
+            .await
+            .unwrap()
+            .execute_once(Request::new(Params::default(), &mut io::empty()))
+            .await
+            .unwrap();
+        assert_eq!(output.stdout.as_deref(), Some(&b"hello"[..]));
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn native_tls_wrong_domain() {
+        let (port, certificate, server) = native_tls_server().await;
+
+        let connector = TlsConnector::builder()
+            .add_root_certificate(certificate)
+            .build()
+            .unwrap();
+        let config = NativeTlsConfig::new(connector).domain("php.internal");
+        let err = Client::connect_tls_keep_alive("localhost", port, config)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, ClientError::TlsHandshake { .. }), "{err}");
+
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "tracing")]
+
+use fcgi_client::{Client, Params, Request};
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+use tokio::io::AsyncWriteExt;
+use tracing::Level;
+
+mod mock;
+
+/// Log output shared with the subscriber.
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Write::write(&mut *self.0.lock().unwrap(), buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn record_events_in_request_span() {
+    let output = Output::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(Level::DEBUG)
+        .with_ansi(false)
+        .with_writer({
+            let output = output.clone();
+            move || output.clone()
+        })
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let (client_side, mut server_side) = tokio::io::duplex(1 << 16);
+    let server = tokio::spawn(async move {
+        let id = mock::read_request(&mut server_side).await[0].request_id;
+        server_side
+            .write_all(&mock::record(mock::STDOUT, id, b"hello"))
+            .await
+            .unwrap();
+        server_side
+            .write_all(&mock::end_request(id, 0, 0))
+            .await
+            .unwrap();
+        server_side
+    });
+
+    Client::new(client_side)
+        .execute_once(Request::new(Params::default(), &mut tokio::io::empty()))
+        .await
+        .unwrap();
+    drop(server.await.unwrap());
+
+    let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+    let records = output
+        .lines()
+        .filter(|line| line.contains("execute{request_id=1 role=Responder}"))
+        .filter_map(|line| {
+            let record_type = line.split("record_type=").nth(1)?.split(' ').next()?;
+            let direction = if line.contains("Write record.") {
+                "write"
+            } else {
+                "read"
+            };
+            Some(format!("{direction} {record_type}"))
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        records,
+        [
+            "write BeginRequest",
+            "write Params",
+            "write Params",
+            "write Stdin",
+            "read Stdout",
+            "read EndRequest",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn warn_on_content_length_mismatch() {
+    let output = Output::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(Level::WARN)
+        .with_ansi(false)
+        .with_writer({
+            let output = output.clone();
+            move || output.clone()
+        })
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let (client_side, mut server_side) = tokio::io::duplex(1 << 16);
+    let server = tokio::spawn(async move {
+        for _ in 0..2 {
+            let id = mock::read_request(&mut server_side).await[0].request_id;
+            server_side
+                .write_all(&mock::end_request(id, 0, 0))
+                .await
+                .unwrap();
+        }
+        server_side
+    });
+
+    let mut client = Client::new_keep_alive(client_side);
+    client
+        .execute(Request::new(
+            Params::default().content_length(4),
+            &mut &b"body"[..],
+        ))
+        .await
+        .unwrap();
+    client
+        .execute(Request::new(
+            Params::default().content_length(10),
+            &mut &b"body"[..],
+        ))
+        .await
+        .unwrap();
+    drop(server.await.unwrap());
+
+    let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+    let warnings = output
+        .lines()
+        .filter(|line| line.contains("CONTENT_LENGTH doesn't match the stdin sent."))
+        .collect::<Vec<_>>();
+    assert_eq!(warnings.len(), 1, "{output}");
+    assert!(warnings[0].contains("id=2 content_length=10 sent=4"));
+}
@@ -0,0 +1,65 @@
+// Copyright 2022 jmjoy
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "vsock")]
+
+use fcgi_client::{Client, Params, Request};
+use tokio::io::{self, AsyncWriteExt};
+use tokio_vsock::{VsockAddr, VsockListener, VMADDR_CID_LOCAL};
+
+mod mock;
+
+#[tokio::test]
+async fn connect_vsock() {
+    // Local vsock connections need the vsock_loopback module.
+    let Ok(listener) = VsockListener::bind(VsockAddr::new(VMADDR_CID_LOCAL, 0)) else {
+        eprintln!("vsock loopback unavailable, skipping");
+        return;
+    };
+    let port = listener.local_addr().unwrap().port();
+
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        for _ in 0..2 {
+            let id = mock::read_request(&mut stream).await[0].request_id;
+            stream
+                .write_all(&mock::record(mock::STDOUT, id, b"hello"))
+                .await
+                .unwrap();
+            stream
+                .write_all(&mock::end_request(id, 0, 0))
+                .await
+                .unwrap();
+        }
+    });
+
+    let mut client = Client::connect_vsock_keep_alive(VMADDR_CID_LOCAL, port)
+        .await
+        .unwrap();
+    for _ in 0..2 {
+        let output = client
+            .execute(Request::new(Params::default(), &mut io::empty()))
+            .await
+            .unwrap();
+        assert_eq!(output.stdout.as_deref(), Some(&b"hello"[..]));
+    }
+
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn connect_vsock_unreachable() {
+    // No VM is given a context ID this high.
+    assert!(Client::connect_vsock(0x7fff_fff0, 9000).await.is_err());
+}